@@ -0,0 +1,140 @@
+use std::env::var;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+
+//shared with the kernel, so the generated files can't drift from what it implements
+#[path = "src/abi_defs.rs"]
+mod abi_defs;
+
+use abi_defs::*;
+
+//C programs link against the runtime with something like
+//`clang --target=x86_64-unknown-none -ffreestanding -nostdlib -fPIE -static-pie -I$C_SDK main.c $C_SDK/libkernel_rt.a`
+//where C_SDK is the c-sdk directory in this script's OUT_DIR, which dependents get as DEP_KERNEL_RT_C_SDK
+fn main()
+{
+    println!("cargo:rerun-if-changed=src/abi_defs.rs");
+
+    let out_dir = PathBuf::from(var("OUT_DIR").unwrap()).join("c-sdk");
+    fs::create_dir_all(&out_dir).unwrap();
+    fs::write(out_dir.join("kernel.h"), header()).unwrap();
+    fs::write(out_dir.join("crt0.S"), crt0()).unwrap();
+    fs::write(out_dir.join("syscalls.S"), syscalls()).unwrap();
+
+    //only assembled for C programs, nothing in the kernel links against it
+    cc::Build::new()
+        .file(out_dir.join("crt0.S"))
+        .file(out_dir.join("syscalls.S"))
+        .flag("-fPIE")
+        .out_dir(&out_dir)
+        .cargo_metadata(false)
+        .compile("kernel_rt");
+
+    println!("cargo:c_sdk={}", out_dir.display());
+}
+
+fn header() -> String
+{
+    let mut h = String::new();
+    writeln!(h, "/* generated from kernel/src/abi_defs.rs by kernel/build.rs, do not edit */").unwrap();
+    writeln!(h, "#ifndef KERNEL_H\n#define KERNEL_H\n").unwrap();
+    writeln!(h, "#define KERNEL_ABI_VERSION {}\n", ABI_VERSION).unwrap();
+    for (nr, def) in SYSCALLS.iter().enumerate()
+    {
+        writeln!(h, "#define SYS_{} {}", def.name.to_uppercase(), nr).unwrap();
+    }
+    writeln!(h, "#define SYSCALL_COUNT {}\n", SYSCALLS.len()).unwrap();
+    writeln!(h, "/* status returned by syscalls that succeeded, failures return one of the error codes */").unwrap();
+    writeln!(h, "#define KERNEL_OK (-1)\n").unwrap();
+    write_enum(&mut h, "kernel_error_kind", "ERROR_KIND", ERROR_KINDS);
+    write_enum(&mut h, "kernel_process_error", "PROCESS_ERROR", PROCESS_ERRORS);
+    writeln!(h, "/* types of the auxiliary vector entries that follow envp */").unwrap();
+    for (name, value) in AUX_TYPES
+    {
+        writeln!(h, "#define {} {:#x}", name, value).unwrap();
+    }
+    writeln!(h).unwrap();
+    writeln!(h, "/* signal numbers, and the handlers that select the default action or ignore a signal */").unwrap();
+    for (name, value) in SIGNALS
+    {
+        writeln!(h, "#define {} {}", name, value).unwrap();
+    }
+    writeln!(h, "#define SIGNAL_COUNT {}\n#define SIG_DFL {}\n#define SIG_IGN {}\n", SIGNAL_COUNT, SIG_DFL, SIG_IGN).unwrap();
+    writeln!(h, "/* file permissions, read and written by the mode syscalls as PERMS_SIZE bytes and by the perms syscalls as LEGACY_PERMS_SIZE bytes */").unwrap();
+    writeln!(h, "#define ROOT_ID {}\n#define MODE_SETUID 0{:o}\n#define MODE_SETGID 0{:o}\n#define MODE_READ {}\n#define MODE_WRITE {}\n#define MODE_EXEC {}\n#define MODE_MASK 0{:o}\n#define PERMS_SIZE {}\n#define LEGACY_PERMS_SIZE {}\n", ROOT_ID, MODE_SETUID, MODE_SETGID, MODE_READ, MODE_WRITE, MODE_EXEC, MODE_MASK, PERMS_SIZE, LEGACY_PERMS_SIZE).unwrap();
+    writeln!(h, "/* rights of a handle, copies can only have fewer */").unwrap();
+    writeln!(h, "#define RIGHT_READ {}\n#define RIGHT_WRITE {}\n#define RIGHT_SIGNAL {}\n#define RIGHT_KILL {}\n#define RIGHT_TRANSFER {}\n#define RIGHTS_ALL {}\n#define NO_HANDLE {:#x}ULL\n", RIGHT_READ, RIGHT_WRITE, RIGHT_SIGNAL, RIGHT_KILL, RIGHT_TRANSFER, RIGHTS_ALL, NO_HANDLE).unwrap();
+    writeln!(h, "{}", C_STRUCTS).unwrap();
+    writeln!(h, "/* each entry calls the syscall of the same name */").unwrap();
+    writeln!(h, "typedef struct\n{{").unwrap();
+    for def in SYSCALLS
+    {
+        writeln!(h, "    {} (*c_{})({});", def.ret, def.name, params(def)).unwrap();
+    }
+    writeln!(h, "}} FFISysCallTable;\n").unwrap();
+    writeln!(h, "/* entry points get a pointer to this, only the first entry_count entries are valid */").unwrap();
+    writeln!(h, "typedef struct\n{{\n    unsigned int abi_version;\n    unsigned int entry_count;\n    FFISysCallTable entries;\n}} SysCallTable;\n").unwrap();
+    writeln!(h, "/* set by crt0 before main is called */").unwrap();
+    writeln!(h, "extern const SysCallTable *kernel_syscall_table;\n").unwrap();
+    for def in SYSCALLS
+    {
+        let noreturn = if def.noreturn {"_Noreturn "} else {""};
+        writeln!(h, "{}{} sys_{}({});", noreturn, def.ret, def.name, params(def)).unwrap();
+    }
+    writeln!(h, "\n/* crt0 calls main(argc, argv, envp), the return value becomes the exit code */").unwrap();
+    writeln!(h, "\n#endif").unwrap();
+    h
+}
+
+fn params(def: &SysCallDef) -> String
+{
+    if def.params.is_empty() {"void".to_owned()}
+    else {def.params.join(", ")}
+}
+
+fn write_enum(h: &mut String, name: &str, prefix: &str, variants: &[&str])
+{
+    writeln!(h, "enum {}\n{{", name).unwrap();
+    let lines: Vec<String> = variants.iter().enumerate().map(|(i, v)| format!("    {}_{} = {}", prefix, v, i)).collect();
+    writeln!(h, "{}\n}};\n", lines.join(",\n")).unwrap();
+}
+
+fn crt0() -> String
+{
+    let exit = SYSCALLS.iter().position(|def| def.name == "proc_exit").unwrap();
+    format!("/* generated by kernel/build.rs, do not edit */
+.intel_syntax noprefix
+.global _start
+_start:
+    mov [rip + kernel_syscall_table], rdi
+    mov rdi, rsi
+    mov rsi, rdx
+    mov rdx, rcx
+    sub rsp, 8
+    call main
+    mov edi, eax
+    mov eax, {}
+    int 0x80
+    jmp .
+
+.bss
+.balign 8
+.global kernel_syscall_table
+kernel_syscall_table:
+    .quad 0
+.section .note.GNU-stack, \"\", @progbits
+", exit)
+}
+
+//each wrapper keeps the System V argument registers, which is what the kernel reads them from
+fn syscalls() -> String
+{
+    let mut s = String::from("/* generated from kernel/src/abi_defs.rs by kernel/build.rs, do not edit */\n.intel_syntax noprefix\n");
+    for (nr, def) in SYSCALLS.iter().enumerate()
+    {
+        write!(s, "\n.global sys_{0}\nsys_{0}:\n    mov eax, {1}\n    int 0x80\n    ret\n", def.name, nr).unwrap();
+    }
+    s.push_str(".section .note.GNU-stack, \"\", @progbits\n");
+    s
+}
\ No newline at end of file
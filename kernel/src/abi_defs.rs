@@ -0,0 +1,232 @@
+//! Description of the syscall ABI in C terms.
+//!
+//! This file is also included by the kernel's build script, which generates the C header and
+//! runtime from it, so it must not depend on anything besides `core`.
+
+/// A syscall as seen from C.
+pub struct SysCallDef
+{
+    pub name: &'static str,
+    /// C return type.
+    pub ret: &'static str,
+    /// C parameter declarations.
+    pub params: &'static [&'static str],
+    /// Set for syscalls that never return to the caller.
+    pub noreturn: bool
+}
+
+const fn def(name: &'static str, ret: &'static str, params: &'static [&'static str]) -> SysCallDef
+{
+    SysCallDef{name, ret, params, noreturn: false}
+}
+
+/// Version of the syscall ABI, raised whenever existing syscalls change.
+pub const ABI_VERSION: u32 = 2;
+
+/// Every syscall, the index is its number.
+pub const SYSCALLS: &[SysCallDef] = &[
+    def("file_read_perms", "CVecShort", &["const char *path", "unsigned char *buf", "unsigned long long buf_len"]),
+    def("file_write_perms", "signed char", &["const char *path", "const unsigned char *perms", "unsigned long long perms_len"]),
+    def("file_read", "CVecShort", &["const char *path", "unsigned char *buf", "unsigned long long buf_len"]),
+    def("file_write", "signed char", &["const char *path", "const unsigned char *data", "unsigned long long data_len"]),
+    def("file_delete", "signed char", &["const char *path"]),
+    def("file_create", "signed char", &["const char *path"]),
+    def("time_now", "long long", &[]),
+    def("rand_buffer", "signed char", &["unsigned char *buf", "unsigned long long buf_len"]),
+    def("proc_set_priority", "signed char", &["unsigned long long pid", "unsigned char priority"]),
+    def("proc_set_time_slice", "signed char", &["unsigned long long ticks"]),
+    def("proc_stats", "signed char", &["unsigned long long pid", "ProcessStats *stats"]),
+    SysCallDef{name: "proc_exit", ret: "void", params: &["int code"], noreturn: true},
+    def("proc_wait", "signed char", &["unsigned long long *pid", "int *code"]),
+    def("proc_waitpid", "signed char", &["unsigned long long pid", "int *code"]),
+    def("proc_spawn_path", "signed char", &["const char *path", "unsigned long long *pid"]),
+    def("proc_kill", "signed char", &["unsigned long long pid"]),
+    def("proc_kill_self", "signed char", &[]),
+    def("proc_pid", "unsigned long long", &[]),
+    def("proc_list", "unsigned long long", &["LegacyProcessInfo *buf", "unsigned long long buf_len"]),
+    def("mem_map", "unsigned long long", &["unsigned long long addr", "unsigned long long pages"]),
+    def("mem_unmap", "signed char", &["unsigned long long addr", "unsigned long long pages"]),
+    def("abi_supported", "_Bool", &["unsigned long long nr"]),
+    def("proc_args", "CVecShort", &["unsigned char *buf", "unsigned long long buf_len"]),
+    def("proc_env", "CVecShort", &["unsigned char *buf", "unsigned long long buf_len"]),
+    def("proc_env_var", "CVecShort", &["const char *name", "unsigned char *buf", "unsigned long long buf_len"]),
+    def("proc_spawn_args", "signed char", &["const char *path", "const char *args", "unsigned long long args_len", "const char *env", "unsigned long long env_len", "unsigned long long *pid"]),
+    def("thread_create", "signed char", &["unsigned long long (*entry)(unsigned long long)", "unsigned long long arg", "unsigned long long *tid"]),
+    def("thread_join", "signed char", &["unsigned long long tid", "unsigned long long *value"]),
+    SysCallDef{name: "thread_exit", ret: "void", params: &["unsigned long long value"], noreturn: true},
+    def("thread_yield", "void", &[]),
+    def("thread_id", "unsigned long long", &[]),
+    def("sleep", "void", &["unsigned long long ms"]),
+    def("futex_wait", "signed char", &["const unsigned int *addr", "unsigned int expected"]),
+    def("futex_wake", "unsigned long long", &["const unsigned int *addr", "unsigned long long count"]),
+    def("pipe_create", "signed char", &["unsigned long long *read_handle", "unsigned long long *write_handle"]),
+    def("pipe_read", "signed char", &["unsigned long long handle", "unsigned char *buf", "unsigned long long buf_len", "unsigned long long *read"]),
+    def("pipe_write", "signed char", &["unsigned long long handle", "const unsigned char *data", "unsigned long long data_len"]),
+    def("handle_close", "signed char", &["unsigned long long handle"]),
+    def("handle_inherit", "signed char", &["unsigned long long handle", "_Bool inherit"]),
+    def("endpoint_register", "signed char", &["const char *name", "unsigned long long *handle"]),
+    def("endpoint_send", "signed char", &["const char *name", "const unsigned char *data", "unsigned long long data_len"]),
+    def("endpoint_send_pid", "signed char", &["unsigned long long pid", "const unsigned char *data", "unsigned long long data_len"]),
+    def("endpoint_receive", "CVecShort", &["unsigned long long handle", "unsigned char *buf", "unsigned long long buf_len", "unsigned long long *sender", "_Bool block"]),
+    def("shm_create", "signed char", &["const char *name", "unsigned long long pages", "_Bool writable", "_Bool executable", "unsigned long long *addr"]),
+    def("shm_map", "signed char", &["const char *name", "_Bool writable", "_Bool executable", "unsigned long long *addr", "unsigned long long *pages"]),
+    def("shm_unmap", "signed char", &["unsigned long long addr"]),
+    def("proc_signal", "signed char", &["unsigned long long pid", "unsigned char sig"]),
+    def("signal_action", "signed char", &["unsigned char sig", "unsigned long long handler", "unsigned long long *old"]),
+    SysCallDef{name: "signal_return", ret: "void", params: &[], noreturn: true},
+    def("proc_limits", "signed char", &["unsigned long long pid", "ResourceLimits *limits"]),
+    def("proc_set_child_limits", "signed char", &["const ResourceLimits *limits"]),
+    def("proc_ids", "signed char", &["unsigned long long pid", "Credentials *cred"]),
+    def("proc_set_ids", "signed char", &["unsigned int uid", "unsigned int gid"]),
+    def("handle_duplicate", "signed char", &["unsigned long long handle", "unsigned int rights", "unsigned long long *new_handle"]),
+    def("handle_rights", "signed char", &["unsigned long long handle", "unsigned int *rights"]),
+    def("file_open", "signed char", &["const char *path", "unsigned int rights", "unsigned long long *handle"]),
+    def("file_handle_read", "CVecShort", &["unsigned long long handle", "unsigned char *buf", "unsigned long long buf_len"]),
+    def("file_handle_write", "signed char", &["unsigned long long handle", "const unsigned char *data", "unsigned long long data_len"]),
+    def("proc_open", "signed char", &["unsigned long long pid", "unsigned int rights", "unsigned long long *handle"]),
+    def("proc_handle_signal", "signed char", &["unsigned long long handle", "unsigned char sig"]),
+    def("proc_handle_kill", "signed char", &["unsigned long long handle"]),
+    def("endpoint_send_handle", "signed char", &["const char *name", "const unsigned char *data", "unsigned long long data_len", "unsigned long long handle"]),
+    def("endpoint_receive_handle", "CVecShort", &["unsigned long long handle", "unsigned char *buf", "unsigned long long buf_len", "MessageInfo *info", "_Bool block"]),
+    def("file_read_mode", "CVecShort", &["const char *path", "unsigned char *buf", "unsigned long long buf_len"]),
+    def("file_write_mode", "signed char", &["const char *path", "const unsigned char *perms", "unsigned long long perms_len"]),
+    def("proc_list_ids", "unsigned long long", &["ProcessInfo *buf", "unsigned long long buf_len"]),
+    def("shm_create_mode", "signed char", &["const char *name", "unsigned long long pages", "unsigned int mode", "_Bool writable", "_Bool executable", "unsigned long long *addr"])
+];
+
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_ENTRY: u64 = 9;
+pub const AT_RANDOM: u64 = 25;
+/// Address of the `SysCallTable`, the other types have their System V meaning.
+pub const AT_SYSCALL_TABLE: u64 = 0x1000;
+
+/// Types of the auxiliary vector entries the kernel passes to programs, by name.
+pub const AUX_TYPES: &[(&str, u64)] = &[("AT_NULL", AT_NULL), ("AT_PHDR", AT_PHDR), ("AT_PHENT", AT_PHENT), ("AT_PHNUM", AT_PHNUM), ("AT_PAGESZ", AT_PAGESZ), ("AT_ENTRY", AT_ENTRY), ("AT_RANDOM", AT_RANDOM), ("AT_SYSCALL_TABLE", AT_SYSCALL_TABLE)];
+
+pub const SIGHUP: u8 = 1;
+pub const SIGINT: u8 = 2;
+pub const SIGQUIT: u8 = 3;
+pub const SIGABRT: u8 = 6;
+pub const SIGKILL: u8 = 9;
+pub const SIGUSR1: u8 = 10;
+pub const SIGSEGV: u8 = 11;
+pub const SIGUSR2: u8 = 12;
+pub const SIGPIPE: u8 = 13;
+pub const SIGALRM: u8 = 14;
+pub const SIGTERM: u8 = 15;
+pub const SIGCHLD: u8 = 17;
+pub const SIGCONT: u8 = 18;
+pub const SIGSTOP: u8 = 19;
+pub const SIGTSTP: u8 = 20;
+pub const SIGTTIN: u8 = 21;
+pub const SIGTTOU: u8 = 22;
+pub const SIGURG: u8 = 23;
+pub const SIGXCPU: u8 = 24;
+pub const SIGWINCH: u8 = 28;
+/// Signal numbers are below this, the ones without a name are valid too and terminate by default.
+pub const SIGNAL_COUNT: u8 = 32;
+
+/// Handler value that selects the default action of a signal.
+pub const SIG_DFL: u64 = 0;
+/// Handler value that discards a signal.
+pub const SIG_IGN: u64 = 1;
+
+/// Signal numbers by name.
+pub const SIGNALS: &[(&str, u8)] = &[("SIGHUP", SIGHUP), ("SIGINT", SIGINT), ("SIGQUIT", SIGQUIT), ("SIGABRT", SIGABRT), ("SIGKILL", SIGKILL), ("SIGUSR1", SIGUSR1), ("SIGSEGV", SIGSEGV), ("SIGUSR2", SIGUSR2), ("SIGPIPE", SIGPIPE), ("SIGALRM", SIGALRM), ("SIGTERM", SIGTERM), ("SIGCHLD", SIGCHLD), ("SIGCONT", SIGCONT), ("SIGSTOP", SIGSTOP), ("SIGTSTP", SIGTSTP), ("SIGTTIN", SIGTTIN), ("SIGTTOU", SIGTTOU), ("SIGURG", SIGURG), ("SIGXCPU", SIGXCPU), ("SIGWINCH", SIGWINCH)];
+
+/// User and group id of root, which passes every permission check.
+pub const ROOT_ID: u32 = 0;
+
+/// Set-user-ID bit, programs loaded from the file run with its owner as their effective user.
+pub const MODE_SETUID: u16 = 0o4000;
+/// Set-group-ID bit, programs loaded from the file run with its group as their effective group.
+pub const MODE_SETGID: u16 = 0o2000;
+/// Read, write and execute bits, shifted left by 6 for the owner and by 3 for the group.
+pub const MODE_READ: u16 = 4;
+pub const MODE_WRITE: u16 = 2;
+pub const MODE_EXEC: u16 = 1;
+/// Bits of the mode that can be set.
+pub const MODE_MASK: u16 = 0o6777;
+/// Size of the permissions the mode syscalls read and write, the owner and group as 32 bit and
+/// the mode as 16 bit little endian integers.
+pub const PERMS_SIZE: usize = 10;
+/// Size of the permissions the perms syscalls read and write, kept from before files had owners.
+/// The bytes are nonzero if reading, and writing or deleting, is left to privileged processes.
+pub const LEGACY_PERMS_SIZE: usize = 2;
+
+/// Rights of a handle. Reading a file or pipe and receiving from an endpoint need `RIGHT_READ`.
+pub const RIGHT_READ: u32 = 1;
+/// Writing a file or pipe.
+pub const RIGHT_WRITE: u32 = 2;
+/// Sending signals to a process.
+pub const RIGHT_SIGNAL: u32 = 4;
+/// Killing a process.
+pub const RIGHT_KILL: u32 = 8;
+/// Passing the handle to children or sending it over an endpoint.
+pub const RIGHT_TRANSFER: u32 = 16;
+pub const RIGHTS_ALL: u32 = 31;
+/// Handle number of a message without an attached handle.
+pub const NO_HANDLE: u64 = u64::MAX;
+
+/// Names of the `embedded_io::ErrorKind` codes file syscalls fail with, in code order.
+pub const ERROR_KINDS: &[&str] = &["OTHER", "NOT_FOUND", "PERMISSION_DENIED", "CONNECTION_REFUSED", "CONNECTION_RESET", "CONNECTION_ABORTED", "NOT_CONNECTED", "ADDR_IN_USE", "ADDR_NOT_AVAILABLE", "BROKEN_PIPE", "ALREADY_EXISTS", "INVALID_INPUT", "INVALID_DATA", "TIMED_OUT", "INTERRUPTED", "UNSUPPORTED", "OUT_OF_MEMORY"];
+
+/// Names of the `ProcessError` codes process syscalls fail with, in code order.
+pub const PROCESS_ERRORS: &[&str] = &["UNPRIVILEGED", "NOT_FOUND", "INVALID_INPUT", "READ_FAILED", "INVALID_EXECUTABLE", "OUT_OF_MEMORY", "LIMIT_EXCEEDED", "INTERRUPTED"];
+
+/// C definitions of the structures syscalls take and return.
+pub const C_STRUCTS: &str = "typedef struct
+{
+    signed char res;
+    const unsigned char *ptr;
+    unsigned long long len;
+} CVecShort;
+
+typedef struct
+{
+    unsigned long long ticks;
+    unsigned long long scheduled;
+    unsigned char priority;
+} ProcessStats;
+
+typedef struct
+{
+    unsigned long long pid;
+    unsigned long long parent;
+    unsigned int uid;
+    unsigned int gid;
+} ProcessInfo;
+
+typedef struct
+{
+    unsigned long long pid;
+    unsigned long long parent;
+    _Bool privileged;
+} LegacyProcessInfo;
+
+typedef struct
+{
+    unsigned long long pages;
+    unsigned long long handles;
+    unsigned long long cpu_ticks;
+    unsigned long long children;
+} ResourceLimits;
+
+typedef struct
+{
+    unsigned int uid;
+    unsigned int gid;
+    unsigned int euid;
+    unsigned int egid;
+} Credentials;
+
+typedef struct
+{
+    unsigned long long sender;
+    unsigned long long handle;
+} MessageInfo;
+";
\ No newline at end of file
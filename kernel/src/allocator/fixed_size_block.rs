@@ -2,6 +2,7 @@ use linked_list_allocator::Heap;
 use alloc::alloc::{Layout, GlobalAlloc};
 use core::ptr::{null_mut, NonNull};
 use core::mem;
+use x86_64::instructions::interrupts;
 use super::Locked;
 
 /// The block sizes to use.
@@ -64,6 +65,22 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator>
 {
     #[deny(unsafe_op_in_unsafe_fn)]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8
+    {
+        //the scheduler can preempt the kernel at any point, so the lock must never be held with interrupts enabled
+        interrupts::without_interrupts(|| unsafe{self.alloc_inner(layout)})
+    }
+
+    #[deny(unsafe_op_in_unsafe_fn)]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout)
+    {
+        interrupts::without_interrupts(|| unsafe{self.dealloc_inner(ptr, layout)})
+    }
+}
+
+impl Locked<FixedSizeBlockAllocator>
+{
+    #[deny(unsafe_op_in_unsafe_fn)]
+    unsafe fn alloc_inner(&self, layout: Layout) -> *mut u8
     {
         let mut allocator = self.lock();
         match allocator.list_index(&layout)
@@ -91,7 +108,7 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator>
     }
 
     #[deny(unsafe_op_in_unsafe_fn)]
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout)
+    unsafe fn dealloc_inner(&self, ptr: *mut u8, layout: Layout)
     {
         let mut allocator = self.lock();
         match allocator.list_index(&layout)
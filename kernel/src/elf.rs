@@ -0,0 +1,672 @@
+//! Loader for the 64 bit x86 ELF files processes are started from.
+//!
+//! Every offset, size and address taken from the file is checked before it is used, so a
+//! malformed file makes loading fail with a `LoadError` instead of bringing the kernel down.
+//!
+//! Files are never held in kernel memory as a whole. The headers are read piece by piece, the
+//! segments a page at a time straight into the pages they are mapped to, and the dynamic tables
+//! are read back from there while linking.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use alloc::string::String;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::PageTableFlags;
+use crate::memory::AddressSpace;
+use crate::usermode::{USER_REGION_START, ANON_REGION_START};
+use crate::linker::MAX_LIBRARIES;
+use crate::syscall_abi::MAX_PATH_LEN;
+
+/// Why an ELF file could not be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError
+{
+    /// The file does not start with a 64 bit little endian ELF header.
+    BadMagic,
+    /// The file is not built for x86_64.
+    WrongArch,
+    /// The file is neither an executable nor a position independent executable.
+    NotExecutable,
+    /// A header, table or segment lies outside the file, or the image outside the user region.
+    SegmentOutOfBounds,
+    /// The image has more loadable segments than the loader accepts.
+    TooManySegments,
+    /// Two loadable segments claim the same addresses.
+    OverlappingSegments,
+    /// A relocation of a kind the loader does not implement.
+    UnsupportedRelocation,
+    /// A relocation refers to a symbol nothing defines.
+    UndefinedSymbol,
+    /// A library the image needs is not on the filesystem, or can't be read without privileges.
+    LibraryNotFound,
+    /// A library the image needs is not a position independent shared object.
+    NotSharedObject,
+    /// The image needs more libraries than a process can load.
+    TooManyLibraries,
+    /// Memory for the image could not be mapped.
+    OutOfMemory,
+    /// The file could not be read.
+    ReadFailed
+}
+
+/// Where the loader reads an ELF file from.
+pub trait ElfSource
+{
+    /// Fills `buf` with the bytes of the file at `offset`, failing with `SegmentOutOfBounds` if
+    /// the file ends before.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), LoadError>;
+}
+
+impl ElfSource for &[u8]
+{
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), LoadError>
+    {
+        buf.copy_from_slice(bytes(self, offset, buf.len() as u64)?);
+        Ok(())
+    }
+}
+
+/// Largest TLS block an image can ask for.
+pub const TLS_MAX_SIZE: u64 = 1 << 20;
+/// Size of a program header.
+pub const PROGRAM_HEADER_SIZE: u64 = 56;
+/// Most loadable segments an image can have.
+pub const MAX_SEGMENTS: usize = 64;
+/// Longest symbol name the loader reads.
+pub const MAX_SYMBOL_NAME: usize = 4096;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const CLASS_64: u8 = 2;
+const DATA_LSB: u8 = 1;
+const MACHINE_X86_64: u16 = 62;
+const TYPE_EXEC: u16 = 2;
+const TYPE_DYN: u16 = 3;
+
+const HEADER_SIZE: u64 = 64;
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_TLS: u32 = 7;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_PLTRELSZ: u64 = 2;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_STRSZ: u64 = 10;
+const DT_REL: u64 = 17;
+const DT_PLTREL: u64 = 20;
+const DT_JMPREL: u64 = 23;
+const DT_GNU_HASH: u64 = 0x6fff_fef5;
+const DYNAMIC_SIZE: usize = 16;
+const RELA_SIZE: usize = 24;
+const SYM_SIZE: u64 = 24;
+
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_64: u32 = 1;
+const R_X86_64_COPY: u32 = 5;
+const R_X86_64_GLOB_DAT: u32 = 6;
+const R_X86_64_JUMP_SLOT: u32 = 7;
+const R_X86_64_RELATIVE: u32 = 8;
+const R_X86_64_TPOFF64: u32 = 18;
+
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xfff1;
+const STB_LOCAL: u8 = 0;
+const STB_WEAK: u8 = 2;
+
+/// A segment as described by its program header.
+#[derive(Clone, Copy)]
+pub struct Segment
+{
+    /// Address the segment was linked at.
+    pub vaddr: u64,
+    pub mem_size: u64,
+    pub offset: u64,
+    pub file_size: u64,
+    flags: u32
+}
+
+impl Segment
+{
+    /// Returns true if `len` bytes at the link time address `vaddr` lie in the segment.
+    pub fn contains(&self, vaddr: u64, len: u64) -> bool
+    {
+        vaddr >= self.vaddr && len <= self.mem_size && vaddr - self.vaddr <= self.mem_size - len
+    }
+
+    pub fn is_executable(&self) -> bool
+    {
+        self.flags & PF_X != 0
+    }
+
+    fn page_flags(&self) -> PageTableFlags
+    {
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if self.flags & PF_W != 0 {flags |= PageTableFlags::WRITABLE;}
+        if !self.is_executable() {flags |= PageTableFlags::NO_EXECUTE;}
+        flags
+    }
+}
+
+/// Initial contents of the thread local variables of an image.
+#[derive(Clone, Copy)]
+pub struct TlsTemplate
+{
+    /// Link time address of the initialized part, it lies inside a loadable segment.
+    pub vaddr: u64,
+    /// Bytes of initialized data, the rest of the block starts zeroed.
+    pub file_size: u64,
+    pub mem_size: u64,
+    /// Alignment of the block, a power of two no larger than a page.
+    pub align: u64
+}
+
+/// An entry of the dynamic symbol table.
+#[derive(Clone, Copy)]
+pub struct Symbol
+{
+    name: u32,
+    info: u8,
+    section: u16,
+    /// Link time address, or the value itself for absolute symbols.
+    pub value: u64,
+    pub size: u64
+}
+
+impl Symbol
+{
+    pub fn is_defined(&self) -> bool
+    {
+        self.section != SHN_UNDEF
+    }
+
+    /// Returns the address of the symbol once its image is moved up by `bias`.
+    pub fn address(&self, bias: u64) -> u64
+    {
+        if self.section == SHN_ABS {self.value}
+        else {self.value.wrapping_add(bias)}
+    }
+
+    fn binding(&self) -> u8
+    {
+        self.info >> 4
+    }
+}
+
+//what the dynamic segment says about relocations and symbols, tables are link time addresses
+#[derive(Default)]
+struct Dynamic
+{
+    rela: (u64, u64),
+    plt: (u64, u64),
+    symtab: Option<u64>,
+    strtab: (u64, u64),
+    hash: Option<u64>,
+    gnu_hash: Option<u64>,
+    //string table offsets of the names of needed libraries
+    needed: Vec<u64>
+}
+
+/// A parsed and validated ELF file.
+pub struct ElfImage
+{
+    /// Set for images linked at 0, which have to be moved before they can run.
+    pub position_independent: bool,
+    /// Link time address of the entry point, it lies inside a loadable segment.
+    pub entry: u64,
+    /// Loadable segments sorted by address, none of them overlap.
+    pub segments: Vec<Segment>,
+    /// Template of the TLS block every thread gets a copy of.
+    pub tls: Option<TlsTemplate>,
+    //file offset and number of the program headers
+    program_headers: (u64, u64),
+    dynamic: Dynamic
+}
+
+impl ElfImage
+{
+    /// Reads and checks the headers of the file in `source`.
+    pub fn parse(source: &mut impl ElfSource) -> Result<Self, LoadError>
+    {
+        let mut header = [0; HEADER_SIZE as usize];
+        source.read_at(0, &mut header).map_err(|e| if e == LoadError::SegmentOutOfBounds {LoadError::BadMagic} else {e})?;
+        if header[0..4] != ELF_MAGIC || header[4] != CLASS_64 || header[5] != DATA_LSB {return Err(LoadError::BadMagic);}
+        if read_u16(&header, 18)? != MACHINE_X86_64 {return Err(LoadError::WrongArch);}
+        let position_independent = match read_u16(&header, 16)?
+        {
+            TYPE_EXEC => false,
+            TYPE_DYN => true,
+            _ => return Err(LoadError::NotExecutable)
+        };
+        let entry = read_u64(&header, 24)?;
+        let ph_offset = read_u64(&header, 32)?;
+        if u64::from(read_u16(&header, 54)?) != PROGRAM_HEADER_SIZE {return Err(LoadError::BadMagic);}
+        let ph_count = u64::from(read_u16(&header, 56)?);
+
+        let mut segments = Vec::new();
+        let mut dynamic_segment = None;
+        let mut tls = None;
+        let mut ph = [0; PROGRAM_HEADER_SIZE as usize];
+        for i in 0..ph_count
+        {
+            source.read_at(element(ph_offset, i, PROGRAM_HEADER_SIZE)?, &mut ph)?;
+            let segment = Segment{vaddr: read_u64(&ph, 16)?, mem_size: read_u64(&ph, 40)?, offset: read_u64(&ph, 8)?, file_size: read_u64(&ph, 32)?, flags: read_u32(&ph, 4)?};
+            //whether the file really holds the segment shows once it is read
+            if segment.file_size > segment.mem_size || segment.offset.checked_add(segment.file_size).is_none() || segment.vaddr.checked_add(segment.mem_size).is_none() {return Err(LoadError::SegmentOutOfBounds);}
+            match read_u32(&ph, 0)?
+            {
+                PT_LOAD if segment.mem_size > 0 =>
+                {
+                    if segments.len() == MAX_SEGMENTS {return Err(LoadError::TooManySegments);}
+                    segments.push(segment);
+                }
+                PT_DYNAMIC => dynamic_segment = Some(segment),
+                PT_TLS =>
+                {
+                    let align = read_u64(&ph, 48)?.max(1);
+                    if !align.is_power_of_two() || align > 4096 || segment.mem_size > TLS_MAX_SIZE {return Err(LoadError::SegmentOutOfBounds);}
+                    tls = Some(TlsTemplate{vaddr: segment.vaddr, file_size: segment.file_size, mem_size: segment.mem_size, align});
+                }
+                _ => {}
+            }
+        }
+        segments.sort_unstable_by_key(|s| s.vaddr);
+        //segments may share a page, but not a byte
+        if segments.windows(2).any(|w| w[0].vaddr + w[0].mem_size > w[1].vaddr) {return Err(LoadError::OverlappingSegments);}
+        if !segments.iter().any(|s| s.contains(entry, 1)) {return Err(LoadError::SegmentOutOfBounds);}
+        if let Some(tls) = &tls
+        {
+            if !segments.iter().any(|s| s.contains(tls.vaddr, tls.file_size)) {return Err(LoadError::SegmentOutOfBounds);}
+        }
+        let dynamic = match dynamic_segment
+        {
+            Some(segment) => parse_dynamic(source, &segment)?,
+            None => Dynamic::default()
+        };
+        Ok(ElfImage{position_independent, entry, segments, tls, program_headers: (ph_offset, ph_count), dynamic})
+    }
+
+    /// Returns the link time address and number of the program headers, if they are loaded with the image.
+    pub fn program_headers(&self) -> Option<(u64, u64)>
+    {
+        let (offset, count) = self.program_headers;
+        let size = count * PROGRAM_HEADER_SIZE;
+        self.segments.iter().find(|s| offset >= s.offset && size <= s.file_size && offset - s.offset <= s.file_size - size).map(|s| (s.vaddr + (offset - s.offset), count))
+    }
+
+    /// Returns the lowest and one past the highest link time address of the image.
+    pub fn bounds(&self) -> (u64, u64)
+    {
+        let first = self.segments.first().unwrap();
+        let last = self.segments.last().unwrap();
+        (first.vaddr, last.vaddr + last.mem_size)
+    }
+
+    /// Maps the image into `space` moved up by `bias` and copies the segments from `source`, a
+    /// page at a time.
+    ///
+    /// The image has to lie below the anon region and must not overlap anything mapped already,
+    /// like the trampoline or another image. Only its own segments may share pages.
+    pub fn map(&self, source: &mut impl ElfSource, space: &AddressSpace, bias: u64) -> Result<(), LoadError>
+    {
+        let (low, high) = self.bounds();
+        let start = low.checked_add(bias).ok_or(LoadError::SegmentOutOfBounds)?;
+        let end = high.checked_add(bias).ok_or(LoadError::SegmentOutOfBounds)?;
+        if start < USER_REGION_START || end > ANON_REGION_START {return Err(LoadError::SegmentOutOfBounds);}
+        space.check_unused(VirtAddr::new(start), end - start).map_err(|_| LoadError::SegmentOutOfBounds)?;
+        let mut buf = vec![0; 4096];
+        for segment in &self.segments
+        {
+            let addr = VirtAddr::new(segment.vaddr + bias);
+            space.map(addr, segment.mem_size, segment.page_flags()).map_err(|_| LoadError::OutOfMemory)?;
+            let mut done = 0;
+            while done < segment.file_size
+            {
+                let len = (segment.file_size - done).min(buf.len() as u64) as usize;
+                source.read_at(segment.offset + done, &mut buf[..len])?;
+                space.write(addr + done, &buf[..len]).map_err(|_| LoadError::OutOfMemory)?;
+                done += len as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An image mapped into an address space.
+///
+/// Its dynamic tables are read from where the image was mapped, the file isn't needed anymore.
+#[derive(Clone, Copy)]
+pub struct Module<'m>
+{
+    pub image: &'m ElfImage,
+    pub space: &'m AddressSpace,
+    /// How far the image was moved up from the addresses it was linked at.
+    pub bias: u64,
+    /// Distance from the thread pointer down to the TLS block of the image.
+    pub tls_offset: u64
+}
+
+impl<'m> Module<'m>
+{
+    /// Returns the names of the libraries the image needs, in the order it lists them.
+    pub fn needed(&self) -> Result<Vec<String>, LoadError>
+    {
+        //names have to fit in a path, including the nul terminator
+        self.image.dynamic.needed.iter().map(|&name| String::from_utf8(self.string(name, MAX_PATH_LEN as usize - 1)?).map_err(|_| LoadError::LibraryNotFound)).collect()
+    }
+
+    /// Finds a symbol the image defines and exports through its hash table.
+    pub fn lookup(&self, name: &[u8]) -> Result<Option<Symbol>, LoadError>
+    {
+        let index = match (self.image.dynamic.gnu_hash, self.image.dynamic.hash)
+        {
+            (Some(table), _) => self.gnu_hash_lookup(table, name)?,
+            (None, Some(table)) => self.sysv_hash_lookup(table, name)?,
+            (None, None) => None
+        };
+        match index
+        {
+            Some(index) => Ok(Some(self.symbol(index)?)),
+            None => Ok(None)
+        }
+    }
+
+    fn gnu_hash_lookup(&self, table: u64, name: &[u8]) -> Result<Option<u64>, LoadError>
+    {
+        let buckets = u64::from(self.read_u32(table)?);
+        let first = u64::from(self.read_u32(element(table, 1, 4)?)?);
+        let bloom_size = u64::from(self.read_u32(element(table, 2, 4)?)?);
+        if buckets == 0 {return Ok(None);}
+        let bucket_table = element(element(table, 4, 4)?, bloom_size, 8)?;
+        let chain = element(bucket_table, buckets, 4)?;
+        let hash = gnu_hash(name);
+        let mut index = u64::from(self.read_u32(element(bucket_table, u64::from(hash) % buckets, 4)?)?);
+        if index < first {return Ok(None);}
+        //each chain ends with an entry that has the lowest bit set, and reading past the file fails
+        loop
+        {
+            let entry = self.read_u32(element(chain, index - first, 4)?)?;
+            if entry | 1 == hash | 1 && self.exports(index, name)? {return Ok(Some(index));}
+            if entry & 1 != 0 {return Ok(None);}
+            index += 1;
+        }
+    }
+
+    fn sysv_hash_lookup(&self, table: u64, name: &[u8]) -> Result<Option<u64>, LoadError>
+    {
+        let buckets = u64::from(self.read_u32(table)?);
+        let chain_len = u64::from(self.read_u32(element(table, 1, 4)?)?);
+        if buckets == 0 {return Ok(None);}
+        let chain = element(element(table, 2, 4)?, buckets, 4)?;
+        let mut index = u64::from(self.read_u32(element(table, 2 + u64::from(sysv_hash(name)) % buckets, 4)?)?);
+        //a malformed chain could loop, but it can't be longer than the symbol table
+        for _ in 0..chain_len
+        {
+            if index == 0 {break;}
+            if self.exports(index, name)? {return Ok(Some(index));}
+            index = u64::from(self.read_u32(element(chain, index, 4)?)?);
+        }
+        Ok(None)
+    }
+
+    //true if symbol `index` is a definition other images can link against called `name`
+    fn exports(&self, index: u64, name: &[u8]) -> Result<bool, LoadError>
+    {
+        let symbol = self.symbol(index)?;
+        Ok(symbol.is_defined() && symbol.binding() != STB_LOCAL && self.string_is(symbol.name.into(), name)?)
+    }
+
+    fn symbol(&self, index: u64) -> Result<Symbol, LoadError>
+    {
+        let symtab = self.image.dynamic.symtab.ok_or(LoadError::UndefinedSymbol)?;
+        let mut sym = [0; SYM_SIZE as usize];
+        self.read_mapped(element(symtab, index, SYM_SIZE)?, &mut sym)?;
+        Ok(Symbol{name: u32::from_le_bytes([sym[0], sym[1], sym[2], sym[3]]), info: sym[4], section: u16::from_le_bytes([sym[6], sym[7]]), value: le_u64(&sym[8..]), size: le_u64(&sym[16..])})
+    }
+
+    //nul terminated string at `offset` in the dynamic string table, without the terminator and
+    //at most `max` bytes long
+    fn string(&self, offset: u64, max: usize) -> Result<Vec<u8>, LoadError>
+    {
+        let (table, size) = self.image.dynamic.strtab;
+        let mut s = Vec::new();
+        let mut chunk = [0; 64];
+        loop
+        {
+            let at = offset.checked_add(s.len() as u64).ok_or(LoadError::SegmentOutOfBounds)?;
+            let len = size.saturating_sub(at).min(chunk.len() as u64) as usize;
+            if len == 0 || s.len() > max {return Err(LoadError::SegmentOutOfBounds);}
+            self.read_mapped(element(table, at, 1)?, &mut chunk[..len])?;
+            if let Some(end) = chunk[..len].iter().position(|&c| c == 0)
+            {
+                s.extend_from_slice(&chunk[..end]);
+                return if s.len() > max {Err(LoadError::SegmentOutOfBounds)} else {Ok(s)};
+            }
+            s.extend_from_slice(&chunk[..len]);
+        }
+    }
+
+    //true if the nul terminated string at `offset` in the dynamic string table is `name`
+    fn string_is(&self, offset: u64, name: &[u8]) -> Result<bool, LoadError>
+    {
+        let (table, size) = self.image.dynamic.strtab;
+        if offset >= size {return Err(LoadError::SegmentOutOfBounds);}
+        let len = name.len() as u64 + 1;
+        if len > size - offset {return Ok(false);}
+        let mut chunk = [0; 64];
+        let mut done = 0;
+        while done < len
+        {
+            let n = (len - done).min(chunk.len() as u64) as usize;
+            self.read_mapped(element(table, offset + done, 1)?, &mut chunk[..n])?;
+            if !chunk[..n].iter().eq(name.iter().chain(&[0]).skip(done as usize).take(n)) {return Ok(false);}
+            done += n as u64;
+        }
+        Ok(true)
+    }
+
+    fn read_u32(&self, vaddr: u64) -> Result<u32, LoadError>
+    {
+        let mut b = [0; 4];
+        self.read_mapped(vaddr, &mut b)?;
+        Ok(u32::from_le_bytes(b))
+    }
+
+    //reads what the file holds for the bytes at the link time address `vaddr` from where it was mapped
+    fn read_mapped(&self, vaddr: u64, buf: &mut [u8]) -> Result<(), LoadError>
+    {
+        let len = buf.len() as u64;
+        if !self.image.segments.iter().any(|s| vaddr >= s.vaddr && len <= s.file_size && vaddr - s.vaddr <= s.file_size - len) {return Err(LoadError::SegmentOutOfBounds);}
+        self.space.read(VirtAddr::new(vaddr + self.bias), buf).map_err(|_| LoadError::OutOfMemory)
+    }
+
+    /// Applies the relocations of the image.
+    ///
+    /// Symbols are looked up in the modules of `scope` in order, so every module has to be
+    /// mapped before any of them is relocated.
+    pub fn relocate(&self, scope: &[Module<'m>]) -> Result<(), LoadError>
+    {
+        self.apply_relocations(self.image.dynamic.rela, scope)?;
+        self.apply_relocations(self.image.dynamic.plt, scope)
+    }
+
+    fn apply_relocations(&self, (table, size): (u64, u64), scope: &[Module<'m>]) -> Result<(), LoadError>
+    {
+        let mut r = [0; RELA_SIZE];
+        for i in 0..size / RELA_SIZE as u64
+        {
+            self.read_mapped(element(table, i, RELA_SIZE as u64)?, &mut r)?;
+            let offset = le_u64(&r);
+            let info = le_u64(&r[8..]);
+            let addend = le_u64(&r[16..]);
+            let value = match info as u32
+            {
+                R_X86_64_NONE => continue,
+                R_X86_64_RELATIVE => self.bias.wrapping_add(addend),
+                R_X86_64_64 => self.address(info >> 32, scope)?.wrapping_add(addend),
+                R_X86_64_GLOB_DAT | R_X86_64_JUMP_SLOT => self.address(info >> 32, scope)?,
+                //offset of a thread local variable from the thread pointer, the initial exec model
+                R_X86_64_TPOFF64 => match self.definition(info >> 32, scope)?
+                {
+                    Some((symbol, module)) => symbol.value.wrapping_add(addend).wrapping_sub(module.tls_offset),
+                    None => return Err(LoadError::UndefinedSymbol)
+                },
+                R_X86_64_COPY =>
+                {
+                    self.copy_symbol(offset, info >> 32, scope)?;
+                    continue;
+                }
+                _ => return Err(LoadError::UnsupportedRelocation)
+            };
+            if !self.image.segments.iter().any(|s| s.contains(offset, 8)) {return Err(LoadError::SegmentOutOfBounds);}
+            self.space.write(VirtAddr::new(offset + self.bias), &value.to_le_bytes()).map_err(|_| LoadError::OutOfMemory)?;
+        }
+        Ok(())
+    }
+
+    //address a symbol of the dynamic symbol table resolves to, weak symbols nothing defines are null
+    fn address(&self, index: u64, scope: &[Module<'m>]) -> Result<u64, LoadError>
+    {
+        Ok(self.definition(index, scope)?.map_or(0, |(symbol, module)| symbol.address(module.bias)))
+    }
+
+    //the definition a symbol of the dynamic symbol table refers to and the module containing it,
+    //the first module of the scope defining it wins
+    fn definition(&self, index: u64, scope: &[Module<'m>]) -> Result<Option<(Symbol, Module<'m>)>, LoadError>
+    {
+        //symbol 0 stands for relocations without a symbol
+        if index == 0 {return Ok(Some((Symbol{name: 0, info: 0, section: SHN_ABS, value: 0, size: 0}, *self)));}
+        let symbol = self.symbol(index)?;
+        if symbol.binding() != STB_LOCAL
+        {
+            let name = self.string(symbol.name.into(), MAX_SYMBOL_NAME)?;
+            for module in scope
+            {
+                if let Some(found) = module.lookup(&name)? {return Ok(Some((found, *module)));}
+            }
+        }
+        //images without hash tables can still use their own symbols
+        if symbol.is_defined() {Ok(Some((symbol, *self)))}
+        else if symbol.binding() == STB_WEAK {Ok(None)}
+        else {Err(LoadError::UndefinedSymbol)}
+    }
+
+    //copy relocations move initialized data of a library into the program, which is linked against
+    //the copy, it is copied page by page inside the address space however large the symbol is
+    fn copy_symbol(&self, offset: u64, index: u64, scope: &[Module<'m>]) -> Result<(), LoadError>
+    {
+        let symbol = self.symbol(index)?;
+        let name = self.string(symbol.name.into(), MAX_SYMBOL_NAME)?;
+        if !self.image.segments.iter().any(|s| s.contains(offset, symbol.size)) {return Err(LoadError::SegmentOutOfBounds);}
+        for module in scope.iter().filter(|m| !core::ptr::eq(m.image, self.image))
+        {
+            if let Some(found) = module.lookup(&name)?
+            {
+                if !module.image.segments.iter().any(|s| s.contains(found.value, symbol.size)) {return Err(LoadError::SegmentOutOfBounds);}
+                let from = VirtAddr::try_new(found.address(module.bias)).map_err(|_| LoadError::SegmentOutOfBounds)?;
+                self.space.copy(from, VirtAddr::new(offset + self.bias), symbol.size).map_err(|_| LoadError::OutOfMemory)?;
+                return Ok(());
+            }
+        }
+        Err(LoadError::UndefinedSymbol)
+    }
+}
+
+//reads what the dynamic segment says, a chunk of entries at a time
+fn parse_dynamic(source: &mut impl ElfSource, segment: &Segment) -> Result<Dynamic, LoadError>
+{
+    let mut dynamic = Dynamic::default();
+    let mut chunk = [0; DYNAMIC_SIZE * 32];
+    let size = segment.file_size - segment.file_size % DYNAMIC_SIZE as u64;
+    let mut done = 0;
+    while done < size
+    {
+        let len = (size - done).min(chunk.len() as u64) as usize;
+        source.read_at(segment.offset + done, &mut chunk[..len])?;
+        for entry in chunk[..len].chunks_exact(DYNAMIC_SIZE)
+        {
+            let value = le_u64(&entry[8..]);
+            match le_u64(entry)
+            {
+                DT_NULL => return Ok(dynamic),
+                DT_NEEDED if dynamic.needed.len() == MAX_LIBRARIES => return Err(LoadError::TooManyLibraries),
+                DT_NEEDED => dynamic.needed.push(value),
+                DT_RELA => dynamic.rela.0 = value,
+                DT_RELASZ => dynamic.rela.1 = value,
+                DT_JMPREL => dynamic.plt.0 = value,
+                DT_PLTRELSZ => dynamic.plt.1 = value,
+                DT_SYMTAB => dynamic.symtab = Some(value),
+                DT_STRTAB => dynamic.strtab.0 = value,
+                DT_STRSZ => dynamic.strtab.1 = value,
+                DT_HASH => dynamic.hash = Some(value),
+                DT_GNU_HASH => dynamic.gnu_hash = Some(value),
+                //x86_64 only uses relocations with explicit addends
+                DT_REL => return Err(LoadError::UnsupportedRelocation),
+                DT_PLTREL if value != DT_RELA => return Err(LoadError::UnsupportedRelocation),
+                _ => {}
+            }
+        }
+        done += len as u64;
+    }
+    Ok(dynamic)
+}
+
+//address of entry `index` of a table of `size` byte entries
+fn element(table: u64, index: u64, size: u64) -> Result<u64, LoadError>
+{
+    index.checked_mul(size).and_then(|o| o.checked_add(table)).ok_or(LoadError::SegmentOutOfBounds)
+}
+
+fn sysv_hash(name: &[u8]) -> u32
+{
+    let mut h = 0u32;
+    for &c in name
+    {
+        h = (h << 4).wrapping_add(c.into());
+        let g = h & 0xf000_0000;
+        h ^= g >> 24;
+        h &= !g;
+    }
+    h
+}
+
+fn gnu_hash(name: &[u8]) -> u32
+{
+    name.iter().fold(5381u32, |h, &c| h.wrapping_mul(33).wrapping_add(c.into()))
+}
+
+fn bytes(data: &[u8], offset: u64, len: u64) -> Result<&[u8], LoadError>
+{
+    let end = offset.checked_add(len).ok_or(LoadError::SegmentOutOfBounds)?;
+    data.get(offset as usize..end as usize).ok_or(LoadError::SegmentOutOfBounds)
+}
+
+fn read_u16(data: &[u8], offset: u64) -> Result<u16, LoadError>
+{
+    let b = bytes(data, offset, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], offset: u64) -> Result<u32, LoadError>
+{
+    let b = bytes(data, offset, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(data: &[u8], offset: u64) -> Result<u64, LoadError>
+{
+    Ok(le_u64(bytes(data, offset, 8)?))
+}
+
+//callers make sure there are at least 8 bytes
+fn le_u64(b: &[u8]) -> u64
+{
+    let mut v = [0; 8];
+    v.copy_from_slice(&b[..8]);
+    u64::from_le_bytes(v)
+}
\ No newline at end of file
@@ -0,0 +1,186 @@
+//! Named endpoints processes send messages to.
+//!
+//! A process registers an endpoint under a name and receives from it through a handle, others
+//! send to it by that name or by the pid of the process that registered it. Every message
+//! carries the pid of its sender, set by the kernel, and can carry a handle, which the receiver
+//! gets a copy of. An endpoint is unregistered once the last handle to it is closed.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use embedded_io::ErrorKind;
+use spin::Mutex;
+use crate::handle::Object;
+use crate::usermode::UserValue;
+use x86_64::instructions::interrupts;
+
+/// Longest message, receiving into a buffer of this size never cuts a message short.
+pub const MAX_MESSAGE_SIZE: usize = 1024;
+/// Bytes of messages an endpoint queues before senders block.
+pub const ENDPOINT_CAPACITY: usize = 4096;
+/// Longest endpoint name.
+pub const MAX_ENDPOINT_NAME: usize = 64;
+
+//only locked with interrupts disabled, like the state of each endpoint
+static ENDPOINTS: Mutex<BTreeMap<String, Arc<Endpoint>>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+pub struct Message
+{
+    /// Pid of the sending process.
+    pub sender: u64,
+    pub data: Vec<u8>,
+    /// Object of a handle sent along and the rights the copy has.
+    pub handle: Option<(Object, u32)>
+}
+
+/// Where receiving a message that can carry a handle stores its sender and handle, `NO_HANDLE`
+/// if none was sent along.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct MessageInfo
+{
+    pub sender: u64,
+    pub handle: u64
+}
+
+unsafe impl UserValue for MessageInfo {}
+
+struct EndpointState
+{
+    queue: VecDeque<Message>,
+    //bytes of message data in the queue
+    queued: usize,
+    open: bool
+}
+
+struct Endpoint
+{
+    name: String,
+    //process that registered the endpoint and the order it did so in, for sending by pid
+    owner: u64,
+    id: u64,
+    state: Mutex<EndpointState>
+}
+
+impl Endpoint
+{
+    //threads blocked on the endpoint wait on its address
+    fn key(&self) -> u64
+    {
+        self as *const Endpoint as u64
+    }
+
+    //blocks while the queue is full
+    fn send(self: &Arc<Self>, message: Message) -> Result<(), ErrorKind>
+    {
+        //an endpoint queueing its own receiver would keep itself alive
+        if let Some((Object::Endpoint(receiver), _)) = &message.handle
+        {
+            if Arc::ptr_eq(&receiver.endpoint, self) {return Err(ErrorKind::InvalidInput);}
+        }
+        let mut message = Some(message);
+        crate::proc_watch::wait_on(self.key(), ||
+        {
+            let mut state = self.state.lock();
+            if !state.open {return Some(Err(ErrorKind::BrokenPipe));}
+            let len = message.as_ref().map_or(0, |m| m.data.len());
+            if state.queued + len > ENDPOINT_CAPACITY {return None;}
+            state.queued += len;
+            state.queue.extend(message.take());
+            Some(Ok(()))
+        })?
+    }
+}
+
+/// The receiving side of a registered endpoint, unregisters it when dropped.
+pub struct EndpointReceiver
+{
+    endpoint: Arc<Endpoint>
+}
+
+/// Registers an endpoint for the process `owner` under `name`.
+pub fn register(name: &str, owner: u64) -> Result<EndpointReceiver, ErrorKind>
+{
+    if name.is_empty() || name.len() > MAX_ENDPOINT_NAME {return Err(ErrorKind::InvalidInput);}
+    interrupts::without_interrupts(||
+    {
+        let mut endpoints = ENDPOINTS.lock();
+        if endpoints.contains_key(name) {return Err(ErrorKind::AlreadyExists);}
+        let state = EndpointState{queue: VecDeque::new(), queued: 0, open: true};
+        let endpoint = Arc::new(Endpoint{name: name.into(), owner, id: NEXT_ID.fetch_add(1, Ordering::Relaxed), state: Mutex::new(state)});
+        endpoints.insert(name.into(), endpoint.clone());
+        Ok(EndpointReceiver{endpoint})
+    })
+}
+
+/// Sends a message to the endpoint registered under `name`, blocking while its queue is full.
+/// Fails with `ErrorKind::Interrupted` if a signal arrives while it blocks.
+///
+/// A message that isn't queued is dropped once this returns, with the handle it carries.
+pub fn send(name: &str, message: Message) -> Result<(), ErrorKind>
+{
+    let endpoint = interrupts::without_interrupts(|| ENDPOINTS.lock().get(name).cloned());
+    send_to(endpoint, message)
+}
+
+/// Sends a message to the endpoint the process `pid` registered first among the ones it still has.
+pub fn send_pid(pid: u64, message: Message) -> Result<(), ErrorKind>
+{
+    let endpoint = interrupts::without_interrupts(|| ENDPOINTS.lock().values().filter(|e| e.owner == pid).min_by_key(|e| e.id).cloned());
+    send_to(endpoint, message)
+}
+
+fn send_to(endpoint: Option<Arc<Endpoint>>, message: Message) -> Result<(), ErrorKind>
+{
+    if message.data.len() > MAX_MESSAGE_SIZE {return Err(ErrorKind::InvalidInput);}
+    endpoint.ok_or(ErrorKind::NotFound)?.send(message)
+}
+
+impl EndpointReceiver
+{
+    /// Returns the next message, waiting for one if `block` is set.
+    ///
+    /// Fails with `ErrorKind::TimedOut` if there is none and `block` is not set, and with
+    /// `ErrorKind::Interrupted` if a signal arrives while it waits.
+    pub fn receive(&self, block: bool) -> Result<Message, ErrorKind>
+    {
+        let endpoint = &self.endpoint;
+        crate::proc_watch::wait_on(endpoint.key(), ||
+        {
+            let mut state = endpoint.state.lock();
+            match state.queue.pop_front()
+            {
+                Some(message) =>
+                {
+                    state.queued -= message.data.len();
+                    Some(Ok(message))
+                },
+                None if block => None,
+                None => Some(Err(ErrorKind::TimedOut))
+            }
+        })?
+    }
+}
+
+impl Drop for EndpointReceiver
+{
+    fn drop(&mut self)
+    {
+        let endpoint = &self.endpoint;
+        //the messages are freed once the endpoint is unlocked and interrupts are enabled again
+        let queue = interrupts::without_interrupts(||
+        {
+            let mut endpoints = ENDPOINTS.lock();
+            if endpoints.get(&endpoint.name).is_some_and(|e| Arc::ptr_eq(e, endpoint)) {endpoints.remove(&endpoint.name);}
+            let mut state = endpoint.state.lock();
+            state.open = false;
+            state.queued = 0;
+            crate::proc_watch::wake_blocked(endpoint.key());
+            core::mem::take(&mut state.queue)
+        });
+        drop(queue);
+    }
+}
\ No newline at end of file
@@ -38,7 +38,11 @@ impl super::FileSystem for FatFileSystem
             a = i.unwrap().attributes();
             break;
         }
-        Ok(FilePermissions{read_privileged: a == FileAttributes::HIDDEN, write_delete_privileged: a == FileAttributes::READ_ONLY})
+        //FAT has no owners, files belong to root and hidden ones can only be read by it
+        let mut mode = super::DEFAULT_MODE;
+        if a == FileAttributes::HIDDEN {mode &= !0o055;}
+        if a == FileAttributes::READ_ONLY {mode &= !0o222;}
+        Ok(FilePermissions{owner: 0, group: 0, mode})
     }
 
     fn read(&self, path: &str) -> Result<Vec<u8>, FileError>
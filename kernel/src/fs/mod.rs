@@ -3,35 +3,111 @@
 pub mod testfs;
 
 use alloc::vec::Vec;
+use alloc::string::String;
 use embedded_io::ErrorKind;
 use spin::{Mutex, Lazy};
 use testfs::TestFS;
 use crate::device::ata::AtaStream;
+use crate::proc::Credentials;
+use crate::abi_defs::{MODE_SETUID, MODE_SETGID, MODE_READ, MODE_WRITE, MODE_EXEC, MODE_MASK, PERMS_SIZE, LEGACY_PERMS_SIZE, ROOT_ID};
 
 pub static FILESYSTEM: Lazy<Mutex<TestFS<AtaStream>>> = Lazy::new(|| Mutex::new(TestFS::init(AtaStream::new())));
 
-#[derive(Default)]
+/// Mode of new files, the owner can read and write them and everyone else can read them. Everyone
+/// can also start them, as any readable file could be started before files had modes.
+pub const DEFAULT_MODE: u16 = 0o755;
+
+/// Owner, group and mode bits of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FilePermissions
 {
-    read_privileged: bool,
-    write_delete_privileged: bool
+    pub owner: u32,
+    pub group: u32,
+    pub mode: u16
+}
+
+impl Default for FilePermissions
+{
+    fn default() -> Self
+    {
+        FilePermissions{owner: ROOT_ID, group: ROOT_ID, mode: DEFAULT_MODE}
+    }
 }
 
 impl FilePermissions
 {
     pub fn from_byte_slice(slice: &[u8]) -> Self
     {
-        let read_privileged = byte_to_bool(slice[0]);
-        let write_delete_privileged = byte_to_bool(slice[1]);
-        FilePermissions {read_privileged, write_delete_privileged}
+        let owner = u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]);
+        let group = u32::from_le_bytes([slice[4], slice[5], slice[6], slice[7]]);
+        let mode = u16::from_le_bytes([slice[8], slice[9]]);
+        FilePermissions{owner, group, mode}
     }
-    pub fn to_byte_arr(&self) -> [u8; 2]
+
+    pub fn to_byte_arr(&self) -> [u8; PERMS_SIZE]
     {
-        let mut res = [0u8; 2];
-        res[0] = self.read_privileged.into();
-        res[1] = self.read_privileged.into();
+        let mut res = [0u8; PERMS_SIZE];
+        res[..4].copy_from_slice(&self.owner.to_le_bytes());
+        res[4..8].copy_from_slice(&self.group.to_le_bytes());
+        res[8..].copy_from_slice(&self.mode.to_le_bytes());
         res
     }
+
+    /// Returns the permissions in the layout from before files had owners, where reading, and
+    /// writing or deleting, is privileged when others may not do it.
+    pub fn to_legacy_arr(&self) -> [u8; LEGACY_PERMS_SIZE]
+    {
+        [(self.mode & MODE_READ == 0).into(), (self.mode & MODE_WRITE == 0).into()]
+    }
+
+    /// Returns the permissions of a file stored before files had owners, which belongs to root
+    /// and is readable, startable and writable by others as `legacy`, in the layout of
+    /// `to_legacy_arr`, says.
+    pub fn from_legacy(legacy: &[u8]) -> FilePermissions
+    {
+        FilePermissions{owner: ROOT_ID, group: ROOT_ID, mode: 0o700}.with_legacy(legacy)
+    }
+
+    /// Returns these permissions changed as `legacy` in the layout of `to_legacy_arr` says, the
+    /// group and others lose or get the read or write bit. The execute bit goes with the read bit,
+    /// readable files could be started before files had modes. The owner keeps its bits.
+    pub fn with_legacy(&self, legacy: &[u8]) -> FilePermissions
+    {
+        let apply = |mode: u16, privileged: u8, bit: u16| if privileged != 0 {mode & !(bit << 3 | bit)} else {mode | bit << 3 | bit};
+        let mode = apply(apply(self.mode, legacy[0], MODE_READ | MODE_EXEC), legacy[1], MODE_WRITE);
+        FilePermissions{mode, ..*self}
+    }
+
+    /// Returns whether `cred` may access the file in every way in `access`, a combination of
+    /// `MODE_READ`, `MODE_WRITE` and `MODE_EXEC`.
+    ///
+    /// The owner bits apply to the owner, the group bits to other members of the group and the
+    /// rest to everyone else. Root may do anything, but only execute files someone may execute.
+    pub fn allows(&self, cred: &Credentials, access: u16) -> bool
+    {
+        if cred.is_root() {return access & MODE_EXEC == 0 || self.mode & 0o111 != 0;}
+        let shift = if cred.euid == self.owner {6} else if cred.egid == self.group {3} else {0};
+        (self.mode >> shift) & access == access
+    }
+
+    /// Returns whether `cred` may replace these permissions with `new`.
+    ///
+    /// Only root and the owner can change permissions, and only root can give files away. The
+    /// owner can move a file to its own effective group.
+    pub fn may_change(&self, new: &FilePermissions, cred: &Credentials) -> bool
+    {
+        if new.mode & !MODE_MASK != 0 {return false;}
+        if cred.is_root() {return true;}
+        cred.euid == self.owner && new.owner == self.owner && (new.group == self.group || new.group == cred.egid)
+    }
+
+    /// Returns the permissions after someone other than root changed the file, which loses its
+    /// setuid and setgid bits so a program can't be replaced while keeping them.
+    pub fn written_by(&self, cred: &Credentials) -> FilePermissions
+    {
+        if cred.is_root() {return *self;}
+        FilePermissions{mode: self.mode & !(MODE_SETUID | MODE_SETGID), ..*self}
+    }
 }
 
 #[derive(Default)]
@@ -76,6 +152,13 @@ impl FileDates
     }
 }
 
+/// A file as handles refer to it, the path it was opened at with the id the file had there.
+pub struct OpenFile
+{
+    pub path: String,
+    pub id: u64
+}
+
 /*pub struct Directory
 {
     entries: Vec<String>
@@ -90,42 +173,33 @@ pub struct File
 
 impl File
 {
-    pub const fn create() -> Self
+    pub const fn create(owner: &Credentials) -> Self
     {
-        let permissions = FilePermissions{read_privileged: false, write_delete_privileged: false};
+        let permissions = FilePermissions{owner: owner.euid, group: owner.egid, mode: DEFAULT_MODE};
         let dates = FileDates{create_date: 0, modify_date: 0, access_date: 0};
         File{data: Vec::new(), permissions, dates}
     }
 
-    pub fn read(&mut self, privileged: bool) -> Result<Vec<u8>, ErrorKind>
+    pub fn read(&mut self, cred: &Credentials) -> Result<Vec<u8>, ErrorKind>
     {
-        if self.permissions.read_privileged && !privileged {return Err(ErrorKind::PermissionDenied);}
+        if !self.permissions.allows(cred, MODE_READ) {return Err(ErrorKind::PermissionDenied);}
         self.dates.access_date = 0;
         Ok(self.data.clone())
     }
 
-    pub fn write(&mut self, data: &[u8], privileged: bool) -> Result<(), ErrorKind>
+    pub fn write(&mut self, data: &[u8], cred: &Credentials) -> Result<(), ErrorKind>
     {
-        if self.permissions.write_delete_privileged && !privileged {return Err(ErrorKind::PermissionDenied);}
+        if !self.permissions.allows(cred, MODE_WRITE) {return Err(ErrorKind::PermissionDenied);}
         self.dates.modify_date = 0;
         self.data = data.into();
+        self.permissions = self.permissions.written_by(cred);
         Ok(())
     }
 
-    pub fn mod_permissions(&mut self, read: Option<bool>, write: Option<bool>, privileged: bool) -> Result<(), ErrorKind>
+    pub fn mod_permissions(&mut self, perms: FilePermissions, cred: &Credentials) -> Result<(), ErrorKind>
     {
-        if !privileged {return Err(ErrorKind::PermissionDenied);}
-        let read_privileged =
-        {
-            if let Some(val) = read {val}
-            else {self.permissions.read_privileged}
-        };
-        let write_delete_privileged =
-        {
-            if let Some(val) = write {val}
-            else {self.permissions.write_delete_privileged}
-        };
-        self.permissions = FilePermissions{read_privileged, write_delete_privileged};
+        if !self.permissions.may_change(&perms, cred) {return Err(ErrorKind::PermissionDenied);}
+        self.permissions = perms;
         Ok(())
     }
 }
@@ -138,36 +212,108 @@ pub trait FileSystem<T>
     fn write_perms(&mut self, path: &str, perms: FilePermissions) -> Result<(), ErrorKind>;
     
     fn read(&mut self, path: &str) -> Result<Vec<u8>, ErrorKind>;
+    /// Reads the bytes of the file at `offset` into `buf` and returns how many there were, fewer
+    /// than fit only where the file ends.
+    fn read_at(&mut self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, ErrorKind>;
+    /// Returns the size of the file at `path` in bytes.
+    fn file_size(&mut self, path: &str) -> Result<u64, ErrorKind>;
     fn write(&mut self, path: &str, data: &[u8]) -> Result<(), ErrorKind>;
     fn delete(&mut self, path: &str) -> Result<(), ErrorKind>;
-    fn create(&mut self, path: &str) -> Result<(), ErrorKind>;
+    /// Creates an empty file at `path` with `perms`, failing with `ErrorKind::AlreadyExists` if
+    /// there is one already. Names are unique, so every other method finds the file by its path.
+    fn create(&mut self, path: &str, perms: FilePermissions) -> Result<(), ErrorKind>;
+    /// Returns a number that identifies the file at `path` until it is deleted, a file created
+    /// at the same path later gets another one.
+    fn file_id(&mut self, path: &str) -> Result<u64, ErrorKind>;
 
-    fn write_perms_checked(&mut self, path: &str, perms: FilePermissions, privileged: bool) -> Result<(), ErrorKind>
+    fn write_perms_checked(&mut self, path: &str, perms: FilePermissions, cred: &Credentials) -> Result<(), ErrorKind>
     {
-        if !privileged {return Err(ErrorKind::PermissionDenied);}
+        let old = self.get_perms(path)?;
+        if !old.may_change(&perms, cred) {return Err(ErrorKind::PermissionDenied);}
         self.write_perms(path, perms)
     }
 
-    fn read_checked(&mut self, path: &str, privileged: bool) -> Result<Vec<u8>, ErrorKind>
+    fn read_checked(&mut self, path: &str, cred: &Credentials) -> Result<Vec<u8>, ErrorKind>
     {
         let perms = self.get_perms(path)?;
-        if perms.read_privileged && !privileged {return Err(ErrorKind::PermissionDenied);}
+        if !perms.allows(cred, MODE_READ) {return Err(ErrorKind::PermissionDenied);}
         self.read(path)
     }
 
-    fn write_checked(&mut self, path: &str, data: &[u8], privileged: bool) -> Result<(), ErrorKind>
+    /// Opens a program to run, which needs read and execute permission, and returns it with its
+    /// permissions so the loader can apply the setuid and setgid bits.
+    ///
+    /// The loader reads the program with `read_open_at` as it maps it, so it is never in kernel
+    /// memory as a whole.
+    fn load_checked(&mut self, path: &str, cred: &Credentials) -> Result<(OpenFile, FilePermissions), ErrorKind>
+    {
+        let perms = self.get_perms(path)?;
+        if !perms.allows(cred, MODE_READ | MODE_EXEC) {return Err(ErrorKind::PermissionDenied);}
+        Ok((OpenFile{path: path.into(), id: self.file_id(path)?}, perms))
+    }
+
+    fn write_checked(&mut self, path: &str, data: &[u8], cred: &Credentials) -> Result<(), ErrorKind>
+    {
+        let perms = self.get_perms(path)?;
+        if !perms.allows(cred, MODE_WRITE) {return Err(ErrorKind::PermissionDenied);}
+        self.write_granted(path, data, cred)
+    }
+
+    /// Writes a file `cred` was already given access to, like through a handle. Writes by anyone
+    /// but root still clear the setuid and setgid bits.
+    fn write_granted(&mut self, path: &str, data: &[u8], cred: &Credentials) -> Result<(), ErrorKind>
+    {
+        let perms = self.get_perms(path)?;
+        self.write(path, data)?;
+        let written = perms.written_by(cred);
+        if written != perms {self.write_perms(path, written)?;}
+        Ok(())
+    }
+
+    /// Returns the file at `path` for a handle if `cred` may access it in every way in `access`.
+    fn open_checked(&mut self, path: &str, access: u16, cred: &Credentials) -> Result<OpenFile, ErrorKind>
     {
         let perms = self.get_perms(path)?;
-        if perms.write_delete_privileged && !privileged {return Err(ErrorKind::PermissionDenied);}
-        self.write(path, data)
+        if !perms.allows(cred, access) {return Err(ErrorKind::PermissionDenied);}
+        Ok(OpenFile{path: path.into(), id: self.file_id(path)?})
+    }
+
+    /// Reads a file opened with `open_checked`, failing with `ErrorKind::NotFound` once it was
+    /// deleted, even if there is another file at its path now.
+    fn read_open(&mut self, file: &OpenFile) -> Result<Vec<u8>, ErrorKind>
+    {
+        if self.file_id(&file.path)? != file.id {return Err(ErrorKind::NotFound);}
+        self.read(&file.path)
+    }
+
+    /// Reads part of a file opened with `open_checked` like `read_at`, failing like `read_open`.
+    fn read_open_at(&mut self, file: &OpenFile, offset: u64, buf: &mut [u8]) -> Result<usize, ErrorKind>
+    {
+        if self.file_id(&file.path)? != file.id {return Err(ErrorKind::NotFound);}
+        self.read_at(&file.path, offset, buf)
+    }
+
+    /// Writes a file opened with `open_checked` like `write_granted`, failing like `read_open`
+    /// once it was deleted.
+    fn write_open(&mut self, file: &OpenFile, data: &[u8], cred: &Credentials) -> Result<(), ErrorKind>
+    {
+        if self.file_id(&file.path)? != file.id {return Err(ErrorKind::NotFound);}
+        self.write_granted(&file.path, data, cred)
     }
 
-    fn delete_checked(&mut self, path: &str, privileged: bool) -> Result<(), ErrorKind>
+    fn delete_checked(&mut self, path: &str, cred: &Credentials) -> Result<(), ErrorKind>
     {
         let perms = self.get_perms(path)?;
-        if perms.write_delete_privileged && !privileged {return Err(ErrorKind::PermissionDenied);}
+        if !perms.allows(cred, MODE_WRITE) {return Err(ErrorKind::PermissionDenied);}
         self.delete(path)
     }
+
+    /// Creates a file owned by the effective user and group of `cred`, failing with
+    /// `ErrorKind::AlreadyExists` without touching the file if there is one at `path` already.
+    fn create_checked(&mut self, path: &str, cred: &Credentials) -> Result<(), ErrorKind>
+    {
+        self.create(path, FilePermissions{owner: cred.euid, group: cred.egid, mode: DEFAULT_MODE})
+    }
 }
 
 pub fn byte_to_bool(byte: u8) -> bool
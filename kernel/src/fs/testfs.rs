@@ -1,4 +1,4 @@
-use super::{FilePermissions, FileDates, slice_to_arr};
+use super::{FilePermissions, FileDates, slice_to_arr, PERMS_SIZE, LEGACY_PERMS_SIZE};
 use alloc::vec::Vec;
 use alloc::vec;
 use alloc::string::{String, ToString};
@@ -7,6 +7,14 @@ use crate::device::DeviceStream;
 use embedded_io::{ErrorKind, SeekFrom};
 
 const BLOCK_SIZE: usize = 512;
+//the permissions in the layout from before files had owners, the dates and the three data block
+//fields are at the end of a metadata block, the name area before them
+const NAME_AREA: usize = ata_x86::ATA_BLOCK_SIZE - LEGACY_PERMS_SIZE - 48;
+//blocks written since files have owners end the name area with this and the full permissions,
+//older blocks have a name reaching it or nuls there, so they never match
+const PERMS_MAGIC: [u8; 4] = [0, b'M', b'O', b'D'];
+//longest name of a file created since files have owners
+const NAME_SIZE: usize = NAME_AREA - PERMS_MAGIC.len() - PERMS_SIZE;
 
 struct MetadataBlock
 {
@@ -27,23 +35,27 @@ impl MetadataBlock
 
     pub fn parse(slice: &[u8]) -> Self
     {
-        let split1 = slice.split_at(ata_x86::ATA_BLOCK_SIZE - 50);
-        let split2 = split1.1.split_at(2);
+        let split1 = slice.split_at(NAME_AREA);
+        let split2 = split1.1.split_at(LEGACY_PERMS_SIZE);
         let split3 = split2.1.split_at(24);
         let split4 = split3.1.split_at(8);
         let split5 = split4.1.split_at(8);
         let split6 = split5.1.split_at(8);
+        let (name_area, perms) = if split1.0[NAME_SIZE..NAME_SIZE + PERMS_MAGIC.len()] == PERMS_MAGIC
+        {
+            (&split1.0[..NAME_SIZE], FilePermissions::from_byte_slice(&split1.0[NAME_SIZE + PERMS_MAGIC.len()..]))
+        }
+        else {(split1.0, FilePermissions::from_legacy(split2.0))};
         let name =
         {
             let mut vec: Vec<u8> = Vec::new();
-            for i in split1.0
+            for i in name_area
             {
                 let o = i.to_owned();
                 if o != 0 {vec.push(o);}
             }
             String::from_utf8(vec).unwrap()
         };
-        let perms = FilePermissions::from_byte_slice(split2.0);
         let dates = FileDates::from_byte_slice(split3.0);
         let data_start_block = u64::from_le_bytes(slice_to_arr(split4.0));
         let data_block_len = u64::from_le_bytes(slice_to_arr(split5.0));
@@ -51,22 +63,35 @@ impl MetadataBlock
         MetadataBlock{name, perms, dates, data_start_block, data_block_len, last_block_len}
     }
 
+    //the last data block is only used up to `last_block_len`
+    fn file_size(&self) -> u64
+    {
+        if self.data_block_len == 0 {0}
+        else {(self.data_block_len - 1) * BLOCK_SIZE as u64 + self.last_block_len}
+    }
+
     pub fn to_bytes(&self) -> [u8; ata_x86::ATA_BLOCK_SIZE]
     {
         let mut res = [0u8; ata_x86::ATA_BLOCK_SIZE];
         let name_vec = self.name.as_bytes().to_vec();
-        for i in 0..ata_x86::ATA_BLOCK_SIZE - 50
+        for i in 0..NAME_AREA
         {
             if i < name_vec.len() {res[i] = name_vec[i];}
         }
-        let perms_arr = self.perms.to_byte_arr();
+        //only files from before files had owners can have longer names, they keep the old layout
+        if name_vec.len() <= NAME_SIZE
+        {
+            res[NAME_SIZE..NAME_SIZE + PERMS_MAGIC.len()].copy_from_slice(&PERMS_MAGIC);
+            res[NAME_SIZE + PERMS_MAGIC.len()..NAME_AREA].copy_from_slice(&self.perms.to_byte_arr());
+        }
+        let perms_arr = self.perms.to_legacy_arr();
         let dates_arr = self.dates.to_byte_arr();
         let start_arr = self.data_start_block.to_le_bytes();
         let block_len_arr = self.data_block_len.to_le_bytes();
         let last_len_arr = self.last_block_len.to_le_bytes();
-        for i in 0..2
+        for i in 0..LEGACY_PERMS_SIZE
         {
-            res[(ata_x86::ATA_BLOCK_SIZE - 50) + i] = perms_arr[i];
+            res[NAME_AREA + i] = perms_arr[i];
         }
         for i in 0..24
         {
@@ -164,6 +189,33 @@ impl<T> super::FileSystem<T> for TestFS<T> where T: DeviceStream
         Ok(data)
     }
 
+    //only reads the blocks the range touches
+    fn read_at(&mut self, path: &str, offset: u64, buf: &mut [u8]) -> Result<usize, ErrorKind>
+    {
+        let meta = self.find_file(path)?.0;
+        let size = meta.file_size();
+        if offset >= size {return Ok(0);}
+        let len = (size - offset).min(buf.len() as u64) as usize;
+        let mut block = [0u8; BLOCK_SIZE];
+        let mut done = 0;
+        while done < len
+        {
+            let pos = offset + done as u64;
+            self.device.seek(SeekFrom::Start((meta.data_start_block + pos / BLOCK_SIZE as u64) * BLOCK_SIZE as u64))?;
+            self.device.read(&mut block)?;
+            let start = (pos % BLOCK_SIZE as u64) as usize;
+            let n = (BLOCK_SIZE - start).min(len - done);
+            buf[done..done + n].copy_from_slice(&block[start..start + n]);
+            done += n;
+        }
+        Ok(len)
+    }
+
+    fn file_size(&mut self, path: &str) -> Result<u64, ErrorKind>
+    {
+        Ok(self.find_file(path)?.0.file_size())
+    }
+
     fn write(&mut self, path: &str, data: &[u8]) -> Result<(), ErrorKind>
     {
         let mut data_vec = data.to_vec();
@@ -204,10 +256,24 @@ impl<T> super::FileSystem<T> for TestFS<T> where T: DeviceStream
         Ok(())
     }
 
-    fn create(&mut self, path: &str) -> Result<(), ErrorKind>
+    //metadata blocks are not reused while the kernel runs, so the block of a file identifies it
+    fn file_id(&mut self, path: &str) -> Result<u64, ErrorKind>
     {
+        Ok(self.find_file(path)?.1 as u64)
+    }
+
+    //the permissions are written with the metadata block, so no other file can get them
+    fn create(&mut self, path: &str, perms: FilePermissions) -> Result<(), ErrorKind>
+    {
+        match self.find_file(path)
+        {
+            Ok(_) => return Err(ErrorKind::AlreadyExists),
+            Err(ErrorKind::NotFound) => {}
+            Err(e) => return Err(e)
+        }
+        if path.len() > NAME_SIZE {return Err(ErrorKind::InvalidInput);}
         if self.free_file_block > 20 {return Err(ErrorKind::AddrNotAvailable);}
-        let buf = MetadataBlock::new(path, self.free_file_block).to_bytes();
+        let buf = MetadataBlock{perms, ..MetadataBlock::new(path, self.free_file_block)}.to_bytes();
         self.device.seek(SeekFrom::Start(self.free_file_block * BLOCK_SIZE as u64))?;
         self.device.write(&buf)?;
         self.free_file_block += 1;
@@ -1,4 +1,5 @@
 use spin::Lazy;
+use core::ptr::{addr_of, addr_of_mut};
 use x86_64::VirtAddr;
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor, SegmentSelector};
@@ -7,37 +8,44 @@ use x86_64::instructions::segmentation::{CS, DS, ES, SS, Segment};
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+const STACK_SIZE: usize = 4096 * 5;
+
 struct Selectors
 {
     code_selector: SegmentSelector,
     data_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
     tss_selector: SegmentSelector
 }
 
-static TSS: Lazy<TaskStateSegment> = Lazy::new(|| 
-{
-    let mut tss = TaskStateSegment::new();
-    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
-    {
-        const STACK_SIZE: usize = 4096 * 5;
-        static STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-        VirtAddr::from_ptr(&STACK) + STACK_SIZE
-    };
-    tss
-});
+//the TSS has to stay mutable after loading so the ring 0 stack can be swapped for each process
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 static GDT: Lazy<(GlobalDescriptorTable, Selectors)> = Lazy::new(||
 {
     let mut gdt = GlobalDescriptorTable::new();
     let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
     let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
-    let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-    (gdt, Selectors{code_selector, data_selector, tss_selector})
+    let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+    let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe{&*addr_of!(TSS)}));
+    (gdt, Selectors{code_selector, data_selector, user_data_selector, user_code_selector, tss_selector})
 });
 
 pub fn init()
 {
+    unsafe
+    {
+        let tss = &mut *addr_of_mut!(TSS);
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+        {
+            static STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+            VirtAddr::from_ptr(&STACK) + STACK_SIZE
+        };
+    }
+
     GDT.0.load();
 
     unsafe
@@ -48,4 +56,16 @@ pub fn init()
         SS::set_reg(GDT.1.data_selector);
         load_tss(GDT.1.tss_selector);
     }
+}
+
+/// Returns the (code, data) segment selectors used for ring 3.
+pub fn user_selectors() -> (SegmentSelector, SegmentSelector)
+{
+    (GDT.1.user_code_selector, GDT.1.user_data_selector)
+}
+
+/// Sets the stack the CPU switches to when an interrupt arrives while ring 3 code is running.
+pub fn set_kernel_stack(top: VirtAddr)
+{
+    unsafe{(*addr_of_mut!(TSS)).privilege_stack_table[0] = top;}
 }
\ No newline at end of file
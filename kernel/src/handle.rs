@@ -0,0 +1,111 @@
+//! Handles, the numbers processes refer to kernel objects by.
+//!
+//! Each process has its own table. Objects are shared between the handles referring to them
+//! and live until the last one is closed, which happens at the latest when the process is freed.
+//!
+//! Handles are capabilities: each carries rights that say what its holder may do with the
+//! object, checked instead of the identities of the process. Copies can only have fewer rights,
+//! and only handles with `RIGHT_TRANSFER` can be passed to children or over endpoints.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use crate::abi_defs::RIGHT_TRANSFER;
+use crate::endpoint::EndpointReceiver;
+use crate::fs::OpenFile;
+use crate::pipe::{PipeReader, PipeWriter};
+
+/// A kernel object a handle refers to.
+///
+/// Dropping the last reference can wake blocked threads, which locks the process queue, so
+/// objects must not be dropped while it is locked.
+#[derive(Clone)]
+pub enum Object
+{
+    PipeReader(Arc<PipeReader>),
+    PipeWriter(Arc<PipeWriter>),
+    Endpoint(Arc<EndpointReceiver>),
+    /// A file, access was checked when the handle was opened. The handle stops working once the
+    /// file is deleted, a file created at the same path later is another one.
+    File(Arc<OpenFile>),
+    /// A process by pid, pids are not reused.
+    Process(u64)
+}
+
+#[derive(Clone)]
+struct Entry
+{
+    object: Object,
+    rights: u32,
+    //copied into the table of children when set
+    inherit: bool
+}
+
+/// The handles of a process.
+pub struct HandleTable
+{
+    entries: BTreeMap<u64, Entry>,
+    next: u64,
+    //most entries the table may hold
+    limit: u64
+}
+
+impl Default for HandleTable
+{
+    fn default() -> Self
+    {
+        HandleTable{entries: BTreeMap::new(), next: 0, limit: u64::MAX}
+    }
+}
+
+impl HandleTable
+{
+    /// Sets how many handles the table may hold, the ones it holds already stay.
+    pub fn set_limit(&mut self, limit: u64)
+    {
+        self.limit = limit;
+    }
+
+    /// Returns true if `count` more handles can be added without exceeding the limit.
+    pub fn has_room(&self, count: u64) -> bool
+    {
+        (self.entries.len() as u64).saturating_add(count) <= self.limit
+    }
+
+    /// Adds an object that children don't inherit and returns its handle, regardless of the limit.
+    pub fn insert(&mut self, object: Object, rights: u32) -> u64
+    {
+        let handle = self.next;
+        self.next += 1;
+        self.entries.insert(handle, Entry{object, rights, inherit: false});
+        handle
+    }
+
+    /// Returns the object a handle refers to and the rights it has on it.
+    pub fn get(&self, handle: u64) -> Option<(&Object, u32)>
+    {
+        self.entries.get(&handle).map(|e| (&e.object, e.rights))
+    }
+
+    /// Removes a handle and returns the object it referred to.
+    pub fn remove(&mut self, handle: u64) -> Option<Object>
+    {
+        self.entries.remove(&handle).map(|e| e.object)
+    }
+
+    /// Sets whether children spawned afterwards get a copy of the handle, returns false if there is no such handle.
+    pub fn set_inherit(&mut self, handle: u64, inherit: bool) -> bool
+    {
+        match self.entries.get_mut(&handle)
+        {
+            Some(entry) => {entry.inherit = inherit; true},
+            None => false
+        }
+    }
+
+    /// Returns the table a child starts with, the inherited handles keep their numbers and rights.
+    pub fn inherited(&self) -> HandleTable
+    {
+        let entries = self.entries.iter().filter(|(_, e)| e.inherit && e.rights & RIGHT_TRANSFER != 0).map(|(&h, e)| (h, e.clone())).collect();
+        HandleTable{entries, next: self.next, limit: self.limit}
+    }
+}
\ No newline at end of file
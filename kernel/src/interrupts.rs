@@ -1,7 +1,9 @@
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 use x86_64::instructions::port::Port;
 use x86_64::registers::control::Cr2;
+use x86_64::PrivilegeLevel;
 use crate::{gdt, hlt_loop};
+use crate::trap::{self, TrapFrame};
 use pic8259::ChainedPics;
 use spin::{Mutex, Lazy};
 use log::error;
@@ -39,9 +41,17 @@ static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(||
 
     unsafe{idt.double_fault.set_handler_fn(double_fault_handler).set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);}
 
-    idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
     idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
-    idt.page_fault.set_handler_fn(page_fault_handler);
+
+    //these can switch to another context, so they go through the stubs in trap.rs
+    unsafe
+    {
+        idt[InterruptIndex::Timer.as_usize()].set_handler_addr(trap::timer_stub());
+        idt.page_fault.set_handler_addr(trap::page_fault_stub());
+        idt.general_protection_fault.set_handler_addr(trap::general_protection_fault_stub());
+        idt[crate::usermode::EXIT_VECTOR as usize].set_handler_addr(trap::exit_stub()).set_privilege_level(PrivilegeLevel::Ring3);
+        idt[crate::usermode::SYSCALL_VECTOR as usize].set_handler_addr(trap::syscall_stub()).set_privilege_level(PrivilegeLevel::Ring3);
+    }
     idt
 });
 
@@ -60,11 +70,11 @@ extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame,
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame)
+pub extern "C" fn timer_handler(frame: *mut TrapFrame) -> *mut TrapFrame
 {
-    x86_64::instructions::interrupts::without_interrupts(|| crate::proc_watch::check());
-
     unsafe{PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer.as_u8());}
+
+    crate::proc_watch::schedule(frame)
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame)
@@ -76,11 +86,46 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     unsafe{PICS.lock().notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());}
 }
 
-extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode)
+/// Entered through the page fault stub, kills the faulting process or halts if the kernel faulted.
+///
+/// # Safety
+///
+/// `frame` has to point to the frame the stub in trap.rs pushed for the interrupted context.
+pub unsafe extern "C" fn page_fault_handler(frame: *mut TrapFrame) -> *mut TrapFrame
 {
+    let stack_frame = unsafe{&*frame};
+    let user = stack_frame.from_user();
+    let addr = Cr2::read();
+    //a process can be preempted while the kernel holds the logger, so let it run while logging
+    if user {x86_64::instructions::interrupts::enable();}
     error!("EXCEPTION: PAGE FAULT");
-    error!("Accessed Address: {:?}", Cr2::read());
-    error!("Error Code: {:?}", error_code);
+    error!("Accessed Address: {:?}", addr);
+    error!("Error Code: {:?}", PageFaultErrorCode::from_bits_truncate(stack_frame.error_code));
+    error!("{:#?}", stack_frame);
+    if user {kill_faulting(frame)}
+    else {hlt_loop()}
+}
+
+/// Entered through the general protection fault stub, kills the faulting process or halts if the kernel faulted.
+///
+/// # Safety
+///
+/// `frame` has to point to the frame the stub in trap.rs pushed for the interrupted context.
+pub unsafe extern "C" fn general_protection_fault_handler(frame: *mut TrapFrame) -> *mut TrapFrame
+{
+    let stack_frame = unsafe{&*frame};
+    let user = stack_frame.from_user();
+    if user {x86_64::instructions::interrupts::enable();}
+    error!("EXCEPTION: GENERAL PROTECTION FAULT");
+    error!("Error Code: {}", stack_frame.error_code);
     error!("{:#?}", stack_frame);
-    hlt_loop();
+    if user {kill_faulting(frame)}
+    else {hlt_loop()}
+}
+
+//a fault in ring 3 only takes down the offending process
+fn kill_faulting(frame: *mut TrapFrame) -> *mut TrapFrame
+{
+    x86_64::instructions::interrupts::disable();
+    crate::proc_watch::exit_current(crate::proc::EXIT_KILLED, frame)
 }
\ No newline at end of file
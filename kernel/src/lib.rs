@@ -2,8 +2,6 @@
 #![cfg_attr(test, no_main)]
 #![feature(abi_x86_interrupt)]
 #![feature(const_mut_refs)]
-#![feature(fn_ptr_trait)]
-#![feature(strict_provenance)]
 
 extern crate alloc;
 
@@ -16,10 +14,21 @@ pub mod task;
 pub mod fs;
 pub mod device;
 pub mod syscall;
+pub mod syscall_abi;
+pub mod abi_defs;
 pub mod time;
 pub mod rand;
+pub mod elf;
+pub mod linker;
+pub mod pipe;
+pub mod endpoint;
+pub mod handle;
+pub mod shm;
+pub mod signal;
 pub mod proc;
 pub mod proc_watch;
+pub mod usermode;
+pub mod trap;
 
 use conquer_once::spin::OnceCell;
 use bootloader_api::info::FrameBufferInfo;
@@ -34,6 +43,7 @@ pub fn init()
 {
     gdt::init();
     interrupts::init_idt();
+    trap::init_fpu();
 
     unsafe{interrupts::PICS.lock().initialize();}
 
@@ -0,0 +1,227 @@
+//! Loading of programs together with the shared libraries they need.
+//!
+//! Libraries are position independent shared objects stored on the filesystem under the name
+//! programs list them by (DT_NEEDED). Each library is read from the filesystem once per boot
+//! and kept in a cache, parsed and with its contents in frames rather than on the kernel heap.
+//! A library is read again once its file is written, or deleted and created anew, and
+//! `clear_cache` drops every one.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::PageTableFlags;
+use crate::elf::{ElfImage, ElfSource, LoadError, Module, TlsTemplate};
+use crate::fs::{FILESYSTEM, FileSystem, OpenFile};
+use crate::proc::Credentials;
+use crate::memory::{AddressSpace, SharedFrames, phys_to_virt};
+use crate::abi_defs::MODE_READ;
+use crate::usermode::{USER_REGION_START, ANON_REGION_START};
+
+/// Most libraries a program can load, counting the libraries they need in turn.
+pub const MAX_LIBRARIES: usize = 64;
+/// Largest library file the loader reads, the cache keeps a list of its frames on the kernel heap.
+pub const MAX_LIBRARY_SIZE: u64 = 2 << 20;
+
+static LIBRARIES: Mutex<BTreeMap<String, Arc<Library>>> = Mutex::new(BTreeMap::new());
+
+//the contents of a library file as they were read, in frames nothing maps
+struct LibraryFile
+{
+    //id of the file at the library's name when it was read
+    id: u64,
+    size: u64,
+    contents: SharedFrames
+}
+
+struct Library
+{
+    file: LibraryFile,
+    image: ElfImage
+}
+
+/// Size of the thread control block the thread pointer points at.
+///
+/// Its first word points to itself, the rest is zeroed room for what compiled code expects at
+/// fixed offsets from the thread pointer, like the stack protector canary at `fs:0x28`.
+pub const TCB_SIZE: u64 = 64;
+
+/// A program linked into an address space.
+pub struct Linked
+{
+    /// How far the program was moved up from the addresses it was linked at.
+    pub bias: u64,
+    pub tls: TlsLayout
+}
+
+/// Where the TLS blocks of a program and its libraries go relative to the thread pointer.
+///
+/// This is the x86_64 variant II layout: the blocks lie right below the thread pointer, the
+/// program's block closest to it, and the thread control block starts at it.
+pub struct TlsLayout
+{
+    /// Bytes from the start of the lowest block up to the thread pointer.
+    size: u64,
+    /// Alignment of the thread pointer.
+    align: u64,
+    templates: Vec<(TlsTemplate, u64, u64)>
+}
+
+impl TlsLayout
+{
+    /// Returns how many bytes the TLS area of a thread takes.
+    pub fn area_size(&self) -> u64
+    {
+        align_up(self.size, 4096) + TCB_SIZE
+    }
+
+    /// Maps the TLS area of a new thread at the page aligned address `start` and returns its thread pointer.
+    ///
+    /// Blocks start as copies of the templates as they are after relocation, copied inside the
+    /// address space so a template of any size needs no kernel memory.
+    pub fn map_area(&self, space: &AddressSpace, start: u64) -> Result<u64, LoadError>
+    {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+        space.map(VirtAddr::new(start), self.area_size(), flags).map_err(|_| LoadError::OutOfMemory)?;
+        //templates are aligned to at most a page, so this stays inside the area
+        let tp = align_up(start + self.size, self.align);
+        for (template, bias, offset) in &self.templates
+        {
+            space.copy(VirtAddr::new(template.vaddr + bias), VirtAddr::new(tp - offset), template.file_size).map_err(|_| LoadError::OutOfMemory)?;
+        }
+        space.write(VirtAddr::new(tp), &tp.to_le_bytes()).map_err(|_| LoadError::OutOfMemory)?;
+        Ok(tp)
+    }
+}
+
+impl ElfSource for OpenFile
+{
+    //the file is read with its id checked every time, a file replaced while loading fails it
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), LoadError>
+    {
+        match FILESYSTEM.lock().read_open_at(self, offset, buf)
+        {
+            Ok(read) if read == buf.len() => Ok(()),
+            Ok(_) => Err(LoadError::SegmentOutOfBounds),
+            Err(_) => Err(LoadError::ReadFailed)
+        }
+    }
+}
+
+impl ElfSource for &LibraryFile
+{
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), LoadError>
+    {
+        let end = offset.checked_add(buf.len() as u64).ok_or(LoadError::SegmentOutOfBounds)?;
+        if end > self.size {return Err(LoadError::SegmentOutOfBounds);}
+        let mut done = 0;
+        while done < buf.len()
+        {
+            let pos = offset + done as u64;
+            let frame = self.contents.frames()[(pos / 4096) as usize];
+            let len = (4096 - (pos % 4096) as usize).min(buf.len() - done);
+            unsafe{phys_to_virt(frame.start_address() + pos % 4096).as_ptr::<u8>().copy_to_nonoverlapping(buf[done..].as_mut_ptr(), len)};
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+/// Maps `image`, read from `source`, and the libraries it needs into `space` and links them.
+pub fn load(space: &AddressSpace, image: &ElfImage, source: &mut impl ElfSource) -> Result<Linked, LoadError>
+{
+    //position independent images are linked at 0, so they get moved to the start of the user region
+    let bias = if image.position_independent {USER_REGION_START} else {0};
+    image.map(source, space, bias)?;
+
+    //every library the image needs directly or indirectly, breadth first, each only once
+    let mut names = Module{image, space, bias, tls_offset: 0}.needed()?;
+    let mut libraries = Vec::new();
+    //libraries follow the program, with an unmapped page between images
+    let mut next = VirtAddr::new(image.bounds().1 + bias).align_up(4096u64).as_u64() + 4096;
+    while libraries.len() < names.len()
+    {
+        if names.len() > MAX_LIBRARIES {return Err(LoadError::TooManyLibraries);}
+        let library = library(&names[libraries.len()])?;
+        let (low, high) = library.image.bounds();
+        let library_bias = next - VirtAddr::new(low).align_down(4096u64).as_u64();
+        let end = high.checked_add(library_bias).ok_or(LoadError::SegmentOutOfBounds)?;
+        if end > ANON_REGION_START {return Err(LoadError::SegmentOutOfBounds);}
+        library.image.map(&mut &library.file, space, library_bias)?;
+        for name in (Module{image: &library.image, space, bias: library_bias, tls_offset: 0}).needed()?
+        {
+            if !names.contains(&name) {names.push(name);}
+        }
+        libraries.push((library, library_bias));
+        next = VirtAddr::new(end).align_up(4096u64).as_u64() + 4096;
+    }
+    let mut scope = Vec::with_capacity(libraries.len() + 1);
+    scope.push(Module{image, space, bias, tls_offset: 0});
+    scope.extend(libraries.iter().map(|(library, bias)| Module{image: &library.image, space, bias: *bias, tls_offset: 0}));
+
+    let mut tls = TlsLayout{size: 0, align: 1, templates: Vec::new()};
+    for module in &mut scope
+    {
+        if let Some(template) = module.image.tls
+        {
+            //every block is at most TLS_MAX_SIZE and there are at most MAX_LIBRARIES + 1, so this can't overflow
+            tls.size = align_up(tls.size + template.mem_size, template.align);
+            tls.align = tls.align.max(template.align);
+            module.tls_offset = tls.size;
+            tls.templates.push((template, module.bias, tls.size));
+        }
+    }
+
+    //libraries are relocated before the images needing them, so copy relocations see relocated data
+    for module in scope.iter().rev()
+    {
+        module.relocate(&scope)?;
+    }
+    Ok(Linked{bias, tls})
+}
+
+//the cache is shared by every process, so libraries have to be readable by everyone
+fn library(name: &str) -> Result<Arc<Library>, LoadError>
+{
+    let mut fs = FILESYSTEM.lock();
+    let file = fs.open_checked(name, MODE_READ, &Credentials::NOBODY).map_err(|_| LoadError::LibraryNotFound)?;
+    if let Some(library) = LIBRARIES.lock().get(name).filter(|library| library.file.id == file.id) {return Ok(library.clone());}
+    let size = fs.file_size(name).map_err(|_| LoadError::ReadFailed)?;
+    if size > MAX_LIBRARY_SIZE {return Err(LoadError::OutOfMemory);}
+    let contents = SharedFrames::new(size.div_ceil(4096) as usize).map_err(|_| LoadError::OutOfMemory)?;
+    for (i, frame) in contents.frames().iter().enumerate()
+    {
+        //nothing maps the frames, so this is the only reference to them
+        let page = unsafe{core::slice::from_raw_parts_mut(phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(), 4096)};
+        let len = (size - i as u64 * 4096).min(4096) as usize;
+        if fs.read_open_at(&file, i as u64 * 4096, &mut page[..len]).map_err(|_| LoadError::ReadFailed)? != len {return Err(LoadError::ReadFailed);}
+    }
+    let file = LibraryFile{id: file.id, size, contents};
+    let image = ElfImage::parse(&mut &file)?;
+    if !image.position_independent {return Err(LoadError::NotSharedObject);}
+    let library = Arc::new(Library{file, image});
+    //writers call `forget` after releasing the filesystem, so holding it until here keeps one
+    //from slipping in between reading the file and caching what was read
+    LIBRARIES.lock().insert(name.into(), library.clone());
+    drop(fs);
+    Ok(library)
+}
+
+fn align_up(value: u64, align: u64) -> u64
+{
+    (value + align - 1) & !(align - 1)
+}
+
+/// Drops the cached library named `name`, called whenever the file at that path is written.
+pub fn forget(name: &str)
+{
+    LIBRARIES.lock().remove(name);
+}
+
+/// Drops every cached library, programs started afterwards load them from the filesystem again.
+pub fn clear_cache()
+{
+    LIBRARIES.lock().clear();
+}
\ No newline at end of file
@@ -41,12 +41,15 @@ fn kernel_start(boot_info: &'static mut BootInfo) -> !
     let mut frame_allocator = unsafe{BootInfoFrameAllocator::init(&boot_info.memory_regions)};
 
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    kernel::usermode::init();
 
     info!("End of Kernel");
 
     let mut executor = Executor::new();
     executor.spawn(Task::new(example_task()));
     executor.spawn(Task::new(kernel::task::keyboard::print_keypresses()));
+    executor.spawn(Task::new(kernel::proc_watch::reap_finished()));
     executor.run();
 }
 
@@ -1,7 +1,30 @@
-use x86_64::structures::paging::{PageTable, OffsetPageTable, PhysFrame, Size4KiB, FrameAllocator};
-use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{PageTable, OffsetPageTable, PhysFrame, Size4KiB, FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, PageTableIndex, Translate};
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::mapper::{MapToError, MappedFrame, TranslateResult};
+use x86_64::registers::control::{Cr3, Cr3Flags};
 use x86_64::{VirtAddr, PhysAddr};
 use bootloader_api::info::{MemoryRegions, MemoryRegionKind};
+use conquer_once::spin::OnceCell;
+use alloc::vec::Vec;
+use spin::Mutex;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub static MAPPER: OnceCell<Mutex<OffsetPageTable<'static>>> = OnceCell::uninit();
+pub static FRAME_ALLOCATOR: OnceCell<Mutex<BootInfoFrameAllocator>> = OnceCell::uninit();
+static PHYS_MEM_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+static KERNEL_LEVEL_4_FRAME: OnceCell<PhysFrame> = OnceCell::uninit();
+
+/// Marks a user page whose frame is not owned by the address space it is mapped in.
+pub const SHARED: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Start of the virtual range kernel stacks of processes are mapped in.
+pub const KERNEL_STACK_REGION: u64 = 0x_5555_0000_0000;
+//each slot holds one stack with unmapped guard pages below it
+const KERNEL_STACK_SLOT: u64 = 4096 * 8;
+const KERNEL_STACK_SIZE: u64 = 4096 * 4;
+
+static NEXT_STACK_SLOT: AtomicU64 = AtomicU64::new(0);
+static FREE_STACK_SLOTS: Mutex<Vec<u64>> = Mutex::new(Vec::new());
 
 #[deny(unsafe_op_in_unsafe_fn)]
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static>
@@ -10,6 +33,424 @@ pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static>
     unsafe{OffsetPageTable::new(level_4_table, physical_memory_offset)}
 }
 
+/// Hands the page table and frame allocator over to the rest of the kernel.
+///
+/// Must be called once the heap is initialized, since freed frames are kept in a `Vec`.
+pub fn init_global(mapper: OffsetPageTable<'static>, frame_allocator: BootInfoFrameAllocator)
+{
+    PHYS_MEM_OFFSET.try_init_once(|| mapper.phys_offset()).expect("memory::init_global should only be called once");
+    KERNEL_LEVEL_4_FRAME.try_init_once(|| Cr3::read().0).expect("memory::init_global should only be called once");
+    MAPPER.try_init_once(|| Mutex::new(mapper)).expect("memory::init_global should only be called once");
+    FRAME_ALLOCATOR.try_init_once(|| Mutex::new(frame_allocator)).expect("memory::init_global should only be called once");
+    //address spaces copy the kernel's level 4 entries when they are created, so the entry
+    //for the kernel stacks has to exist before the first one is
+    reserve_level_4_entry(VirtAddr::new(KERNEL_STACK_REGION));
+}
+
+fn reserve_level_4_entry(addr: VirtAddr)
+{
+    let mut mapper = MAPPER.try_get().expect("memory not initialized").lock();
+    let entry = &mut mapper.level_4_table()[addr.p4_index()];
+    if !entry.is_unused() {return;}
+    let frame = FRAME_ALLOCATOR.try_get().expect("memory not initialized").lock().allocate_frame().expect("failed to allocate page table");
+    unsafe{table_at(frame)}.zero();
+    entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+}
+
+/// Maps `size` bytes starting at `start` to freshly allocated, zeroed frames.
+pub fn map_range(start: VirtAddr, size: u64, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>>
+{
+    let mut mapper = MAPPER.try_get().expect("memory not initialized").lock();
+    let mut frame_allocator = FRAME_ALLOCATOR.try_get().expect("memory not initialized").lock();
+    let start_page: Page = Page::containing_address(start);
+    let end_page: Page = Page::containing_address(start + size - 1u64);
+    for page in Page::range_inclusive(start_page, end_page)
+    {
+        let frame = frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+        unsafe{mapper.map_to(page, frame, flags, &mut *frame_allocator)}?.flush();
+        unsafe{page.start_address().as_mut_ptr::<u8>().write_bytes(0, 4096)};
+    }
+    Ok(())
+}
+
+/// Unmaps `size` bytes starting at `start` and returns the backing frames to the frame allocator.
+///
+/// Pages in the range that are not mapped are skipped.
+pub fn unmap_range(start: VirtAddr, size: u64)
+{
+    let mut mapper = MAPPER.try_get().expect("memory not initialized").lock();
+    let mut frame_allocator = FRAME_ALLOCATOR.try_get().expect("memory not initialized").lock();
+    let start_page: Page = Page::containing_address(start);
+    let end_page: Page = Page::containing_address(start + size - 1u64);
+    for page in Page::range_inclusive(start_page, end_page)
+    {
+        if let Ok((frame, flush)) = mapper.unmap(page)
+        {
+            flush.flush();
+            unsafe{frame_allocator.deallocate_frame(frame)};
+        }
+    }
+}
+
+/// An address that is not mapped, or not with the access it was used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BadAddress;
+
+/// A kernel stack for a single thread, unmapped when dropped.
+pub struct KernelStack
+{
+    slot: u64
+}
+
+impl KernelStack
+{
+    pub fn new() -> Result<Self, MapToError<Size4KiB>>
+    {
+        let slot = FREE_STACK_SLOTS.lock().pop().unwrap_or_else(|| NEXT_STACK_SLOT.fetch_add(1, Ordering::Relaxed));
+        let stack = KernelStack{slot};
+        map_range(stack.top() - KERNEL_STACK_SIZE, KERNEL_STACK_SIZE, PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE)?;
+        Ok(stack)
+    }
+
+    /// Returns the address just past the highest byte of the stack.
+    pub fn top(&self) -> VirtAddr
+    {
+        VirtAddr::new(KERNEL_STACK_REGION + (self.slot + 1) * KERNEL_STACK_SLOT)
+    }
+}
+
+impl Drop for KernelStack
+{
+    fn drop(&mut self)
+    {
+        unmap_range(self.top() - KERNEL_STACK_SIZE, KERNEL_STACK_SIZE);
+        FREE_STACK_SLOTS.lock().push(self.slot);
+    }
+}
+
+/// Zeroed frames outside the kernel heap, which address spaces can map with `map_shared`, freed
+/// when dropped.
+///
+/// Mapped frames must be unmapped from every address space before that, or dropped along with it.
+pub struct SharedFrames
+{
+    frames: Vec<PhysFrame>
+}
+
+impl SharedFrames
+{
+    pub fn new(count: usize) -> Result<Self, MapToError<Size4KiB>>
+    {
+        let mut shared = SharedFrames{frames: Vec::with_capacity(count)};
+        let mut frame_allocator = FRAME_ALLOCATOR.try_get().expect("memory not initialized").lock();
+        for _ in 0..count
+        {
+            //the frames allocated so far are freed by `drop` if this fails
+            let frame = frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+            unsafe{phys_to_virt(frame.start_address()).as_mut_ptr::<u8>().write_bytes(0, 4096)};
+            shared.frames.push(frame);
+        }
+        Ok(shared)
+    }
+
+    pub fn frames(&self) -> &[PhysFrame]
+    {
+        &self.frames
+    }
+}
+
+impl Drop for SharedFrames
+{
+    fn drop(&mut self)
+    {
+        let mut frame_allocator = FRAME_ALLOCATOR.try_get().expect("memory not initialized").lock();
+        for &frame in &self.frames
+        {
+            unsafe{frame_allocator.deallocate_frame(frame)};
+        }
+    }
+}
+
+/// Switches back to the kernel's own page table.
+///
+/// # Safety
+///
+/// Nothing may still rely on mappings of the previously active address space.
+pub unsafe fn activate_kernel()
+{
+    let frame = *KERNEL_LEVEL_4_FRAME.try_get().expect("memory not initialized");
+    unsafe{Cr3::write(frame, Cr3Flags::empty())};
+}
+
+/// Returns the kernel virtual address of the given physical address.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr
+{
+    *PHYS_MEM_OFFSET.try_get().expect("memory not initialized") + addr.as_u64()
+}
+
+/// Returns the flags `addr` is mapped with in the active address space.
+pub fn active_page_flags(addr: VirtAddr) -> Option<PageTableFlags>
+{
+    let offset = *PHYS_MEM_OFFSET.try_get().expect("memory not initialized");
+    let mapper = unsafe{OffsetPageTable::new(table_at(Cr3::read().0), offset)};
+    match mapper.translate(addr)
+    {
+        TranslateResult::Mapped{flags, ..} => Some(flags),
+        _ => None
+    }
+}
+
+//the frame must hold a page table that is not otherwise referenced mutably
+unsafe fn table_at(frame: PhysFrame) -> &'static mut PageTable
+{
+    unsafe{&mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>()}
+}
+
+/// A level 4 page table owned by a single process.
+///
+/// Entries inside `range` are private to the address space, every other entry is
+/// copied from the kernel's page table so the kernel stays mapped while it is active.
+pub struct AddressSpace
+{
+    level_4_frame: PhysFrame,
+    range: (VirtAddr, VirtAddr),
+    //held while the private part of the page table is changed
+    edit: Mutex<()>
+}
+
+impl AddressSpace
+{
+    /// Creates an address space with nothing mapped between `start` and `end`.
+    ///
+    /// Both bounds must be aligned to the 512 GiB covered by a level 4 entry.
+    pub fn new(start: VirtAddr, end: VirtAddr) -> Result<Self, MapToError<Size4KiB>>
+    {
+        let level_4_frame = FRAME_ALLOCATOR.try_get().expect("memory not initialized").lock().allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+        let table = unsafe{table_at(level_4_frame)};
+        let kernel_table = unsafe{table_at(*KERNEL_LEVEL_4_FRAME.try_get().expect("memory not initialized"))};
+        for i in 0..512
+        {
+            if i >= usize::from(start.p4_index()) && i < usize::from(end.p4_index()) {table[i].set_unused();}
+            else {table[i] = kernel_table[i].clone();}
+        }
+        Ok(AddressSpace{level_4_frame, range: (start, end), edit: Mutex::new(())})
+    }
+
+    fn mapper(&self) -> OffsetPageTable<'static>
+    {
+        let offset = *PHYS_MEM_OFFSET.try_get().expect("memory not initialized");
+        unsafe{OffsetPageTable::new(table_at(self.level_4_frame), offset)}
+    }
+
+    /// Returns true if `size` bytes starting at `start` lie inside the private range.
+    pub fn contains(&self, start: VirtAddr, size: u64) -> bool
+    {
+        start >= self.range.0 && start <= self.range.1 && size <= self.range.1 - start
+    }
+
+    /// Maps `size` bytes starting at `start` to freshly allocated, zeroed frames.
+    ///
+    /// Pages that are already mapped keep their frame, and get the more permissive
+    /// combination of their current and the requested flags. Fails without changing anything if
+    /// one of them maps a shared frame, whose permissions a private mapping must not raise.
+    pub fn map(&self, start: VirtAddr, size: u64, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>>
+    {
+        if size == 0 {return Ok(());}
+        let _edit = self.edit.lock();
+        let mut mapper = self.mapper();
+        let mut frame_allocator = FRAME_ALLOCATOR.try_get().expect("memory not initialized").lock();
+        let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        let start_page: Page = Page::containing_address(start);
+        let end_page: Page = Page::containing_address(start + size - 1u64);
+        for page in Page::range_inclusive(start_page, end_page)
+        {
+            if let TranslateResult::Mapped{frame: MappedFrame::Size4KiB(frame), flags, ..} = mapper.translate(page.start_address())
+            {
+                if flags.contains(SHARED) {return Err(MapToError::PageAlreadyMapped(frame));}
+            }
+        }
+        for page in Page::range_inclusive(start_page, end_page)
+        {
+            if let TranslateResult::Mapped{flags: old, ..} = mapper.translate(page.start_address())
+            {
+                //the address space may be active, where the old flags can still be cached
+                if let Ok(flush) = unsafe{mapper.update_flags(page, merge_flags(old, flags))} {flush.flush();}
+                continue;
+            }
+            let frame = frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+            unsafe{phys_to_virt(frame.start_address()).as_mut_ptr::<u8>().write_bytes(0, 4096)};
+            unsafe{mapper.map_to_with_table_flags(page, frame, flags, table_flags, &mut *frame_allocator)}?.ignore();
+        }
+        Ok(())
+    }
+
+    /// Like `map`, but fails without mapping anything if a page in the range is already mapped.
+    pub fn map_unused(&self, start: VirtAddr, size: u64, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>>
+    {
+        self.check_unused(start, size)?;
+        self.map(start, size, flags)
+    }
+
+    /// Fails if a page overlapping the range is mapped.
+    pub fn check_unused(&self, start: VirtAddr, size: u64) -> Result<(), MapToError<Size4KiB>>
+    {
+        if size == 0 {return Ok(());}
+        let _edit = self.edit.lock();
+        let mapper = self.mapper();
+        let start_page: Page = Page::containing_address(start);
+        let end_page: Page = Page::containing_address(start + size - 1u64);
+        for page in Page::range_inclusive(start_page, end_page)
+        {
+            if let Ok(frame) = mapper.translate_page(page) {return Err(MapToError::PageAlreadyMapped(frame));}
+        }
+        Ok(())
+    }
+
+    /// Unmaps every page overlapping the range, frees the frames the address space owns and
+    /// returns how many there were.
+    ///
+    /// Must only be called while this address space is active, or the TLB keeps stale entries.
+    pub fn unmap(&self, start: VirtAddr, size: u64) -> u64
+    {
+        if size == 0 {return 0;}
+        let _edit = self.edit.lock();
+        let mut mapper = self.mapper();
+        let mut frame_allocator = FRAME_ALLOCATOR.try_get().expect("memory not initialized").lock();
+        let start_page: Page = Page::containing_address(start);
+        let end_page: Page = Page::containing_address(start + size - 1u64);
+        let mut freed = 0;
+        for page in Page::range_inclusive(start_page, end_page)
+        {
+            let shared = matches!(mapper.translate(page.start_address()), TranslateResult::Mapped{flags, ..} if flags.contains(SHARED));
+            if let Ok((frame, flush)) = mapper.unmap(page)
+            {
+                flush.flush();
+                if !shared
+                {
+                    unsafe{frame_allocator.deallocate_frame(frame)};
+                    freed += 1;
+                }
+            }
+        }
+        freed
+    }
+
+    /// Maps a frame the address space does not own, it is left alone when the address space is dropped.
+    pub fn map_shared(&self, page: Page, frame: PhysFrame, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>>
+    {
+        let _edit = self.edit.lock();
+        let mut mapper = self.mapper();
+        let mut frame_allocator = FRAME_ALLOCATOR.try_get().expect("memory not initialized").lock();
+        let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        unsafe{mapper.map_to_with_table_flags(page, frame, flags | SHARED, table_flags, &mut *frame_allocator)}?.ignore();
+        Ok(())
+    }
+
+    /// Copies `data` into the address space at `addr`, regardless of page permissions.
+    pub fn write(&self, addr: VirtAddr, data: &[u8]) -> Result<(), BadAddress>
+    {
+        let _edit = self.edit.lock();
+        let mapper = self.mapper();
+        let mut done = 0;
+        while done < data.len()
+        {
+            let cur = addr + done as u64;
+            let phys = mapper.translate_addr(cur).ok_or(BadAddress)?;
+            let len = (4096 - u64::from(cur.page_offset()) as usize).min(data.len() - done);
+            unsafe{phys_to_virt(phys).as_mut_ptr::<u8>().copy_from_nonoverlapping(data[done..].as_ptr(), len)};
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Copies bytes at `addr` out of the address space into `buf`.
+    pub fn read(&self, addr: VirtAddr, buf: &mut [u8]) -> Result<(), BadAddress>
+    {
+        let _edit = self.edit.lock();
+        let mapper = self.mapper();
+        let mut done = 0;
+        while done < buf.len()
+        {
+            let cur = addr + done as u64;
+            let phys = mapper.translate_addr(cur).ok_or(BadAddress)?;
+            let len = (4096 - u64::from(cur.page_offset()) as usize).min(buf.len() - done);
+            unsafe{phys_to_virt(phys).as_ptr::<u8>().copy_to_nonoverlapping(buf[done..].as_mut_ptr(), len)};
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Copies `len` bytes from `src` to `dst` inside the address space, page by page without a
+    /// buffer in between. The ranges must not overlap for `dst` to end up a copy of `src`.
+    pub fn copy(&self, src: VirtAddr, dst: VirtAddr, len: u64) -> Result<(), BadAddress>
+    {
+        let _edit = self.edit.lock();
+        let mapper = self.mapper();
+        let mut done = 0;
+        while done < len
+        {
+            let from = VirtAddr::try_new(src.as_u64().checked_add(done).ok_or(BadAddress)?).map_err(|_| BadAddress)?;
+            let to = VirtAddr::try_new(dst.as_u64().checked_add(done).ok_or(BadAddress)?).map_err(|_| BadAddress)?;
+            let from_phys = mapper.translate_addr(from).ok_or(BadAddress)?;
+            let to_phys = mapper.translate_addr(to).ok_or(BadAddress)?;
+            let len = (4096 - u64::from(from.page_offset())).min(4096 - u64::from(to.page_offset())).min(len - done);
+            unsafe{phys_to_virt(from_phys).as_ptr::<u8>().copy_to(phys_to_virt(to_phys).as_mut_ptr::<u8>(), len as usize)};
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Makes this the active address space.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may rely on mappings of the previously active address space inside the
+    /// private range.
+    pub unsafe fn activate(&self)
+    {
+        unsafe{Cr3::write(self.level_4_frame, Cr3Flags::empty())};
+    }
+}
+
+//the union of both permission sets, a page is executable if either asks for it
+fn merge_flags(old: PageTableFlags, new: PageTableFlags) -> PageTableFlags
+{
+    let mut merged = (old | new) - PageTableFlags::NO_EXECUTE;
+    if old.contains(PageTableFlags::NO_EXECUTE) && new.contains(PageTableFlags::NO_EXECUTE) {merged |= PageTableFlags::NO_EXECUTE;}
+    merged
+}
+
+impl Drop for AddressSpace
+{
+    fn drop(&mut self)
+    {
+        let mut frame_allocator = FRAME_ALLOCATOR.try_get().expect("memory not initialized").lock();
+        let table = unsafe{table_at(self.level_4_frame)};
+        for i in usize::from(self.range.0.p4_index())..usize::from(self.range.1.p4_index())
+        {
+            free_entry(&mut table[PageTableIndex::new(i as u16)], 3, &mut *frame_allocator);
+        }
+        unsafe{frame_allocator.deallocate_frame(self.level_4_frame)};
+    }
+}
+
+//frees the frame behind `entry` and, for page tables, everything it maps
+fn free_entry(entry: &mut PageTableEntry, level: u8, frame_allocator: &mut BootInfoFrameAllocator)
+{
+    if entry.is_unused() {return;}
+    let frame = PhysFrame::containing_address(entry.addr());
+    if level > 0
+    {
+        let table = unsafe{table_at(frame)};
+        for child in table.iter_mut()
+        {
+            free_entry(child, level - 1, frame_allocator);
+        }
+    }
+    if level > 0 || !entry.flags().contains(SHARED) {unsafe{frame_allocator.deallocate_frame(frame)};}
+    entry.set_unused();
+}
+
 /// Returns a mutable reference to the active level 4 table.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -28,7 +469,8 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
 pub struct BootInfoFrameAllocator
 {
     memory_map: &'static MemoryRegions,
-    next: usize
+    next: usize,
+    free: Vec<PhysFrame>
 }
 
 impl BootInfoFrameAllocator
@@ -40,7 +482,7 @@ impl BootInfoFrameAllocator
     /// as `USABLE` in it are really unused.
     pub unsafe fn init(memory_map: &'static MemoryRegions) -> Self
     {
-        BootInfoFrameAllocator{memory_map, next: 0}
+        BootInfoFrameAllocator{memory_map, next: 0, free: Vec::new()}
     }
 
     fn usable_frames(&self) -> impl Iterator<Item = PhysFrame>
@@ -56,8 +498,17 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator
 {
     fn allocate_frame(&mut self) -> Option<PhysFrame>
     {
+        if let Some(frame) = self.free.pop() {return Some(frame);}
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
         frame
     }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator
+{
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame)
+    {
+        self.free.push(frame);
+    }
 }
\ No newline at end of file
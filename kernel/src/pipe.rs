@@ -0,0 +1,134 @@
+//! Pipes, one way byte streams between threads of any processes.
+//!
+//! Reading blocks until there is data and writing until there is room in the buffer. Once the
+//! write end is dropped, reads return what is left and then 0 for the end of the stream, and
+//! once the read end is dropped, writes fail with `ErrorKind::BrokenPipe`.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use embedded_io::ErrorKind;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Bytes a pipe buffers before writers block.
+pub const PIPE_CAPACITY: usize = 4096;
+
+struct PipeState
+{
+    buffer: VecDeque<u8>,
+    reader_open: bool,
+    writer_open: bool
+}
+
+//the state is only locked with interrupts disabled, so a blocked thread can't be holding it
+struct Pipe
+{
+    state: Mutex<PipeState>
+}
+
+impl Pipe
+{
+    //threads blocked on the pipe wait on its address
+    fn key(&self) -> u64
+    {
+        self as *const Pipe as u64
+    }
+
+    //runs `f` on the state until it returns a result, blocking the running thread in between
+    fn wait_for<T>(&self, mut f: impl FnMut(&mut PipeState) -> Option<T>) -> Result<T, ErrorKind>
+    {
+        crate::proc_watch::wait_on(self.key(), || f(&mut self.state.lock()))
+    }
+
+    fn close(&self, f: impl FnOnce(&mut PipeState))
+    {
+        interrupts::without_interrupts(||
+        {
+            f(&mut self.state.lock());
+            crate::proc_watch::wake_blocked(self.key());
+        });
+    }
+}
+
+/// Read end of a pipe, closed when dropped.
+pub struct PipeReader
+{
+    pipe: Arc<Pipe>
+}
+
+/// Write end of a pipe, closed when dropped.
+pub struct PipeWriter
+{
+    pipe: Arc<Pipe>
+}
+
+/// Creates a pipe and returns its read and write end.
+pub fn pipe() -> (PipeReader, PipeWriter)
+{
+    let state = PipeState{buffer: VecDeque::with_capacity(PIPE_CAPACITY), reader_open: true, writer_open: true};
+    let pipe = Arc::new(Pipe{state: Mutex::new(state)});
+    (PipeReader{pipe: pipe.clone()}, PipeWriter{pipe})
+}
+
+impl PipeReader
+{
+    /// Blocks until there is data and reads as much of it as fits into `buf`, returns 0 once
+    /// the write end is closed and everything was read.
+    ///
+    /// Fails with `ErrorKind::Interrupted` if a signal arrives while it blocks.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, ErrorKind>
+    {
+        if buf.is_empty() {return Ok(0);}
+        self.pipe.wait_for(|state|
+        {
+            if state.buffer.is_empty() {return if state.writer_open {None} else {Some(0)};}
+            let n = buf.len().min(state.buffer.len());
+            for (dst, src) in buf.iter_mut().zip(state.buffer.drain(..n)) {*dst = src;}
+            Some(n)
+        })
+    }
+}
+
+impl PipeWriter
+{
+    /// Writes all of `data`, blocking whenever the buffer is full.
+    ///
+    /// Fails with `ErrorKind::Interrupted` if a signal arrives while it blocks, part of `data`
+    /// might have been written then.
+    pub fn write(&self, data: &[u8]) -> Result<(), ErrorKind>
+    {
+        let mut written = 0;
+        while written < data.len()
+        {
+            written += self.pipe.wait_for(|state|
+            {
+                if !state.reader_open {return Some(Err(ErrorKind::BrokenPipe));}
+                let n = (PIPE_CAPACITY - state.buffer.len()).min(data.len() - written);
+                if n == 0 {return None;}
+                state.buffer.extend(&data[written..written + n]);
+                Some(Ok(n))
+            })??;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PipeReader
+{
+    fn drop(&mut self)
+    {
+        self.pipe.close(|state|
+        {
+            state.reader_open = false;
+            state.buffer.clear();
+        });
+    }
+}
+
+impl Drop for PipeWriter
+{
+    fn drop(&mut self)
+    {
+        self.pipe.close(|state| state.writer_open = false);
+    }
+}
\ No newline at end of file
@@ -1,211 +1,560 @@
 use core::sync::atomic::{AtomicU64, Ordering};
-use core::ffi::{c_char, c_uchar, c_schar, c_longlong, c_ulonglong};
-use core::marker::FnPtr;
+use core::ffi::{c_char, c_uchar, c_schar, c_int, c_uint, c_longlong, c_ulonglong};
 use alloc::vec::Vec;
-use alloc::alloc::{GlobalAlloc, Layout};
 use alloc::string::String;
 use alloc::format;
-use crate::allocator::ALLOCATOR;
+use alloc::sync::Arc;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use crate::elf::{ElfImage, ElfSource, LoadError, PROGRAM_HEADER_SIZE};
+use crate::linker::TlsLayout;
 use crate::fs::FilePermissions;
+use crate::endpoint::MessageInfo;
+use crate::handle::HandleTable;
+use crate::shm::SharedMemory;
+use crate::signal::SignalState;
+use crate::memory::{AddressSpace, KernelStack};
+use crate::proc_watch::RUNNING_PROCESS;
+use crate::abi_defs::{ROOT_ID, MODE_SETUID, MODE_SETGID, ABI_VERSION, AT_NULL, AT_PHDR, AT_PHENT, AT_PHNUM, AT_PAGESZ, AT_ENTRY, AT_RANDOM, AT_SYSCALL_TABLE};
+use crate::syscall_abi::{self, SYSCALL_COUNT};
+use crate::trap::{FpuState, TrapFrame};
+use crate::usermode;
 use crate::syscall::*;
-use embedded_io::ErrorKind;
-use xmas_elf::sections::SectionData;
 use iced_x86::{Decoder, DecoderOptions, NasmFormatter, Formatter, Instruction};
 use x86_64::VirtAddr;
+use x86_64::registers::model_specific::FsBase;
+use x86_64::structures::paging::PageTableFlags;
 use log::info;
 
 static NEXT_ID: AtomicU64 = AtomicU64::new(0);
 
+//the stack sits right below the trampoline, with a guard page in between
+const USER_STACK_TOP: u64 = usermode::TRAMPOLINE_ADDR - 4096;
+const USER_STACK_SIZE: u64 = 4096 * 16;
+
+/// Number of priority levels, higher levels are scheduled first.
+pub const PRIORITY_LEVELS: u8 = 8;
+pub const DEFAULT_PRIORITY: u8 = 3;
+
+/// Exit code of processes that were killed or faulted.
+pub const EXIT_KILLED: i32 = -1;
+
 #[derive(PartialEq, Clone)]
 pub enum ProcessStatus
 {
+    Ready,
+    Busy,
+    /// Exited with the given code, the process is freed soon after and its parent can collect the code.
+    Done(i32)
+}
+
+#[derive(PartialEq, Clone)]
+pub enum ThreadStatus
+{
+    Ready,
     Busy,
-    Done(bool)
+    /// Blocked until the timer tick count reaches the given value.
+    Sleeping(u64),
+    /// Blocked until the futex at the given address is woken.
+    Waiting(u64),
+    /// Blocked until the kernel object with the given key changes, see `proc_watch::block_running`.
+    Blocked(u64),
+    /// Ended with the given value, the thread is kept until another thread of the process joins it.
+    Done(u64)
 }
 
 
+/// The syscalls as C functions, each entry points at the user mode stub of the `c_*` function of the same name.
 #[repr(C)]
-pub struct NativeSysCallTable
+pub struct FFISysCallTable
 {
-    file_read_perms: fn(&str) -> Result<FilePermissions, ErrorKind>,
-    file_write_perms: fn(&str, FilePermissions) -> Result<(), ErrorKind>,
-    file_read: fn(&str) -> Result<Vec<u8>, ErrorKind>,
-    file_write: fn(&str, data: &[u8]) -> Result<(), ErrorKind>,
-    file_delete: fn(&str) -> Result<(), ErrorKind>,
-    file_create: fn(&str) -> Result<(), ErrorKind>,
-    time_now: fn() -> i64,
-    rand_buffer: fn(buf: &mut [u8])
+    c_file_read_perms: extern "C" fn(*const c_char, *mut c_uchar, c_ulonglong) -> CVecShort,
+    c_file_write_perms: extern "C" fn(*const c_char, *const c_uchar, c_ulonglong) -> c_schar,
+    c_file_read: extern "C" fn(*const c_char, *mut c_uchar, c_ulonglong) -> CVecShort,
+    c_file_write: extern "C" fn(*const c_char, *const c_uchar, c_ulonglong) -> c_schar,
+    c_file_delete: extern "C" fn(*const c_char) -> c_schar,
+    c_file_create: extern "C" fn(*const c_char) -> c_schar,
+    c_time_now: extern "C" fn() -> c_longlong,
+    c_rand_buffer: extern "C" fn(*mut c_uchar, c_ulonglong) -> c_schar,
+    c_proc_set_priority: extern "C" fn(c_ulonglong, c_uchar) -> c_schar,
+    c_proc_set_time_slice: extern "C" fn(c_ulonglong) -> c_schar,
+    c_proc_stats: extern "C" fn(c_ulonglong, *mut ProcessStats) -> c_schar,
+    c_proc_exit: extern "C" fn(c_int) -> !,
+    c_proc_wait: extern "C" fn(*mut c_ulonglong, *mut c_int) -> c_schar,
+    c_proc_waitpid: extern "C" fn(c_ulonglong, *mut c_int) -> c_schar,
+    c_proc_spawn_path: extern "C" fn(*const c_char, *mut c_ulonglong) -> c_schar,
+    c_proc_kill: extern "C" fn(c_ulonglong) -> c_schar,
+    c_proc_kill_self: extern "C" fn() -> c_schar,
+    c_proc_pid: extern "C" fn() -> c_ulonglong,
+    c_proc_list: extern "C" fn(*mut LegacyProcessInfo, c_ulonglong) -> c_ulonglong,
+    c_mem_map: extern "C" fn(c_ulonglong, c_ulonglong) -> c_ulonglong,
+    c_mem_unmap: extern "C" fn(c_ulonglong, c_ulonglong) -> c_schar,
+    c_abi_supported: extern "C" fn(c_ulonglong) -> bool,
+    c_proc_args: extern "C" fn(*mut c_uchar, c_ulonglong) -> CVecShort,
+    c_proc_env: extern "C" fn(*mut c_uchar, c_ulonglong) -> CVecShort,
+    c_proc_env_var: extern "C" fn(*const c_char, *mut c_uchar, c_ulonglong) -> CVecShort,
+    c_proc_spawn_args: extern "C" fn(*const c_char, *const c_char, c_ulonglong, *const c_char, c_ulonglong, *mut c_ulonglong) -> c_schar,
+    c_thread_create: extern "C" fn(c_ulonglong, c_ulonglong, *mut c_ulonglong) -> c_schar,
+    c_thread_join: extern "C" fn(c_ulonglong, *mut c_ulonglong) -> c_schar,
+    c_thread_exit: extern "C" fn(c_ulonglong) -> !,
+    c_thread_yield: extern "C" fn(),
+    c_thread_id: extern "C" fn() -> c_ulonglong,
+    c_sleep: extern "C" fn(c_ulonglong),
+    c_futex_wait: extern "C" fn(*const c_uint, c_uint) -> c_schar,
+    c_futex_wake: extern "C" fn(*const c_uint, c_ulonglong) -> c_ulonglong,
+    c_pipe_create: extern "C" fn(*mut c_ulonglong, *mut c_ulonglong) -> c_schar,
+    c_pipe_read: extern "C" fn(c_ulonglong, *mut c_uchar, c_ulonglong, *mut c_ulonglong) -> c_schar,
+    c_pipe_write: extern "C" fn(c_ulonglong, *const c_uchar, c_ulonglong) -> c_schar,
+    c_handle_close: extern "C" fn(c_ulonglong) -> c_schar,
+    c_handle_inherit: extern "C" fn(c_ulonglong, bool) -> c_schar,
+    c_endpoint_register: extern "C" fn(*const c_char, *mut c_ulonglong) -> c_schar,
+    c_endpoint_send: extern "C" fn(*const c_char, *const c_uchar, c_ulonglong) -> c_schar,
+    c_endpoint_send_pid: extern "C" fn(c_ulonglong, *const c_uchar, c_ulonglong) -> c_schar,
+    c_endpoint_receive: extern "C" fn(c_ulonglong, *mut c_uchar, c_ulonglong, *mut c_ulonglong, bool) -> CVecShort,
+    c_shm_create: extern "C" fn(*const c_char, c_ulonglong, bool, bool, *mut c_ulonglong) -> c_schar,
+    c_shm_map: extern "C" fn(*const c_char, bool, bool, *mut c_ulonglong, *mut c_ulonglong) -> c_schar,
+    c_shm_unmap: extern "C" fn(c_ulonglong) -> c_schar,
+    c_proc_signal: extern "C" fn(c_ulonglong, c_uchar) -> c_schar,
+    c_signal_action: extern "C" fn(c_uchar, c_ulonglong, *mut c_ulonglong) -> c_schar,
+    c_signal_return: extern "C" fn() -> !,
+    c_proc_limits: extern "C" fn(c_ulonglong, *mut ResourceLimits) -> c_schar,
+    c_proc_set_child_limits: extern "C" fn(*const ResourceLimits) -> c_schar,
+    c_proc_ids: extern "C" fn(c_ulonglong, *mut Credentials) -> c_schar,
+    c_proc_set_ids: extern "C" fn(c_uint, c_uint) -> c_schar,
+    c_handle_duplicate: extern "C" fn(c_ulonglong, c_uint, *mut c_ulonglong) -> c_schar,
+    c_handle_rights: extern "C" fn(c_ulonglong, *mut c_uint) -> c_schar,
+    c_file_open: extern "C" fn(*const c_char, c_uint, *mut c_ulonglong) -> c_schar,
+    c_file_handle_read: extern "C" fn(c_ulonglong, *mut c_uchar, c_ulonglong) -> CVecShort,
+    c_file_handle_write: extern "C" fn(c_ulonglong, *const c_uchar, c_ulonglong) -> c_schar,
+    c_proc_open: extern "C" fn(c_ulonglong, c_uint, *mut c_ulonglong) -> c_schar,
+    c_proc_handle_signal: extern "C" fn(c_ulonglong, c_uchar) -> c_schar,
+    c_proc_handle_kill: extern "C" fn(c_ulonglong) -> c_schar,
+    c_endpoint_send_handle: extern "C" fn(*const c_char, *const c_uchar, c_ulonglong, c_ulonglong) -> c_schar,
+    c_endpoint_receive_handle: extern "C" fn(c_ulonglong, *mut c_uchar, c_ulonglong, *mut MessageInfo, bool) -> CVecShort,
+    c_file_read_mode: extern "C" fn(*const c_char, *mut c_uchar, c_ulonglong) -> CVecShort,
+    c_file_write_mode: extern "C" fn(*const c_char, *const c_uchar, c_ulonglong) -> c_schar,
+    c_proc_list_ids: extern "C" fn(*mut ProcessInfo, c_ulonglong) -> c_ulonglong,
+    c_shm_create_mode: extern "C" fn(*const c_char, c_ulonglong, c_uint, bool, bool, *mut c_ulonglong) -> c_schar
 }
 
-impl NativeSysCallTable
+impl FFISysCallTable
 {
-    pub const fn gen() -> Self
+    pub fn gen() -> Self
     {
-        NativeSysCallTable{file_read_perms, file_write_perms, file_read, file_write, file_delete, file_create, time_now, rand_buffer}
+        FFISysCallTable{c_file_read_perms: stub(syscall_abi::FILE_READ_PERMS), c_file_write_perms: stub(syscall_abi::FILE_WRITE_PERMS), c_file_read: stub(syscall_abi::FILE_READ), c_file_write: stub(syscall_abi::FILE_WRITE), c_file_delete: stub(syscall_abi::FILE_DELETE), c_file_create: stub(syscall_abi::FILE_CREATE), c_time_now: stub(syscall_abi::TIME_NOW), c_rand_buffer: stub(syscall_abi::RAND_BUFFER), c_proc_set_priority: stub(syscall_abi::PROC_SET_PRIORITY), c_proc_set_time_slice: stub(syscall_abi::PROC_SET_TIME_SLICE), c_proc_stats: stub(syscall_abi::PROC_STATS), c_proc_exit: stub(syscall_abi::PROC_EXIT), c_proc_wait: stub(syscall_abi::PROC_WAIT), c_proc_waitpid: stub(syscall_abi::PROC_WAITPID), c_proc_spawn_path: stub(syscall_abi::PROC_SPAWN_PATH), c_proc_kill: stub(syscall_abi::PROC_KILL), c_proc_kill_self: stub(syscall_abi::PROC_KILL_SELF), c_proc_pid: stub(syscall_abi::PROC_PID), c_proc_list: stub(syscall_abi::PROC_LIST), c_mem_map: stub(syscall_abi::MEM_MAP), c_mem_unmap: stub(syscall_abi::MEM_UNMAP), c_abi_supported: stub(syscall_abi::ABI_SUPPORTED), c_proc_args: stub(syscall_abi::PROC_ARGS), c_proc_env: stub(syscall_abi::PROC_ENV), c_proc_env_var: stub(syscall_abi::PROC_ENV_VAR), c_proc_spawn_args: stub(syscall_abi::PROC_SPAWN_ARGS), c_thread_create: stub(syscall_abi::THREAD_CREATE), c_thread_join: stub(syscall_abi::THREAD_JOIN), c_thread_exit: stub(syscall_abi::THREAD_EXIT), c_thread_yield: stub(syscall_abi::THREAD_YIELD), c_thread_id: stub(syscall_abi::THREAD_ID), c_sleep: stub(syscall_abi::SLEEP), c_futex_wait: stub(syscall_abi::FUTEX_WAIT), c_futex_wake: stub(syscall_abi::FUTEX_WAKE), c_pipe_create: stub(syscall_abi::PIPE_CREATE), c_pipe_read: stub(syscall_abi::PIPE_READ), c_pipe_write: stub(syscall_abi::PIPE_WRITE), c_handle_close: stub(syscall_abi::HANDLE_CLOSE), c_handle_inherit: stub(syscall_abi::HANDLE_INHERIT), c_endpoint_register: stub(syscall_abi::ENDPOINT_REGISTER), c_endpoint_send: stub(syscall_abi::ENDPOINT_SEND), c_endpoint_send_pid: stub(syscall_abi::ENDPOINT_SEND_PID), c_endpoint_receive: stub(syscall_abi::ENDPOINT_RECEIVE), c_shm_create: stub(syscall_abi::SHM_CREATE), c_shm_map: stub(syscall_abi::SHM_MAP), c_shm_unmap: stub(syscall_abi::SHM_UNMAP), c_proc_signal: stub(syscall_abi::PROC_SIGNAL), c_signal_action: stub(syscall_abi::SIGNAL_ACTION), c_signal_return: stub(syscall_abi::SIGNAL_RETURN), c_proc_limits: stub(syscall_abi::PROC_LIMITS), c_proc_set_child_limits: stub(syscall_abi::PROC_SET_CHILD_LIMITS), c_proc_ids: stub(syscall_abi::PROC_IDS), c_proc_set_ids: stub(syscall_abi::PROC_SET_IDS), c_handle_duplicate: stub(syscall_abi::HANDLE_DUPLICATE), c_handle_rights: stub(syscall_abi::HANDLE_RIGHTS), c_file_open: stub(syscall_abi::FILE_OPEN), c_file_handle_read: stub(syscall_abi::FILE_HANDLE_READ), c_file_handle_write: stub(syscall_abi::FILE_HANDLE_WRITE), c_proc_open: stub(syscall_abi::PROC_OPEN), c_proc_handle_signal: stub(syscall_abi::PROC_HANDLE_SIGNAL), c_proc_handle_kill: stub(syscall_abi::PROC_HANDLE_KILL), c_endpoint_send_handle: stub(syscall_abi::ENDPOINT_SEND_HANDLE), c_endpoint_receive_handle: stub(syscall_abi::ENDPOINT_RECEIVE_HANDLE), c_file_read_mode: stub(syscall_abi::FILE_READ_MODE), c_file_write_mode: stub(syscall_abi::FILE_WRITE_MODE), c_proc_list_ids: stub(syscall_abi::PROC_LIST_IDS), c_shm_create_mode: stub(syscall_abi::SHM_CREATE_MODE)}
     }
+}
+
+//reinterprets the stub address of a syscall as a function pointer of the matching signature
+fn stub<F>(nr: u64) -> F
+{
+    assert_eq!(core::mem::size_of::<F>(), 8);
+    unsafe{core::mem::transmute_copy(&usermode::syscall_stub_addr(nr))}
+}
+
+/// What entry points get a pointer to.
+///
+/// Entry points have the signature `extern "C" fn(*const SysCallTable, argc: usize,
+/// argv: *const *const c_char, envp: *const *const c_char, auxv: *const [u64; 2]) -> bool`,
+/// the arrays are the ones of the start block on the stack.
+///
+/// Syscall numbers are never reused and new syscalls are only appended, so a program built
+/// against an older table keeps working, and can check `entry_count` or `abi_supported`
+/// before using syscalls that were added later.
+#[repr(C)]
+pub struct SysCallTable
+{
+    pub abi_version: u32,
+    /// Number of valid entries in `entries`.
+    pub entry_count: u32,
+    pub entries: FFISysCallTable
+}
 
-    pub fn to_byte_slice(&self) -> [u8; 64]
+impl SysCallTable
+{
+    pub fn gen() -> Self
     {
-        let mut res = [0u8; 64];
-        let mut addr = self.file_read_perms.addr().addr();
-        let mut arr = addr.to_le_bytes();
-        for i in 0..8
-        {
-            res[i] = arr[i];
-        }
-        addr = self.file_write_perms.addr().expose_addr();
-        arr = addr.to_le_bytes();
-        for i in 0..8
-        {
-            res[i + 8] = arr[i];
-        }
-        addr = self.file_read.addr().expose_addr();
-        arr = addr.to_le_bytes();
-        for i in 0..8
-        {
-            res[i + 16] = arr[i];
-        }
-        addr = self.file_write.addr().expose_addr();
-        arr = addr.to_le_bytes();
-        for i in 0..8
-        {
-            res[i + 24] = arr[i];
-        }
-        addr = self.file_delete.addr().expose_addr();
-        arr = addr.to_le_bytes();
-        for i in 0..8
-        {
-            res[i + 32] = arr[i];
-        }
-        addr = self.file_create.addr().expose_addr();
-        arr = addr.to_le_bytes();
-        for i in 0..8
-        {
-            res[i + 40] = arr[i];
-        }
-        addr = self.time_now.addr().expose_addr();
-        arr = addr.to_le_bytes();
-        for i in 0..8
-        {
-            res[i + 48] = arr[i];
-        }
-        addr = self.rand_buffer.addr().expose_addr();
-        arr = addr.to_le_bytes();
-        for i in 0..8
-        {
-            res[i + 56] = arr[i];
-        }
-        res
+        SysCallTable{abi_version: ABI_VERSION, entry_count: SYSCALL_COUNT as u32, entries: FFISysCallTable::gen()}
     }
 }
 
+/// Limits on what a process may use, set when it is spawned. `u64::MAX` means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
-pub struct FFISysCallTable
+pub struct ResourceLimits
 {
-    c_file_read_perms: extern "C" fn(*const c_char) -> CVecShort,
-    c_file_write_perms: extern "C" fn(*const c_char, *const c_uchar, c_ulonglong) -> c_schar,
-    c_file_read: extern "C" fn(*const c_char) -> CVecShort,
-    c_file_write: extern "C" fn(*const c_char, *const c_uchar, c_ulonglong) -> c_schar,
-    c_file_delete: extern "C" fn(*const c_char) -> c_schar,
-    c_file_create: extern "C" fn(*const c_char) -> c_schar,
-    c_time_now: extern "C" fn() -> c_longlong,
-    c_rand_buffer: extern "C" fn(*mut c_uchar, c_ulonglong)
+    /// Pages of memory mapped at runtime, including thread stacks and shared memory.
+    pub pages: u64,
+    /// Handles held at the same time.
+    pub handles: u64,
+    /// Timer ticks the process may spend running before it is ended with `SIGXCPU`.
+    pub cpu_ticks: u64,
+    /// Children that were not collected with a wait yet.
+    pub children: u64
 }
 
-impl FFISysCallTable
+unsafe impl usermode::UserValue for ResourceLimits {}
+
+impl ResourceLimits
 {
-    pub const fn gen() -> Self
+    pub const UNLIMITED: ResourceLimits = ResourceLimits{pages: u64::MAX, handles: u64::MAX, cpu_ticks: u64::MAX, children: u64::MAX};
+
+    /// Returns the stricter of both limits for every resource.
+    pub fn min(self, other: ResourceLimits) -> ResourceLimits
     {
-        FFISysCallTable{c_file_read_perms, c_file_write_perms, c_file_read, c_file_write, c_file_delete, c_file_create, c_time_now, c_rand_buffer}
+        ResourceLimits{pages: self.pages.min(other.pages), handles: self.handles.min(other.handles), cpu_ticks: self.cpu_ticks.min(other.cpu_ticks), children: self.children.min(other.children)}
     }
 }
 
-#[allow(improper_ctypes_definitions)] //C apps are not supposed to use NativeSysCallTable
-type StartFunc = extern "C" fn ([u8; 64], FFISysCallTable) -> bool;
+impl Default for ResourceLimits
+{
+    fn default() -> Self
+    {
+        ResourceLimits::UNLIMITED
+    }
+}
 
-#[derive(Clone)]
-pub struct Process
+/// User and group id nothing belongs to.
+pub const NOBODY_ID: u32 = 65534;
+
+/// User and group identities of a process.
+///
+/// Permission checks use the effective ids. The real ids belong to whoever started the process,
+/// they only differ from the effective ones while it runs a setuid or setgid program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Credentials
+{
+    pub uid: u32,
+    pub gid: u32,
+    pub euid: u32,
+    pub egid: u32
+}
+
+unsafe impl usermode::UserValue for Credentials {}
+
+impl Credentials
+{
+    pub const ROOT: Credentials = Credentials::new(ROOT_ID, ROOT_ID);
+    /// Identities that only get the permissions files give everyone.
+    pub const NOBODY: Credentials = Credentials::new(NOBODY_ID, NOBODY_ID);
+
+    /// Returns credentials with the same real and effective ids.
+    pub const fn new(uid: u32, gid: u32) -> Self
+    {
+        Credentials{uid, gid, euid: uid, egid: gid}
+    }
+
+    pub fn is_root(&self) -> bool
+    {
+        self.euid == ROOT_ID
+    }
+
+    /// Returns the credentials a program loaded from a file with `perms` runs with, the setuid
+    /// and setgid bits make the file's owner and group the effective ids.
+    pub fn exec(self, perms: &FilePermissions) -> Credentials
+    {
+        let euid = if perms.mode & MODE_SETUID != 0 {perms.owner} else {self.euid};
+        let egid = if perms.mode & MODE_SETGID != 0 {perms.group} else {self.egid};
+        Credentials{euid, egid, ..self}
+    }
+
+    /// Returns whether a process with these credentials may switch to the given user and group.
+    ///
+    /// Root can become anyone, everyone else can only pick from their real and effective ids.
+    pub fn may_become(&self, uid: u32, gid: u32) -> bool
+    {
+        self.is_root() || ((uid == self.uid || uid == self.euid) && (gid == self.gid || gid == self.egid))
+    }
+}
+
+/// Scheduling accounting of a process.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct ProcessStats
+{
+    /// Timer ticks spent running.
+    pub ticks: u64,
+    /// Times the scheduler switched to the process.
+    pub scheduled: u64,
+    pub priority: u8,
+    _padding: [u8; 7]
+}
+
+unsafe impl usermode::UserValue for ProcessStats {}
+
+/// Entry of the process list handed to programs.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct ProcessInfo
 {
-    //code: Vec<u8>,
-    mem_ptr: VirtAddr,
-    layout: Layout,
-    pub start: StartFunc,
     pub pid: u64,
+    pub parent: u64,
+    /// Effective user id.
+    pub uid: u32,
+    /// Effective group id.
+    pub gid: u32
+}
+
+unsafe impl usermode::UserValue for ProcessInfo {}
+
+/// Entry of the process list from before processes had user and group ids.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct LegacyProcessInfo
+{
+    pub pid: u64,
+    pub parent: u64,
+    /// Set if the process runs as root.
     pub privileged: bool,
-    pub status: ProcessStatus,
-    pub retries: u64
+    _padding: [u8; 7]
 }
 
-impl Process
+unsafe impl usermode::UserValue for LegacyProcessInfo {}
+
+impl From<ProcessInfo> for LegacyProcessInfo
 {
-    pub fn spawn(data: &[u8], log: bool) -> Self
+    fn from(info: ProcessInfo) -> Self
     {
-        let elf = xmas_elf::ElfFile::new(data).expect("invalid ELF code");
-        let mut min = u64::MAX;
-        let mut max = 0u64;
-        for header in elf.program_iter()
+        LegacyProcessInfo{pid: info.pid, parent: info.parent, privileged: info.uid == ROOT_ID, _padding: [0; 7]}
+    }
+}
+
+/// Largest combined size of the arguments and environment of a process.
+pub const MAX_START_DATA: usize = 16 * 1024;
+
+/// Returns how much of `MAX_START_DATA` the arguments and environment take.
+pub fn start_data_size(args: &[String], env: &BTreeMap<String, String>) -> usize
+{
+    //each string takes a pointer and a nul terminator, variables also the `=`
+    let args: usize = args.iter().map(|a| a.len() + 9).sum();
+    let env: usize = env.iter().map(|(k, v)| k.len() + v.len() + 10).sum();
+    args + env
+}
+
+//the System V style block entry points find above their return address: argc, argv and envp
+//ended by null pointers and the auxiliary vector ended by AT_NULL, followed by the syscall
+//table, the AT_RANDOM bytes and the strings
+struct StartBlock
+{
+    data: Vec<u8>,
+    start: u64,
+    table: u64,
+    argv: u64,
+    envp: u64,
+    auxv: u64
+}
+
+impl StartBlock
+{
+    //lays the block out to end at most at `top`, `aux` gets the table and random bytes appended
+    fn new(top: u64, args: &[String], env: &BTreeMap<String, String>, aux: &[(u64, u64)]) -> Self
+    {
+        let env: Vec<String> = env.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        let strings: usize = args.iter().chain(env.iter()).map(|s| s.len() + 1).sum();
+        let word_count = 1 + (args.len() + 1) + (env.len() + 1) + 2 * (aux.len() + 3);
+        let table_size = core::mem::size_of::<SysCallTable>();
+        let size = word_count * 8 + table_size + 16 + strings;
+        let start = (top - size as u64) & !15;
+        let argv = start + 8;
+        let envp = argv + (args.len() as u64 + 1) * 8;
+        let auxv = envp + (env.len() as u64 + 1) * 8;
+        let table = start + word_count as u64 * 8;
+        let random = table + table_size as u64;
+
+        let mut words = Vec::with_capacity(word_count);
+        let mut string = random + 16;
+        words.push(args.len() as u64);
+        for list in [args, &env[..]]
         {
-            let size = header.mem_size();
-            if size > 0
+            for s in list
             {
-                let addr = header.virtual_addr();
-                min = min.min(addr);
-                max = max.max(addr + size);
+                words.push(string);
+                string += s.len() as u64 + 1;
             }
+            words.push(0);
         }
-        let cap = max as usize;
-        let layout = unsafe{Layout::from_size_align_unchecked(cap, 4096)};
-        let ptr = unsafe{ALLOCATOR.alloc(layout)};
-        let mut data_vec = unsafe{Vec::from_raw_parts(ptr, 0, cap)};
-        let ptr = VirtAddr::from_ptr(ptr);
-        for _ in min..max
+        for &(kind, value) in aux.iter().chain(&[(AT_SYSCALL_TABLE, table), (AT_RANDOM, random), (AT_NULL, 0)])
         {
-            data_vec.push(0);
+            words.extend_from_slice(&[kind, value]);
         }
-        for header in elf.program_iter()
+
+        let mut data: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let syscall_table = SysCallTable::gen();
+        data.extend_from_slice(unsafe{core::slice::from_raw_parts(&syscall_table as *const SysCallTable as *const u8, table_size)});
+        let mut random_bytes = [0u8; 16];
+        rand_buffer(&mut random_bytes);
+        data.extend_from_slice(&random_bytes);
+        for s in args.iter().chain(env.iter())
         {
-            if let Ok(xmas_elf::program::Type::Load) = header.get_type()
-            {
-                let mut b = 0;
-                let t = (header.virtual_addr() - min) as usize;
-                let os = header.offset() as usize;
-                for e in os..os + header.file_size() as usize
-                {
-                    data_vec[b + t] = data[e];
-                    b += 1;
-                }
-                if let Some(rela) = elf.find_section_by_name(".rela.dyn")
-                {
-                    if let Ok(SectionData::Rela64(arr)) = rela.get_data(&elf)
-                    {
-                        for r in &arr[0..]
-                        {
-                            let o = r.get_offset();
-                            let a = r.get_addend();
-                            //let t = r.get_type();
-                            if o >= header.virtual_addr() && o < header.virtual_addr() + header.mem_size()
-                            {
-                                let go = data_vec.as_ptr() as u64;
-                                let p64 = unsafe{data_vec.as_ptr().offset(o as isize)} as *mut u64;
-                                unsafe{p64.write(a + go)};
-                            }
-                        }
-                    }
-                }
-            }
+            data.extend_from_slice(s.as_bytes());
+            data.push(0);
         }
-        let funcd = unsafe{data_vec.as_mut_ptr().offset((elf.header.pt2.entry_point() - min) as isize)};
-        let func: StartFunc = unsafe{core::intrinsics::transmute(funcd)};
+        StartBlock{data, start, table, argv, envp, auxv}
+    }
+}
+
+/// Stack size of threads started at runtime, the first thread uses the process stack.
+pub const THREAD_STACK_SIZE: u64 = USER_STACK_SIZE;
+
+/// Returns how much runtime memory a thread started at runtime takes.
+pub fn thread_region_size(tls: &TlsLayout) -> u64
+{
+    //the stack has an unmapped guard page below it and the TLS area above it
+    4096 + THREAD_STACK_SIZE + VirtAddr::new(tls.area_size()).align_up(4096u64).as_u64()
+}
+
+/// A thread of a process, it shares the address space of the process but has its own stacks and TLS area.
+pub struct Thread
+{
+    /// Number of the thread within its process, the first thread is 0.
+    pub tid: u64,
+    pub status: ThreadStatus,
+    kernel_stack: KernelStack,
+    //address of the saved TrapFrame to resume, on the kernel stack
+    context: u64,
+    //thread pointer, loaded into the FS base register whenever the thread runs
+    fs_base: u64,
+    //SSE and x87 registers while the thread isn't running, boxed to keep threads small
+    fpu: Box<FpuState>,
+    //runtime memory holding the user stack and TLS area of threads started at runtime
+    region: Option<(u64, u64)>
+}
+
+impl Thread
+{
+    /// Sets up a thread that calls `entry(arg)` on its own stack and TLS area, which are mapped
+    /// at `start`, the start of `thread_region_size` bytes of reserved runtime memory.
+    ///
+    /// Returning from `entry` ends the thread with the returned value. The thread gets its id
+    /// once it is added to a process.
+    pub fn new(space: &AddressSpace, tls: &TlsLayout, start: u64, entry: u64, arg: u64) -> Result<Self, LoadError>
+    {
+        let stack_top = start + 4096 + THREAD_STACK_SIZE;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+        space.map(VirtAddr::new(start + 4096), THREAD_STACK_SIZE, flags).map_err(|_| LoadError::OutOfMemory)?;
+        let fs_base = tls.map_area(space, stack_top)?;
+        let user_rsp = usermode::prepare_stack(space, VirtAddr::new(stack_top), &[], usermode::THREAD_RETURN_ADDR).map_err(|_| LoadError::OutOfMemory)?;
+        let frame = usermode::initial_frame(VirtAddr::new(entry), user_rsp, &[arg]);
+        Self::with_frame(frame, fs_base, Some((start, thread_region_size(tls))))
+    }
+
+    //the first switch to the thread pops `frame` off its kernel stack
+    fn with_frame(frame: TrapFrame, fs_base: u64, region: Option<(u64, u64)>) -> Result<Self, LoadError>
+    {
+        let kernel_stack = KernelStack::new().map_err(|_| LoadError::OutOfMemory)?;
+        let context = kernel_stack.top() - core::mem::size_of::<TrapFrame>() as u64;
+        unsafe{context.as_mut_ptr::<TrapFrame>().write(frame)};
+        Ok(Thread{tid: 0, status: ThreadStatus::Ready, kernel_stack, context: context.as_u64(), fs_base, fpu: Box::new(FpuState::new()), region})
+    }
+
+    /// Returns the size of the runtime memory holding the stack and TLS area of the thread.
+    pub(crate) fn region_size(&self) -> u64
+    {
+        self.region.map_or(0, |(_, size)| size)
+    }
+
+    //true if the thread was interrupted inside a syscall, where it might hold kernel locks
+    fn in_kernel(&self) -> bool
+    {
+        !unsafe{(*(self.context as *const TrapFrame)).from_user()}
+    }
+
+    /// Unmaps the stack and TLS area of a thread that is not running anymore and returns the value it ended with.
+    pub(crate) fn release(self, space: &AddressSpace) -> u64
+    {
+        if let Some((start, size)) = self.region {space.unmap(VirtAddr::new(start), size);}
+        match self.status
+        {
+            ThreadStatus::Done(value) => value,
+            _ => 0
+        }
+    }
+}
+
+pub struct Process
+{
+    space: Arc<AddressSpace>,
+    pub(crate) threads: Vec<Thread>,
+    //thread that is running or ran last
+    current: u64,
+    next_tid: u64,
+    pub(crate) tls: Arc<TlsLayout>,
+    pub pid: u64,
+    /// Process that spawned this one, `KERNEL_PID` for processes started by the kernel.
+    pub parent: u64,
+    pub cred: Credentials,
+    pub status: ProcessStatus,
+    pub priority: u8,
+    pub ticks: u64,
+    pub scheduled: u64,
+    //ticks spent ready since the process last ran, raises its effective priority
+    pub(crate) waited: u64,
+    //ticks used of the current time slice
+    pub(crate) slice_used: u64,
+    //where the next runtime memory request without an address hint is placed
+    pub(crate) anon_next: u64,
+    pub(crate) handles: HandleTable,
+    //shared memory mapped into the process, by the address it starts at
+    pub(crate) shared: BTreeMap<u64, Arc<SharedMemory>>,
+    pub(crate) signals: SignalState,
+    pub(crate) limits: ResourceLimits,
+    //what children spawned from now on get as their limits
+    pub(crate) child_limits: ResourceLimits,
+    //pages of runtime memory counted against `limits.pages`
+    pub(crate) mapped_pages: u64,
+    //ranges `mem_map` mapped and `mem_unmap` may unmap again, the end of each by its start
+    pub(crate) mapped: BTreeMap<u64, u64>,
+    //exit code of a kill that takes effect once no thread is inside the kernel
+    pub(crate) killed: Option<i32>,
+    pub(crate) args: Vec<String>,
+    pub(crate) env: BTreeMap<String, String>
+}
+
+impl Process
+{
+    /// Loads the ELF image in `source` into a new address space, the process starts running once
+    /// it is queued.
+    ///
+    /// The arguments and environment have to fit in `MAX_START_DATA`. With `log` set the code at
+    /// the entry point is disassembled into the kernel log.
+    pub fn spawn(source: &mut impl ElfSource, args: Vec<String>, env: BTreeMap<String, String>, log: bool) -> Result<Self, LoadError>
+    {
+        let image = ElfImage::parse(source)?;
+        let space = usermode::new_address_space().map_err(|_| LoadError::OutOfMemory)?;
+        let linked = crate::linker::load(&space, &image, source)?;
+        let bias = linked.bias;
+        //the first TLS area goes at the start of the runtime memory range
+        let fs_base = linked.tls.map_area(&space, usermode::ANON_REGION_START)?;
+        let anon_next = VirtAddr::new(usermode::ANON_REGION_START + linked.tls.area_size()).align_up(4096u64).as_u64();
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+        space.map(VirtAddr::new(USER_STACK_TOP - USER_STACK_SIZE), USER_STACK_SIZE, flags).map_err(|_| LoadError::OutOfMemory)?;
+        let entry = VirtAddr::new(image.entry + bias);
+
+        let mut aux = vec![(AT_PAGESZ, 4096), (AT_ENTRY, entry.as_u64())];
+        if let Some((addr, count)) = image.program_headers()
+        {
+            aux.extend_from_slice(&[(AT_PHDR, addr + bias), (AT_PHENT, PROGRAM_HEADER_SIZE), (AT_PHNUM, count)]);
+        }
+        let block = StartBlock::new(USER_STACK_TOP, &args, &env, &aux);
+        let user_rsp = usermode::prepare_stack(&space, VirtAddr::new(block.start + block.data.len() as u64), &block.data, usermode::TRAMPOLINE_ADDR).map_err(|_| LoadError::OutOfMemory)?;
+        let entry_args = [block.table, args.len() as u64, block.argv, block.envp, block.auxv];
+        let main_thread = Thread::with_frame(usermode::initial_frame(entry, user_rsp, &entry_args), fs_base, None)?;
 
         if log
         {
-            let entry = elf.header.pt2.entry_point() - min;
             let mut code = Vec::new();
-            for i in entry.try_into().unwrap()..data_vec.len()
+            for segment in &image.segments
             {
-                code.push(data_vec[i]);
+                if image.entry >= segment.vaddr && image.entry < segment.vaddr + segment.file_size
+                {
+                    //only up to the end of the page, the image is read back from where it was mapped
+                    code.resize((segment.vaddr + segment.file_size - image.entry).min(4096 - u64::from(entry.page_offset())) as usize, 0);
+                    space.read(entry, &mut code).map_err(|_| LoadError::OutOfMemory)?;
+                }
             }
-            let hcbl = 8;
+            let entry = entry.as_u64();
             let mut decoder = Decoder::with_ip(64, &code, entry, DecoderOptions::NONE);
             let mut formatter = NasmFormatter::new();
             formatter.options_mut().set_digit_separator("_");
@@ -215,48 +564,189 @@ impl Process
             while decoder.can_decode()
             {
                 decoder.decode_out(&mut inst);
-                info!("{:?}", inst);
                 output.clear();
                 formatter.format(&inst, &mut output);
-                let mut strbuild = String::new();
-                strbuild = format!("{}{:016X} ", strbuild, inst.ip());
-                let start = inst.ip() - entry;
-                let mut instr_bytes: Vec<u8> = Vec::new();
-                for i in start..start + inst.len() as u64
-                {
-                    instr_bytes.push(code[i as usize]);
-                }
-                if instr_bytes.len() < hcbl
-                {
-                    for _ in 0..hcbl - instr_bytes.len()
-                    {
-                        strbuild = format!("{} {}", strbuild, output);
-                    }
-                }
+                let start = (inst.ip() - entry) as usize;
+                let bytes: String = code[start..start + inst.len()].iter().map(|b| format!("{:02X}", b)).collect();
+                info!("{:016X} {:<30} {}", inst.ip(), bytes, output);
             }
         }
 
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        Ok(Process{space: Arc::new(space), threads: vec![main_thread], current: 0, next_tid: 1, tls: Arc::new(linked.tls), pid: id, parent: RUNNING_PROCESS.load(Ordering::Relaxed), cred: Credentials::ROOT, status: ProcessStatus::Ready, priority: DEFAULT_PRIORITY, ticks: 0, scheduled: 0, waited: 0, slice_used: 0, anon_next, handles: HandleTable::default(), shared: BTreeMap::new(), signals: SignalState::default(), limits: ResourceLimits::UNLIMITED, child_limits: ResourceLimits::UNLIMITED, mapped_pages: 0, mapped: BTreeMap::new(), killed: None, args, env})
+    }
 
-        let id = NEXT_ID.load(Ordering::Relaxed);
-        NEXT_ID.fetch_add(1, Ordering::Relaxed);
-        Process{/*code: data_vec,*/ mem_ptr: ptr, layout, start: func, pid: id, privileged: true, status: ProcessStatus::Done(true), retries: 0}
+    pub(crate) fn space(&self) -> &Arc<AddressSpace>
+    {
+        &self.space
     }
 
-    pub fn call(&mut self)
+    pub fn info(&self) -> ProcessInfo
     {
-        let ntable = NativeSysCallTable::gen().to_byte_slice();
-        let ctable = FFISysCallTable::gen();
-        self.status = ProcessStatus::Busy;
-        let r = (self.start)(ntable, ctable);
-        self.status = ProcessStatus::Done(r);
+        ProcessInfo{pid: self.pid, parent: self.parent, uid: self.cred.euid, gid: self.cred.egid}
     }
-}
 
-impl Drop for Process
-{
-    fn drop(&mut self)
+    pub fn stats(&self) -> ProcessStats
+    {
+        ProcessStats{ticks: self.ticks, scheduled: self.scheduled, priority: self.priority, _padding: [0; 7]}
+    }
+
+    /// Returns the key threads joining another thread of the process or waiting for one of its
+    /// children block on, see
+    /// `proc_watch::wait_on`. The address space stays at the same address while the process
+    /// moves around the queue.
+    pub(crate) fn wait_key(&self) -> u64
     {
-        let ptr: *mut u8 = self.mem_ptr.as_mut_ptr();
-        unsafe{ALLOCATOR.dealloc(ptr, self.layout)};
+        Arc::as_ptr(&self.space) as u64
+    }
+
+    /// Returns the thread that is running or ran last.
+    pub fn current_thread(&self) -> u64
+    {
+        self.current
+    }
+
+    /// Adds a thread created with `Thread::new` and returns its id.
+    pub(crate) fn add_thread(&mut self, mut thread: Thread) -> u64
+    {
+        thread.tid = self.next_tid;
+        self.next_tid += 1;
+        self.threads.push(thread);
+        self.next_tid - 1
+    }
+
+    /// Ends the running thread with `value`, returns true if that was the last one not done.
+    pub(crate) fn exit_thread(&mut self, value: u64) -> bool
+    {
+        let current = self.current;
+        if let Some(thread) = self.threads.iter_mut().find(|t| t.tid == current) {thread.status = ThreadStatus::Done(value);}
+        self.wake_blocked(self.wait_key());
+        self.threads.iter().all(|t| matches!(t.status, ThreadStatus::Done(_)))
+    }
+
+    /// Blocks the running thread, it is not scheduled again until it is made ready.
+    pub(crate) fn block_thread(&mut self, status: ThreadStatus)
+    {
+        let current = self.current;
+        if let Some(thread) = self.threads.iter_mut().find(|t| t.tid == current) {thread.status = status;}
+    }
+
+    /// Makes the threads whose sleep ends by tick `now` ready again.
+    pub(crate) fn wake_sleepers(&mut self, now: u64)
+    {
+        for thread in &mut self.threads
+        {
+            if matches!(thread.status, ThreadStatus::Sleeping(until) if until <= now) {thread.status = ThreadStatus::Ready;}
+        }
+    }
+
+    /// Makes up to `count` threads waiting on the futex at `addr` ready again and returns how many there were.
+    pub(crate) fn wake_waiting(&mut self, addr: u64, count: u64) -> u64
+    {
+        let mut woken = 0;
+        for thread in self.threads.iter_mut().filter(|t| t.status == ThreadStatus::Waiting(addr))
+        {
+            if woken == count {break;}
+            thread.status = ThreadStatus::Ready;
+            woken += 1;
+        }
+        woken
+    }
+
+    /// Makes the threads blocked on the kernel object `key` ready again.
+    pub(crate) fn wake_blocked(&mut self, key: u64)
+    {
+        for thread in self.threads.iter_mut().filter(|t| t.status == ThreadStatus::Blocked(key))
+        {
+            thread.status = ThreadStatus::Ready;
+        }
+    }
+
+    /// Ends the process with `code` once none of its threads is inside the kernel anymore, a
+    /// thread preempted in a syscall might hold locks the rest of the kernel needs.
+    ///
+    /// Threads in user mode run no further than `resume`, which then makes them exit.
+    pub(crate) fn kill(&mut self, code: i32)
+    {
+        self.killed.get_or_insert(code);
+        self.signals.stopped = false;
+        self.interrupt_waits();
+    }
+
+    /// Makes sleeping threads and ones waiting on a futex or kernel object ready again, so a
+    /// pending signal gets handled without waiting for them. Waits on kernel objects then fail
+    /// with `ErrorKind::Interrupted`.
+    pub(crate) fn interrupt_waits(&mut self)
+    {
+        for thread in &mut self.threads
+        {
+            if matches!(thread.status, ThreadStatus::Sleeping(_) | ThreadStatus::Waiting(_) | ThreadStatus::Blocked(_)) {thread.status = ThreadStatus::Ready;}
+        }
+    }
+
+    /// Returns true if the process was killed or has a signal whose handler can run, waits on
+    /// kernel objects are given up then.
+    pub(crate) fn interrupted(&self) -> bool
+    {
+        self.killed.is_some() || self.signals.deliverable()
+    }
+
+    /// Returns true if the process can be scheduled, which takes a thread that is not blocked and
+    /// the process not being stopped.
+    pub(crate) fn runnable(&self) -> bool
+    {
+        self.status == ProcessStatus::Ready && !self.signals.stopped && self.threads.iter().any(|t| t.status == ThreadStatus::Ready)
+    }
+
+    /// Records where the interrupted context of the running thread was saved.
+    pub(crate) fn save(&mut self, frame: *mut TrapFrame)
+    {
+        let current = self.current;
+        if let Some(thread) = self.threads.iter_mut().find(|t| t.tid == current)
+        {
+            thread.context = frame as u64;
+            thread.fpu.save();
+            if thread.status == ThreadStatus::Busy {thread.status = ThreadStatus::Ready;}
+        }
+        if self.status == ProcessStatus::Busy {self.status = ProcessStatus::Ready;}
+    }
+
+    /// Activates the address space of the process and the kernel stack of its next ready thread
+    /// and returns the context to resume.
+    ///
+    /// Threads take turns, starting after the one that ran last. The process has to be `runnable`.
+    /// A thread about to continue in user mode runs the handler of a pending signal first, or
+    /// exits if the process was killed. Threads inside the kernel go first then, so nothing
+    /// they hold is lost.
+    ///
+    /// This function is unsafe because the returned frame must be resumed right away.
+    pub(crate) unsafe fn resume(&mut self) -> *mut TrapFrame
+    {
+        let count = self.threads.len();
+        let last = self.threads.iter().position(|t| t.tid == self.current).map_or(0, |i| i + 1);
+        let kernel_only = self.killed.is_some() && self.threads.iter().any(|t| t.status == ThreadStatus::Ready && t.in_kernel());
+        let next = (0..count).map(|i| (last + i) % count).find(|&i| self.threads[i].status == ThreadStatus::Ready && (!kernel_only || self.threads[i].in_kernel())).expect("resumed a process without a ready thread");
+        let thread = &mut self.threads[next];
+        thread.status = ThreadStatus::Busy;
+        self.current = thread.tid;
+        self.status = ProcessStatus::Busy;
+        self.scheduled += 1;
+        self.waited = 0;
+        self.slice_used = 0;
+        crate::gdt::set_kernel_stack(thread.kernel_stack.top());
+        FsBase::write(VirtAddr::new(thread.fs_base));
+        thread.fpu.restore();
+        unsafe{self.space.activate()};
+        let frame = thread.context as *mut TrapFrame;
+        //the handler is set up on the user stack, which takes the address space to be active
+        if unsafe{(*frame).from_user()}
+        {
+            match self.killed
+            {
+                Some(code) => crate::signal::exit_from(unsafe{&mut *frame}, code),
+                None => self.signals.deliver(unsafe{&mut *frame})
+            }
+        }
+        frame
     }
 }
\ No newline at end of file
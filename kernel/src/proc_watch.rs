@@ -1,12 +1,62 @@
 use alloc::collections::VecDeque;
-use crate::proc::{Process, ProcessStatus};
-use spin::{Mutex, MutexGuard};
-use core::sync::atomic::{AtomicU64, Ordering};
+use alloc::vec::Vec;
+use alloc::sync::Arc;
+use alloc::string::String;
+use alloc::collections::BTreeMap;
+use crate::proc::{Credentials, Process, ProcessInfo, ProcessStatus, ProcessStats, ResourceLimits, Thread, ThreadStatus, PRIORITY_LEVELS, EXIT_KILLED};
+use crate::linker::TlsLayout;
+use crate::handle::HandleTable;
+use crate::memory::AddressSpace;
+use crate::shm::SharedMemory;
+use crate::abi_defs::{SIGSEGV, SIGXCPU};
+use crate::signal::{Posted, EXIT_SIGNALED};
+use crate::syscall::ProcessError;
+use crate::trap::TrapFrame;
+use crate::usermode::{ANON_REGION_START, ANON_REGION_END};
+use spin::Mutex;
+use embedded_io::ErrorKind;
+use core::future::poll_fn;
+use core::task::Poll;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use futures_util::task::AtomicWaker;
+use x86_64::instructions::interrupts;
 
-pub const RETRY_LIMIT: u64 = 1000;
+/// Value of `RUNNING_PROCESS` while the kernel itself is running.
+pub const KERNEL_PID: u64 = u64::MAX;
 
+/// Every process in round-robin order, among equal priorities the next one to run is at the front.
+///
+/// The timer interrupt locks this, so it must only be locked with interrupts disabled.
 pub static PROCESS_QUEUE: Mutex<VecDeque<Process>> = Mutex::new(VecDeque::new());
-pub static RUNNING_PROCESS: AtomicU64 = AtomicU64::new(0);
+pub static RUNNING_PROCESS: AtomicU64 = AtomicU64::new(KERNEL_PID);
+
+pub const DEFAULT_TIME_SLICE: u64 = 2;
+/// Ticks a ready process has to wait to be treated as one priority level higher.
+pub const AGING_TICKS: u64 = 10;
+/// Timer interrupts per 1000 seconds, the PIT runs at its power-on rate of about 18.2 Hz.
+pub const TIMER_MILLIHERTZ: u64 = 18_206;
+
+/// Exit status of a finished process that its parent has not collected yet.
+struct Zombie
+{
+    pid: u64,
+    parent: u64,
+    code: i32
+}
+
+//exited processes waiting to be collected, only locked with interrupts disabled and after PROCESS_QUEUE
+static ZOMBIES: Mutex<Vec<Zombie>> = Mutex::new(Vec::new());
+
+//saved frame of the kernel's own context while a process is running
+static KERNEL_CONTEXT: AtomicU64 = AtomicU64::new(0);
+//processes switched to since the kernel last had a turn
+static SINCE_KERNEL: AtomicUsize = AtomicUsize::new(0);
+//timer interrupts since boot
+static TICKS: AtomicU64 = AtomicU64::new(0);
+//timer ticks a process may run before it gets preempted
+static TIME_SLICE: AtomicU64 = AtomicU64::new(DEFAULT_TIME_SLICE);
+static REAP_PENDING: AtomicBool = AtomicBool::new(false);
+static REAP_WAKER: AtomicWaker = AtomicWaker::new();
 
 pub fn find(proc_iter: alloc::collections::vec_deque::Iter<'_, Process>, pid: u64) -> Option<usize>
 {
@@ -19,54 +69,695 @@ pub fn find(proc_iter: alloc::collections::vec_deque::Iter<'_, Process>, pid: u6
     None
 }
 
-fn push_proc_and_call(mut guard: MutexGuard<'_, VecDeque<Process>>, mut proc: Process)
+/// Saves the interrupted context and returns the frame of the context to run next.
+///
+/// Called from the timer interrupt, this is what preempts running processes once their
+/// time slice is used up.
+pub fn schedule(frame: *mut TrapFrame) -> *mut TrapFrame
+{
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    let mut pq = match PROCESS_QUEUE.try_lock()
+    {
+        Some(pq) => pq,
+        None => return frame
+    };
+    let running = RUNNING_PROCESS.load(Ordering::Relaxed);
+    let mut slice_left = false;
+    for proc in pq.iter_mut()
+    {
+        //sleepers missed while the queue was locked get woken a tick late
+        proc.wake_sleepers(now);
+        if proc.pid == running
+        {
+            proc.ticks += 1;
+            proc.slice_used += 1;
+            if proc.ticks > proc.limits.cpu_ticks && proc.status == ProcessStatus::Busy {proc.kill(EXIT_SIGNALED + SIGXCPU as i32);}
+            //a killed process gets switched away from, it ends when resumed
+            slice_left = proc.status == ProcessStatus::Busy && proc.killed.is_none() && proc.slice_used < TIME_SLICE.load(Ordering::Relaxed);
+        }
+        else if proc.runnable() {proc.waited += 1;}
+    }
+    if slice_left {return frame;}
+    save_current(&mut pq, frame);
+    switch_next(&mut pq)
+}
+
+/// Ends the running process and returns the frame of the context to run next.
+pub fn exit_current(code: i32, frame: *mut TrapFrame) -> *mut TrapFrame
+{
+    let mut pq = PROCESS_QUEUE.lock();
+    if let Some(i) = find(pq.iter(), RUNNING_PROCESS.load(Ordering::Relaxed))
+    {
+        pq[i].status = ProcessStatus::Done(code);
+        wake_reaper();
+    }
+    save_current(&mut pq, frame);
+    switch_next(&mut pq)
+}
+
+/// Ends the running thread with `value` and returns the frame of the context to run next.
+///
+/// The process exits with code 0 once none of its threads is left.
+pub fn exit_thread(value: u64, frame: *mut TrapFrame) -> *mut TrapFrame
+{
+    let mut pq = PROCESS_QUEUE.lock();
+    if let Some(i) = find(pq.iter(), RUNNING_PROCESS.load(Ordering::Relaxed))
+    {
+        if pq[i].exit_thread(value)
+        {
+            pq[i].status = ProcessStatus::Done(0);
+            wake_reaper();
+        }
+    }
+    save_current(&mut pq, frame);
+    switch_next(&mut pq)
+}
+
+/// Gives up the rest of the running time slice and returns the frame of the context to run next.
+pub fn yield_current(frame: *mut TrapFrame) -> *mut TrapFrame
 {
-    guard.pop_front();
-    proc.retries = 0;
-    guard.push_back(proc);
-    RUNNING_PROCESS.store(guard.front().unwrap().pid, Ordering::Relaxed);
-    guard.front_mut().unwrap().call();
+    let mut pq = PROCESS_QUEUE.lock();
+    save_current(&mut pq, frame);
+    switch_next(&mut pq)
 }
 
-pub fn check()
+/// Blocks the running thread for `ms` milliseconds and returns the frame of the context to run next.
+///
+/// Sleeps are rounded up to whole timer ticks, sleeping for 0 milliseconds only yields.
+pub fn sleep_current(ms: u64, frame: *mut TrapFrame) -> *mut TrapFrame
+{
+    let ticks = ms.saturating_mul(TIMER_MILLIHERTZ).div_ceil(1_000_000);
+    if ticks == 0 {return yield_current(frame);}
+    block_current(ThreadStatus::Sleeping(TICKS.load(Ordering::Relaxed).saturating_add(ticks)), frame)
+}
+
+/// Blocks the running thread with the given status and returns the frame of the context to run next.
+///
+/// The kernel itself can't block, so it just continues.
+pub fn block_current(status: ThreadStatus, frame: *mut TrapFrame) -> *mut TrapFrame
 {
     let mut pq = PROCESS_QUEUE.lock();
-    let proc = pq.get(find(pq.iter(), RUNNING_PROCESS.load(Ordering::Relaxed)).unwrap()).unwrap();
-    let mut proc = proc.clone();
-    if let ProcessStatus::Done(_) = proc.status
+    match find(pq.iter(), RUNNING_PROCESS.load(Ordering::Relaxed))
     {
-        push_proc_and_call(pq, proc);
-        
+        Some(i) => pq[i].block_thread(status),
+        None => return frame
     }
-    else
+    save_current(&mut pq, frame);
+    switch_next(&mut pq)
+}
+
+/// Makes up to `count` threads of a process that wait on the futex at `addr` ready again and
+/// returns how many there were.
+pub fn futex_wake(pid: u64, addr: u64, count: u64) -> u64
+{
+    interrupts::without_interrupts(||
     {
-        if proc.retries > RETRY_LIMIT
+        let mut pq = PROCESS_QUEUE.lock();
+        match find(pq.iter(), pid)
         {
-            proc.status = ProcessStatus::Done(false);
-            push_proc_and_call(pq, proc);
+            Some(i) => pq[i].wake_waiting(addr, count),
+            None => 0
         }
-        else
+    })
+}
+
+/// Marks the running thread as blocked on the kernel object `key`, it stops running once it
+/// yields and runs again after `wake_blocked(key)`.
+///
+/// Returns false without blocking if the process has a signal to handle or was killed, see
+/// `Process::interrupted`. Must be called with interrupts disabled and the lock guarding what
+/// the thread waits for held, so the wake can't slip in between checking and blocking.
+pub fn block_running(key: u64) -> bool
+{
+    let mut pq = PROCESS_QUEUE.lock();
+    match find(pq.iter(), RUNNING_PROCESS.load(Ordering::Relaxed))
+    {
+        Some(i) if pq[i].interrupted() => false,
+        Some(i) => {pq[i].block_thread(ThreadStatus::Blocked(key)); true},
+        None => true
+    }
+}
+
+/// Makes every thread blocked on the kernel object `key` ready again, must be called with interrupts disabled.
+pub fn wake_blocked(key: u64)
+{
+    for proc in PROCESS_QUEUE.lock().iter_mut()
+    {
+        proc.wake_blocked(key);
+    }
+}
+
+/// Calls `f` with interrupts disabled until it returns a result, blocking the running thread
+/// on the kernel object `key` in between. Threads blocked on `key` are woken once it does.
+///
+/// Fails with `ErrorKind::Interrupted` instead of blocking once the process has a signal to
+/// handle or was killed. Wakers have to change the object with interrupts disabled too, so a
+/// wake can't slip in between `f` checking and the thread blocking.
+pub fn wait_on<T>(key: u64, mut f: impl FnMut() -> Option<T>) -> Result<T, ErrorKind>
+{
+    loop
+    {
+        let res = interrupts::without_interrupts(||
+        {
+            match f()
+            {
+                //whatever the others wait for might have changed, they check again once woken
+                Some(res) => {wake_blocked(key); Some(Ok(res))},
+                None if !block_running(key) => Some(Err(ErrorKind::Interrupted)),
+                None => None
+            }
+        });
+        match res
         {
-            pq.pop_back();
-            proc.retries += 1;
-            pq.push_back(proc);
+            Some(res) => return res,
+            None => crate::syscall::thread_yield()
         }
     }
 }
 
+fn save_current(pq: &mut VecDeque<Process>, frame: *mut TrapFrame)
+{
+    let pid = RUNNING_PROCESS.load(Ordering::Relaxed);
+    if pid == KERNEL_PID
+    {
+        KERNEL_CONTEXT.store(frame as u64, Ordering::Relaxed);
+        return;
+    }
+    if let Some(i) = find(pq.iter(), pid)
+    {
+        //removing before pushing keeps the deque from reallocating inside the interrupt
+        let mut proc = pq.remove(i).unwrap();
+        proc.save(frame);
+        pq.push_back(proc);
+    }
+}
+
+fn switch_next(pq: &mut VecDeque<Process>) -> *mut TrapFrame
+{
+    let since_kernel = SINCE_KERNEL.load(Ordering::Relaxed);
+    //the kernel gets a turn once every process had one
+    if since_kernel < pq.len()
+    {
+        //max_by_key returns the last maximum, so search from the back to keep round-robin order
+        if let Some(proc) = pq.iter_mut().rev().filter(|p| p.runnable()).max_by_key(|p| effective_priority(p))
+        {
+            SINCE_KERNEL.store(since_kernel + 1, Ordering::Relaxed);
+            RUNNING_PROCESS.store(proc.pid, Ordering::Relaxed);
+            return unsafe{proc.resume()};
+        }
+    }
+    SINCE_KERNEL.store(0, Ordering::Relaxed);
+    RUNNING_PROCESS.store(KERNEL_PID, Ordering::Relaxed);
+    unsafe{crate::memory::activate_kernel()};
+    KERNEL_CONTEXT.load(Ordering::Relaxed) as *mut TrapFrame
+}
+
+fn effective_priority(proc: &Process) -> u8
+{
+    let boost = (proc.waited / AGING_TICKS).min(PRIORITY_LEVELS as u64) as u8;
+    (proc.priority + boost).min(PRIORITY_LEVELS - 1)
+}
+
 pub fn spawn(proc: Process)
 {
-    PROCESS_QUEUE.lock().push_back(proc);
+    interrupts::without_interrupts(|| PROCESS_QUEUE.lock().push_back(proc));
 }
 
+/// Queues a process unless its parent already has as many children as its limits allow, in
+/// which case the process is handed back to be freed with the queue unlocked.
+///
+/// Children count until they are collected with `wait_child`, so exit codes nobody collects
+/// can't pile up.
+pub fn spawn_child(proc: Process) -> Option<Process>
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        if let Some(i) = find(pq.iter(), proc.parent)
+        {
+            let children = pq.iter().filter(|p| p.parent == proc.parent).count() + ZOMBIES.lock().iter().filter(|z| z.parent == proc.parent).count();
+            if children as u64 >= pq[i].limits.children {return Some(proc);}
+        }
+        pq.push_back(proc);
+        None
+    })
+}
+
+/// Returns the user and group identities of a process.
+pub fn credentials(pid: u64) -> Option<Credentials>
+{
+    interrupts::without_interrupts(||
+    {
+        let pq = PROCESS_QUEUE.lock();
+        find(pq.iter(), pid).map(|i| pq[i].cred)
+    })
+}
+
+/// Replaces the identities of a process, returns false if there is no such process.
+pub fn set_credentials(pid: u64, cred: Credentials) -> bool
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        match find(pq.iter(), pid)
+        {
+            Some(i) => {pq[i].cred = cred; true},
+            None => false
+        }
+    })
+}
+
+/// Returns the resource limits of a process and the limits its children get.
+pub fn limits(pid: u64) -> Option<(ResourceLimits, ResourceLimits)>
+{
+    interrupts::without_interrupts(||
+    {
+        let pq = PROCESS_QUEUE.lock();
+        find(pq.iter(), pid).map(|i| (pq[i].limits, pq[i].child_limits))
+    })
+}
+
+/// Sets the limits children of a process spawned from now on get, returns false if there is no such process.
+pub fn set_child_limits(pid: u64, limits: ResourceLimits) -> bool
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        match find(pq.iter(), pid)
+        {
+            Some(i) => {pq[i].child_limits = limits; true},
+            None => false
+        }
+    })
+}
+
+/// Kills the process, it ends with `EXIT_KILLED` once none of its threads is inside the kernel
+/// and gets freed by `reap_finished`.
 pub fn remove(pid: u64) -> bool
 {
-    let mut pq = PROCESS_QUEUE.lock();
-    let opt = find(pq.iter(), pid);
-    if let Some(i) = opt
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        match find(pq.iter(), pid)
+        {
+            Some(i) if !matches!(pq[i].status, ProcessStatus::Done(_)) => {pq[i].kill(EXIT_KILLED); true},
+            _ => false
+        }
+    })
+}
+
+/// Sends signal `sig` to the process `pid`, which has to be the process `from` or one of its
+/// children unless `from` is `None`.
+pub fn signal(pid: u64, sig: u8, from: Option<u64>) -> Result<(), ProcessError>
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        let i = find(pq.iter(), pid).ok_or(ProcessError::NotFound)?;
+        let proc = &mut pq[i];
+        if matches!(proc.status, ProcessStatus::Done(_)) {return Err(ProcessError::NotFound);}
+        if from.is_some_and(|from| proc.pid != from && proc.parent != from) {return Err(ProcessError::Unprivileged);}
+        match proc.signals.post(sig)
+        {
+            Posted::Terminate(code) => proc.kill(code),
+            Posted::Pending => proc.interrupt_waits(),
+            Posted::Done => {}
+        }
+        Ok(())
+    })
+}
+
+/// Sets the handler of signal `sig` for the process `pid` and returns the previous one.
+pub fn set_signal_handler(pid: u64, sig: u8, handler: u64) -> Result<u64, ProcessError>
+{
+    interrupts::without_interrupts(||
     {
-        pq.remove(i).unwrap();
+        let mut pq = PROCESS_QUEUE.lock();
+        let i = find(pq.iter(), pid).ok_or(ProcessError::NotFound)?;
+        pq[i].signals.set_handler(sig, handler).ok_or(ProcessError::InvalidInput)
+    })
+}
+
+/// Restores the context the signal handler of the running process that returned into `frame`
+/// interrupted, and runs the next pending handler if there is one.
+///
+/// Ends the process and returns the frame of the context to run next if there is no valid
+/// signal frame on the stack.
+///
+/// # Safety
+///
+/// `frame` has to point to the frame the syscall stub pushed for the running thread.
+pub unsafe fn signal_return(frame: *mut TrapFrame) -> *mut TrapFrame
+{
+    let restored =
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        let f = unsafe{&mut *frame};
+        match find(pq.iter(), RUNNING_PROCESS.load(Ordering::Relaxed))
+        {
+            Some(i) if f.from_user() && pq[i].signals.restore(f) =>
+            {
+                pq[i].signals.deliver(f);
+                true
+            },
+            _ => false
+        }
+    };
+    if restored {frame}
+    else {exit_current(EXIT_SIGNALED + SIGSEGV as i32, frame)}
+}
+
+/// Sets the priority of a process, returns false if there is no such process.
+pub fn set_priority(pid: u64, priority: u8) -> bool
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        match find(pq.iter(), pid)
+        {
+            Some(i) => {pq[i].priority = priority; true},
+            None => false
+        }
+    })
+}
+
+/// Sets how many timer ticks a process may run before it gets preempted.
+pub fn set_time_slice(ticks: u64)
+{
+    TIME_SLICE.store(ticks.max(1), Ordering::Relaxed);
+}
+
+pub fn stats(pid: u64) -> Option<ProcessStats>
+{
+    interrupts::without_interrupts(||
+    {
+        let pq = PROCESS_QUEUE.lock();
+        find(pq.iter(), pid).map(|i| pq[i].stats())
+    })
+}
+
+/// Returns the list entry of a process that has not exited.
+pub fn info(pid: u64) -> Option<ProcessInfo>
+{
+    interrupts::without_interrupts(||
+    {
+        let pq = PROCESS_QUEUE.lock();
+        find(pq.iter(), pid).filter(|&i| !matches!(pq[i].status, ProcessStatus::Done(_))).map(|i| pq[i].info())
+    })
+}
+
+/// Returns the arguments and environment a process was started with.
+pub fn start_data(pid: u64) -> Option<(Vec<String>, BTreeMap<String, String>)>
+{
+    interrupts::without_interrupts(||
+    {
+        let pq = PROCESS_QUEUE.lock();
+        find(pq.iter(), pid).map(|i| (pq[i].args.clone(), pq[i].env.clone()))
+    })
+}
+
+/// Runs `f` on the handle table of a process, returns `None` if there is no such process.
+///
+/// Objects must not be dropped inside `f`, removed ones have to be returned instead.
+pub fn with_handles<T>(pid: u64, f: impl FnOnce(&mut HandleTable) -> T) -> Option<T>
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        let i = find(pq.iter(), pid)?;
+        Some(f(&mut pq[i].handles))
+    })
+}
+
+/// Records that a shared memory object is mapped into a process at `start`.
+///
+/// Gives the object back if there is no such process.
+pub fn add_shared(pid: u64, start: u64, memory: Arc<SharedMemory>) -> Result<(), Arc<SharedMemory>>
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        match find(pq.iter(), pid)
+        {
+            Some(i) => {pq[i].shared.insert(start, memory); Ok(())},
+            None => Err(memory)
+        }
+    })
+}
+
+/// Removes the shared memory object mapped into a process at `start` and returns it.
+///
+/// The caller has to drop it with the process queue unlocked, it can free frames.
+pub fn remove_shared(pid: u64, start: u64) -> Option<Arc<SharedMemory>>
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        let i = find(pq.iter(), pid)?;
+        pq[i].shared.remove(&start)
+    })
+}
+
+/// Records that `mem_map` mapped `size` bytes at `start` into a process, merged with the
+/// recorded ranges right next to it.
+pub fn add_mapping(pid: u64, start: u64, size: u64)
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        let Some(i) = find(pq.iter(), pid) else {return};
+        let mapped = &mut pq[i].mapped;
+        let mut range = (start, start + size);
+        if let Some(end) = mapped.remove(&range.1) {range.1 = end;}
+        if let Some((&before, &end)) = mapped.range(..start).next_back()
+        {
+            if end == start {range.0 = before;}
+        }
+        mapped.insert(range.0, range.1);
+    })
+}
+
+/// Forgets `size` bytes at `start` of the ranges `add_mapping` recorded for a process and stops
+/// counting them against its page limit, the caller unmaps them afterwards.
+///
+/// Returns false without changing anything unless the bytes lie within a recorded range, so
+/// memory the kernel mapped for the process can't be unmapped or uncounted this way.
+pub fn remove_mapping(pid: u64, start: u64, size: u64) -> bool
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        let Some(i) = find(pq.iter(), pid) else {return false};
+        let proc = &mut pq[i];
+        let Some(end) = start.checked_add(size) else {return false};
+        let (from, to) = match proc.mapped.range(..=start).next_back()
+        {
+            Some((&from, &to)) if end <= to => (from, to),
+            _ => return false
+        };
+        proc.mapped.remove(&from);
+        if from < start {proc.mapped.insert(from, start);}
+        if end < to {proc.mapped.insert(end, to);}
+        proc.mapped_pages = proc.mapped_pages.saturating_sub(size.div_ceil(4096));
         true
+    })
+}
+
+/// Returns the TLS layout new threads of a process get their TLS area from.
+pub fn tls_layout(pid: u64) -> Option<Arc<TlsLayout>>
+{
+    interrupts::without_interrupts(||
+    {
+        let pq = PROCESS_QUEUE.lock();
+        find(pq.iter(), pid).map(|i| pq[i].tls.clone())
+    })
+}
+
+/// Returns the thread of a process that is running or ran last.
+pub fn current_thread(pid: u64) -> Option<u64>
+{
+    interrupts::without_interrupts(||
+    {
+        let pq = PROCESS_QUEUE.lock();
+        find(pq.iter(), pid).map(|i| pq[i].current_thread())
+    })
+}
+
+/// Adds a thread to a process, it starts running once scheduled. Returns the id of the thread,
+/// or gives it back if the process has exited.
+pub fn add_thread(pid: u64, thread: Thread) -> Result<u64, Thread>
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        match find(pq.iter(), pid)
+        {
+            Some(i) if !matches!(pq[i].status, ProcessStatus::Done(_)) => Ok(pq[i].add_thread(thread)),
+            _ => Err(thread)
+        }
+    })
+}
+
+/// Waits until thread `tid` of process `pid` has ended, removes it and returns the value it ended with.
+///
+/// Fails with `ProcessError::NotFound` if there is no such thread and with
+/// `ProcessError::Interrupted` if a signal arrives first.
+pub fn join_thread(pid: u64, tid: u64) -> Result<u64, ProcessError>
+{
+    let key = wait_key(pid).ok_or(ProcessError::NotFound)?;
+    let res = wait_on(key, ||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        let Some(i) = find(pq.iter(), pid) else {return Some(None)};
+        let proc = &mut pq[i];
+        match proc.threads.iter().position(|t| t.tid == tid)
+        {
+            Some(j) if matches!(proc.threads[j].status, ThreadStatus::Done(_)) => Some(Some((proc.space().clone(), proc.threads.remove(j)))),
+            Some(_) => None,
+            None => Some(None)
+        }
+    }).map_err(|_| ProcessError::Interrupted)?;
+    //unmapping needs locks a preempted thread might hold, so it can't happen with interrupts disabled
+    let (space, thread) = res.ok_or(ProcessError::NotFound)?;
+    release_anon(pid, thread.region_size());
+    Ok(thread.release(&space))
+}
+
+//key the threads of a process wait on for its other threads and its children, see `Process::wait_key`
+fn wait_key(pid: u64) -> Option<u64>
+{
+    interrupts::without_interrupts(||
+    {
+        let pq = PROCESS_QUEUE.lock();
+        find(pq.iter(), pid).map(|i| pq[i].wait_key())
+    })
+}
+
+/// Waits until a child of `parent` has exited, or the child `pid` if given, and collects it.
+///
+/// Returns the pid and exit code of the child. Fails with `ProcessError::NotFound` if there is no
+/// such child and with `ProcessError::Interrupted` if a signal arrives first.
+pub fn wait_child(parent: u64, pid: Option<u64>) -> Result<(u64, i32), ProcessError>
+{
+    let is_child = |child: u64, child_parent: u64| child_parent == parent && pid.is_none_or(|p| p == child);
+    let key = wait_key(parent).ok_or(ProcessError::NotFound)?;
+    wait_on(key, ||
+    {
+        let pq = PROCESS_QUEUE.lock();
+        let mut zombies = ZOMBIES.lock();
+        if let Some(i) = zombies.iter().position(|z| is_child(z.pid, z.parent))
+        {
+            let z = zombies.swap_remove(i);
+            return Some(Ok((z.pid, z.code)));
+        }
+        //children that exited but were not reaped yet still count as running
+        if pq.iter().any(|p| is_child(p.pid, p.parent)) {None}
+        else {Some(Err(ProcessError::NotFound))}
+    }).map_err(|_| ProcessError::Interrupted)?
+}
+
+/// Reserves `size` bytes of runtime memory for a process at `hint`, or after the previous
+/// reservation without one, and returns its address space with the start of the range.
+///
+/// The range still has to be mapped, which can't be done here since the queue is locked. Its
+/// pages count against the page limit of the process until they are given back with
+/// `release_anon`, exceeding it fails with `ErrorKind::OutOfMemory`.
+pub fn reserve_anon(pid: u64, hint: Option<u64>, size: u64) -> Result<(Arc<AddressSpace>, u64), ErrorKind>
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        let i = find(pq.iter(), pid).ok_or(ErrorKind::InvalidInput)?;
+        let proc = &mut pq[i];
+        let start = hint.unwrap_or(proc.anon_next);
+        let end = start.checked_add(size).ok_or(ErrorKind::InvalidInput)?;
+        if start < ANON_REGION_START || end > ANON_REGION_END {return Err(ErrorKind::InvalidInput);}
+        let pages = proc.mapped_pages.saturating_add(size.div_ceil(4096));
+        if pages > proc.limits.pages {return Err(ErrorKind::OutOfMemory);}
+        proc.mapped_pages = pages;
+        proc.anon_next = proc.anon_next.max(end);
+        Ok((proc.space().clone(), start))
+    })
+}
+
+/// Stops counting `size` bytes of runtime memory that were unmapped against the page limit of a process.
+pub fn release_anon(pid: u64, size: u64)
+{
+    interrupts::without_interrupts(||
+    {
+        let mut pq = PROCESS_QUEUE.lock();
+        if let Some(i) = find(pq.iter(), pid) {pq[i].mapped_pages = pq[i].mapped_pages.saturating_sub(size.div_ceil(4096));}
+    })
+}
+
+/// Returns the address space of a process.
+pub fn space(pid: u64) -> Option<Arc<AddressSpace>>
+{
+    interrupts::without_interrupts(||
+    {
+        let pq = PROCESS_QUEUE.lock();
+        find(pq.iter(), pid).map(|i| pq[i].space().clone())
+    })
+}
+
+/// Returns every process that has not exited.
+pub fn list() -> Vec<ProcessInfo>
+{
+    interrupts::without_interrupts(||
+    {
+        PROCESS_QUEUE.lock().iter().filter(|p| !matches!(p.status, ProcessStatus::Done(_))).map(|p| p.info()).collect()
+    })
+}
+
+fn wake_reaper()
+{
+    REAP_PENDING.store(true, Ordering::Relaxed);
+    REAP_WAKER.wake();
+}
+
+/// Frees finished processes.
+///
+/// This runs as a kernel task because a process that just finished is still on its own
+/// kernel stack, and unmapping it needs locks the interrupted kernel might be holding.
+pub async fn reap_finished()
+{
+    loop
+    {
+        poll_fn(|cx|
+        {
+            REAP_WAKER.register(cx.waker());
+            if REAP_PENDING.swap(false, Ordering::Relaxed) {Poll::Ready(())}
+            else {Poll::Pending}
+        }).await;
+        let finished: Vec<Process> = interrupts::without_interrupts(||
+        {
+            let mut pq = PROCESS_QUEUE.lock();
+            let running = RUNNING_PROCESS.load(Ordering::Relaxed);
+            let mut finished = Vec::new();
+            let mut i = 0;
+            while i < pq.len()
+            {
+                if matches!(pq[i].status, ProcessStatus::Done(_)) && pq[i].pid != running {finished.push(pq.remove(i).unwrap());}
+                else {i += 1;}
+            }
+            let mut zombies = ZOMBIES.lock();
+            for proc in &finished
+            {
+                //orphans go to the kernel, which never collects exit codes
+                for child in pq.iter_mut().filter(|p| p.parent == proc.pid) {child.parent = KERNEL_PID;}
+                zombies.retain(|z| z.parent != proc.pid);
+            }
+            for proc in &finished
+            {
+                let parent_alive = pq.iter().any(|p| p.pid == proc.parent && !matches!(p.status, ProcessStatus::Done(_)));
+                if let (true, ProcessStatus::Done(code)) = (parent_alive, &proc.status)
+                {
+                    zombies.push(Zombie{pid: proc.pid, parent: proc.parent, code: *code});
+                    if let Some(parent) = pq.iter_mut().find(|p| p.pid == proc.parent) {parent.wake_blocked(parent.wait_key());}
+                }
+            }
+            finished
+        });
+        drop(finished);
     }
-    else {false}
 }
\ No newline at end of file
@@ -0,0 +1,103 @@
+//! Named shared memory, pages several processes map at the same time.
+//!
+//! An object lives as long as some process has it mapped, its name is free again once the last
+//! mapping is gone. Like a file it has an owner, a group and mode bits, which decide who may map
+//! it and how. No mapping is ever writable and executable at once.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use embedded_io::ErrorKind;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use crate::memory::{AddressSpace, SharedFrames};
+use crate::fs::FilePermissions;
+use crate::proc::Credentials;
+use crate::abi_defs::{MODE_READ, MODE_WRITE, MODE_EXEC};
+
+/// Most pages a shared memory object can have.
+pub const MAX_SHARED_PAGES: u64 = 1024;
+/// Longest shared memory name.
+pub const MAX_SHARED_NAME: usize = 64;
+/// Mode of objects created without one, only the owner can map them.
+pub const DEFAULT_SHARED_MODE: u16 = 0o600;
+
+//only locked with interrupts disabled, objects must not be dropped while it is locked
+static SHARED: Mutex<BTreeMap<String, Weak<SharedMemory>>> = Mutex::new(BTreeMap::new());
+
+pub struct SharedMemory
+{
+    frames: SharedFrames,
+    perms: FilePermissions
+}
+
+/// Returns the access a mapping needs, fails with `InvalidInput` for writable and executable ones.
+pub fn access(writable: bool, executable: bool) -> Result<u16, ErrorKind>
+{
+    if writable && executable {return Err(ErrorKind::InvalidInput);}
+    Ok(MODE_READ | if writable {MODE_WRITE} else {0} | if executable {MODE_EXEC} else {0})
+}
+
+/// Creates a zeroed object of `pages` pages under `name`, owned by the effective ids of `cred`
+/// with the permission bits `mode`.
+///
+/// It is freed again when the returned reference and every one made from it are dropped.
+pub fn create(name: &str, pages: u64, mode: u16, cred: &Credentials) -> Result<Arc<SharedMemory>, ErrorKind>
+{
+    if name.is_empty() || name.len() > MAX_SHARED_NAME || pages == 0 || pages > MAX_SHARED_PAGES || mode & !0o777 != 0 {return Err(ErrorKind::InvalidInput);}
+    let perms = FilePermissions{owner: cred.euid, group: cred.egid, mode};
+    //allocated before taking the lock, a preempted thread might hold the frame allocator
+    let memory = Arc::new(SharedMemory{frames: SharedFrames::new(pages as usize).map_err(|_| ErrorKind::OutOfMemory)?, perms});
+    let inserted = interrupts::without_interrupts(||
+    {
+        let mut shared = SHARED.lock();
+        shared.retain(|_, m| m.strong_count() > 0);
+        if shared.contains_key(name) {return false;}
+        shared.insert(name.into(), Arc::downgrade(&memory));
+        true
+    });
+    if inserted {Ok(memory)}
+    else {Err(ErrorKind::AlreadyExists)}
+}
+
+/// Returns the object named `name` if `cred` may access it in every way in `access`.
+pub fn open(name: &str, access: u16, cred: &Credentials) -> Result<Arc<SharedMemory>, ErrorKind>
+{
+    let memory = interrupts::without_interrupts(|| SHARED.lock().get(name).and_then(Weak::upgrade)).ok_or(ErrorKind::NotFound)?;
+    //dropped outside the lock if it was the last reference
+    if !memory.perms.allows(cred, access) {return Err(ErrorKind::PermissionDenied);}
+    Ok(memory)
+}
+
+impl SharedMemory
+{
+    pub fn pages(&self) -> u64
+    {
+        self.frames.frames().len() as u64
+    }
+
+    /// Maps the object into `space` at `start`, which must not have anything mapped yet.
+    ///
+    /// The pages are readable, writable and executable as requested, but never writable and
+    /// executable at once.
+    pub fn map_into(&self, space: &AddressSpace, start: u64, writable: bool, executable: bool) -> Result<(), ErrorKind>
+    {
+        access(writable, executable)?;
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if writable {flags |= PageTableFlags::WRITABLE;}
+        if !executable {flags |= PageTableFlags::NO_EXECUTE;}
+        for (i, &frame) in self.frames.frames().iter().enumerate()
+        {
+            let page = Page::containing_address(VirtAddr::new(start + i as u64 * 4096));
+            if space.map_shared(page, frame, flags).is_err()
+            {
+                //shared pages are only unmapped, their frames stay with the object
+                space.unmap(VirtAddr::new(start), i as u64 * 4096);
+                return Err(ErrorKind::OutOfMemory);
+            }
+        }
+        Ok(())
+    }
+}
\ No newline at end of file
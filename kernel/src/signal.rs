@@ -0,0 +1,210 @@
+//! POSIX-like signals, asynchronous notifications sent to processes.
+//!
+//! Signals without a handler take their default action as soon as they are sent, though a
+//! process that gets ended keeps running until none of its threads is inside the kernel. Signals with
+//! one stay pending until a thread of the process is about to resume in user mode, which then
+//! runs the handler on its own stack first. The handler returns through the trampoline, which
+//! restores the interrupted context with `signal_return`.
+//!
+//! Pending and blocked signals are tracked per process, whichever thread resumes next runs the
+//! handler and the signal stays blocked for every thread until it returns.
+
+use core::mem::size_of;
+use crate::abi_defs::{SIGKILL, SIGSEGV, SIGCHLD, SIGCONT, SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU, SIGURG, SIGWINCH, SIGNAL_COUNT, SIG_DFL, SIG_IGN};
+use crate::trap::TrapFrame;
+use crate::usermode::{USER_REGION_START, USER_REGION_END, SIGNAL_RETURN_ADDR, EXIT_STUB_ADDR, check_user_range, copy_value_to_user, UserValue};
+
+/// A process ended by signal `sig` exits with code `EXIT_SIGNALED + sig`.
+pub const EXIT_SIGNALED: i32 = 128;
+
+//signals that can be neither handled, ignored nor blocked
+const UNCATCHABLE: u32 = 1 << SIGKILL | 1 << SIGSTOP;
+//the flags a handler may change in the context it returns to, the carry, parity, adjust, zero,
+//sign, direction and overflow flags
+const USER_FLAGS: u64 = 0xDD5;
+//the reserved bit and the interrupt flag
+const FIXED_FLAGS: u64 = 0x202;
+//the System V red zone below the interrupted stack pointer is left alone
+const RED_ZONE: u64 = 128;
+
+/// What happens to a signal nobody installed a handler for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction
+{
+    Terminate,
+    Ignore,
+    Stop,
+    Continue
+}
+
+/// Returns the default action of a valid signal.
+pub fn default_action(sig: u8) -> DefaultAction
+{
+    match sig
+    {
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGCONT => DefaultAction::Continue,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        _ => DefaultAction::Terminate
+    }
+}
+
+pub fn valid(sig: u8) -> bool
+{
+    sig > 0 && sig < SIGNAL_COUNT
+}
+
+//pushed below the interrupted stack pointer while a handler runs
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SignalFrame
+{
+    context: TrapFrame,
+    blocked: u32,
+    _padding: u32
+}
+
+unsafe impl UserValue for SignalFrame {}
+
+/// What the sender of a signal has to do with the process after `SignalState::post`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Posted
+{
+    /// Nothing, the signal was discarded or changed whether the process is stopped.
+    Done,
+    /// End the process with the given code.
+    Terminate(i32),
+    /// The signal is pending, wake threads blocked in user requested waits so the handler runs soon.
+    Pending
+}
+
+/// Signal dispositions and pending signals of a process.
+#[derive(Clone)]
+pub struct SignalState
+{
+    //bit `sig` is set for every signal waiting for its handler
+    pending: u32,
+    //signals that stay pending until unblocked
+    blocked: u32,
+    handlers: [u64; SIGNAL_COUNT as usize],
+    /// Set by stop signals and cleared by `SIGCONT`, a stopped process is not scheduled.
+    pub stopped: bool
+}
+
+impl Default for SignalState
+{
+    fn default() -> Self
+    {
+        SignalState{pending: 0, blocked: 0, handlers: [SIG_DFL; SIGNAL_COUNT as usize], stopped: false}
+    }
+}
+
+impl SignalState
+{
+    /// Sets the handler of `sig` and returns the previous one, or `None` if the signal can't be handled.
+    ///
+    /// Ignoring a signal discards it if it is pending.
+    pub fn set_handler(&mut self, sig: u8, handler: u64) -> Option<u64>
+    {
+        if !valid(sig) || UNCATCHABLE & 1 << sig != 0 {return None;}
+        if handler == SIG_IGN || (handler == SIG_DFL && default_action(sig) == DefaultAction::Ignore) {self.pending &= !(1 << sig);}
+        Some(core::mem::replace(&mut self.handlers[sig as usize], handler))
+    }
+
+    /// Applies a signal sent to the process, `sig` has to be valid.
+    pub fn post(&mut self, sig: u8) -> Posted
+    {
+        //continuing happens even if the signal is handled or ignored
+        if sig == SIGCONT {self.stopped = false;}
+        match self.handlers[sig as usize]
+        {
+            SIG_IGN => Posted::Done,
+            SIG_DFL => match default_action(sig)
+            {
+                DefaultAction::Terminate => Posted::Terminate(EXIT_SIGNALED + sig as i32),
+                DefaultAction::Stop =>
+                {
+                    self.stopped = true;
+                    Posted::Done
+                },
+                DefaultAction::Ignore | DefaultAction::Continue => Posted::Done
+            },
+            _ =>
+            {
+                self.pending |= 1 << sig;
+                Posted::Pending
+            }
+        }
+    }
+
+    /// Returns true if a pending signal is not blocked, so `deliver` runs its handler.
+    pub fn deliverable(&self) -> bool
+    {
+        self.pending & !self.blocked != 0
+    }
+
+    /// Runs the handler of the lowest pending signal that is not blocked, by making `frame`
+    /// enter it once resumed.
+    ///
+    /// `frame` must be a user mode context of the process, whose address space has to be active.
+    pub fn deliver(&mut self, frame: &mut TrapFrame)
+    {
+        let ready = self.pending & !self.blocked;
+        if ready == 0 {return;}
+        let sig = ready.trailing_zeros() as u8;
+        self.pending &= !(1 << sig);
+        match self.handlers[sig as usize]
+        {
+            SIG_IGN => {},
+            //the handler was removed after the signal was sent, stopping is skipped then
+            SIG_DFL => if default_action(sig) == DefaultAction::Terminate {exit_from(frame, EXIT_SIGNALED + sig as i32)},
+            handler =>
+            {
+                let saved = SignalFrame{context: *frame, blocked: self.blocked, _padding: 0};
+                let frame_addr = (frame.rsp.wrapping_sub(RED_ZONE + size_of::<SignalFrame>() as u64)) & !0xF;
+                //the handler is entered like a call, with the return address on top of the stack
+                let rsp = frame_addr.wrapping_sub(8);
+                if copy_value_to_user(frame_addr, &saved).is_err() || copy_value_to_user(rsp, &SIGNAL_RETURN_ADDR).is_err()
+                {
+                    exit_from(frame, EXIT_SIGNALED + SIGSEGV as i32);
+                    return;
+                }
+                self.blocked |= 1 << sig;
+                frame.rip = handler;
+                frame.rsp = rsp;
+                frame.rdi = sig as u64;
+                //functions expect the direction flag to be clear
+                frame.rflags &= !0x400;
+            }
+        }
+    }
+
+    /// Restores the context a handler interrupted from the signal frame at `frame.rsp`, which is
+    /// where the stack pointer is once the handler returned.
+    ///
+    /// Returns false if there is no valid signal frame. The address space of the process has to be active.
+    pub fn restore(&mut self, frame: &mut TrapFrame) -> bool
+    {
+        let addr = frame.rsp;
+        if !check_user_range(addr, size_of::<SignalFrame>() as u64, false) {return false;}
+        let saved = unsafe{(addr as *const SignalFrame).read_unaligned()};
+        let c = &saved.context;
+        //the segments stay those of user mode and the addresses have to be user ones, so iretq can't fault in the kernel
+        let user = |addr: u64| (USER_REGION_START..USER_REGION_END).contains(&addr);
+        if !user(c.rip) || !user(c.rsp) {return false;}
+        let (cs, ss) = (frame.cs, frame.ss);
+        *frame = *c;
+        frame.cs = cs;
+        frame.ss = ss;
+        frame.rflags = c.rflags & USER_FLAGS | FIXED_FLAGS;
+        self.blocked = saved.blocked & !UNCATCHABLE;
+        true
+    }
+}
+
+/// Makes the user mode context `frame` exit the process with `code` once resumed.
+pub fn exit_from(frame: &mut TrapFrame, code: i32)
+{
+    frame.rip = EXIT_STUB_ADDR;
+    frame.rdi = code as u64;
+}
\ No newline at end of file
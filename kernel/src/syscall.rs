@@ -1,55 +1,105 @@
+use alloc::vec;
 use alloc::vec::Vec;
 use alloc::string::String;
-use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::format;
 use embedded_io::ErrorKind;
 use crate::fs::{FILESYSTEM, FileSystem, FilePermissions};
-use crate::proc_watch::{PROCESS_QUEUE, RUNNING_PROCESS, find};
+use crate::handle::Object;
+use crate::endpoint::{Message, MessageInfo, MAX_MESSAGE_SIZE};
+use crate::pipe::PIPE_CAPACITY;
+use crate::shm::{SharedMemory, DEFAULT_SHARED_MODE};
+use crate::abi_defs::{MODE_READ, MODE_WRITE, PERMS_SIZE, LEGACY_PERMS_SIZE, RIGHT_READ, RIGHT_WRITE, RIGHT_SIGNAL, RIGHT_KILL, RIGHT_TRANSFER, NO_HANDLE, SIG_DFL, SIG_IGN};
+use alloc::sync::Arc;
+use crate::elf::{ElfSource, LoadError};
+use crate::proc::{Credentials, Process, LegacyProcessInfo, ProcessInfo, ProcessStats, ResourceLimits, Thread, PRIORITY_LEVELS, EXIT_KILLED, MAX_START_DATA, start_data_size, thread_region_size};
+use crate::proc_watch::{PROCESS_QUEUE, RUNNING_PROCESS, KERNEL_PID, find};
 use core::sync::atomic::Ordering;
-use core::ffi::{CStr, c_char, c_uchar, c_schar, c_longlong, c_ulonglong};
+use crate::syscall_abi::{MAX_PATH_LEN, THREAD_EXIT, THREAD_YIELD, SLEEP, FUTEX_WAIT};
+use crate::usermode::{USER_REGION_START, USER_REGION_END, SYSCALL_VECTOR, check_user_range, copy_from_user, copy_to_user, copy_value_to_user, copy_str_from_user, UserValue};
+use core::mem::size_of;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::structures::paging::mapper::MapToError;
+use core::ffi::{c_char, c_uchar, c_schar, c_int, c_uint, c_longlong, c_ulonglong};
 use chrono::{NaiveDateTime, NaiveDate, NaiveTime};
 
+/// Most bytes a file write from a process can carry, they are copied to the kernel heap first.
+pub const MAX_FILE_WRITE: u64 = 16 * 1024;
+
 #[repr(i8)]
 pub enum ProcessError
 {
     Unprivileged,
-    NotFound
+    NotFound,
+    InvalidInput,
+    /// The executable or a library it needs could not be read from the filesystem.
+    ReadFailed,
+    /// The executable is not a valid ELF file for this machine.
+    InvalidExecutable,
+    /// There was not enough memory to load the executable.
+    OutOfMemory,
+    /// The caller has as many uncollected children as its limits allow.
+    LimitExceeded,
+    /// A signal arrived while the call was blocked.
+    Interrupted
 }
 
+/// Result of a syscall that returns data into a buffer owned by the caller.
+///
+/// `ptr` is the caller's buffer and `len` the full size of the data, which can be larger than
+/// the buffer. In that case only the first part was copied, so calling with an empty buffer
+/// queries the size to allocate.
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct CVecShort
 {
     res: c_char,
+    _padding: [u8; 7],
     ptr: *const c_uchar,
     len: c_ulonglong
 }
 
-impl From<Result<Vec<u8>, ErrorKind>> for CVecShort
+unsafe impl UserValue for CVecShort {}
+
+impl CVecShort
 {
-    fn from(input: Result<Vec<u8>, ErrorKind>) -> Self
+    /// Copies as much of `input` as fits into the caller's buffer.
+    fn copy_out(input: Result<Vec<u8>, ErrorKind>, buf_ptr: *mut c_uchar, buf_len: c_ulonglong) -> Self
     {
-        let res = ffi_errorkind_res(input.as_ref().map(|_| ()).map_err(|e| e.to_owned().to_owned()));
-        let mut vec = Vec::new();
-        if input.is_ok()
+        let copied = input.and_then(|data|
         {
-            vec.append(&mut input.unwrap());
-        }
-        let ptr = vec.as_ptr();
-        let len: c_ulonglong = vec.len().try_into().unwrap();
-        CVecShort{res, ptr, len}
+            let n = data.len().min(buf_len as usize);
+            copy_to_user(buf_ptr as u64, &data[..n]).map_err(|_| ErrorKind::InvalidInput)?;
+            Ok(data.len())
+        });
+        let len = *copied.as_ref().unwrap_or(&0) as c_ulonglong;
+        CVecShort{res: ffi_errorkind_res(copied.map(|_| ())), _padding: [0; 7], ptr: buf_ptr, len}
     }
 }
 
+//kernel code calling the natives directly runs as root
+fn caller_credentials() -> Credentials
+{
+    if RUNNING_PROCESS.load(Ordering::Relaxed) == KERNEL_PID {return Credentials::ROOT;}
+    x86_64::instructions::interrupts::without_interrupts(||
+    {
+        let pq = PROCESS_QUEUE.lock();
+        let i = find(pq.iter(), RUNNING_PROCESS.load(Ordering::Relaxed)).unwrap();
+        pq.get(i).unwrap().cred
+    })
+}
+
 fn check_privilege() -> bool
 {
-    let pq = PROCESS_QUEUE.lock();
-    let i = find(pq.iter(), RUNNING_PROCESS.load(Ordering::Relaxed)).unwrap();
-    pq.get(i).unwrap().privileged
+    caller_credentials().is_root()
 }
 
-fn ffi_str_from_ptr(ptr: *const c_char) -> String
+//copies a path out of the calling process
+fn ffi_str_from_ptr(ptr: *const c_char) -> Result<String, ErrorKind>
 {
-    let c = unsafe{CStr::from_ptr(ptr)};
-    c.to_str().unwrap().to_owned()
+    let bytes = copy_str_from_user(ptr as u64, MAX_PATH_LEN).map_err(|_| ErrorKind::InvalidInput)?;
+    String::from_utf8(bytes).map_err(|_| ErrorKind::InvalidData)
 }
 
 fn ffi_errorkind_res(res: Result<(), ErrorKind>) -> c_char
@@ -58,10 +108,87 @@ fn ffi_errorkind_res(res: Result<(), ErrorKind>) -> c_char
     else {res.unwrap_err() as i8}
 }
 
-fn ffi_byte_slice_from_parts(ptr: *const u8, len: c_ulonglong) -> Vec<u8>
+fn ffi_process_res(res: Result<(), ProcessError>) -> c_schar
+{
+    match res
+    {
+        Ok(()) => -1,
+        Err(e) => e as i8
+    }
+}
+
+//copies as much of `list` as fits into the caller's buffer of `buf_len` entries and returns the full length
+fn ffi_copy_list<T: UserValue>(list: &[T], buf_ptr: *mut T, buf_len: c_ulonglong) -> c_ulonglong
+{
+    let n = list.len().min(buf_len as usize);
+    let bytes = unsafe{core::slice::from_raw_parts(list.as_ptr() as *const u8, n * size_of::<T>())};
+    if copy_to_user(buf_ptr as u64, bytes).is_err() {return c_ulonglong::MAX;}
+    list.len() as c_ulonglong
+}
+
+fn ffi_byte_slice_from_parts(ptr: *const u8, len: c_ulonglong) -> Result<Vec<u8>, ErrorKind>
+{
+    copy_from_user(ptr as u64, len).map_err(|_| ErrorKind::InvalidInput)
+}
+
+//splits a block of nul terminated strings, like the ones proc_args returns
+fn ffi_strings_from_parts(ptr: *const c_char, len: c_ulonglong) -> Result<Vec<String>, ProcessError>
+{
+    if len > MAX_START_DATA as u64 {return Err(ProcessError::InvalidInput);}
+    let bytes = ffi_byte_slice_from_parts(ptr as *const u8, len).map_err(|_| ProcessError::InvalidInput)?;
+    match bytes.split_last()
+    {
+        None => Ok(Vec::new()),
+        Some((0, strings)) => strings.split(|&b| b == 0).map(|s| String::from_utf8(s.to_vec()).map_err(|_| ProcessError::InvalidInput)).collect(),
+        Some(_) => Err(ProcessError::InvalidInput)
+    }
+}
+
+//a null pointer means the caller's environment gets inherited
+fn ffi_env_from_parts(ptr: *const c_char, len: c_ulonglong) -> Result<Option<BTreeMap<String, String>>, ProcessError>
 {
-    assert!(!ptr.is_null());
-    unsafe{core::slice::from_raw_parts(ptr, len.try_into().unwrap())}.to_vec()
+    if ptr.is_null() {return Ok(None);}
+    let mut env = BTreeMap::new();
+    for var in ffi_strings_from_parts(ptr, len)?
+    {
+        let (key, value) = var.split_once('=').filter(|(key, _)| !key.is_empty()).ok_or(ProcessError::InvalidInput)?;
+        env.insert(key.into(), value.into());
+    }
+    Ok(Some(env))
+}
+
+fn nul_separated(strings: impl Iterator<Item = String>) -> Vec<u8>
+{
+    let mut data = Vec::new();
+    for s in strings
+    {
+        data.extend_from_slice(s.as_bytes());
+        data.push(0);
+    }
+    data
+}
+
+//the arguments and environment of the caller, the kernel itself has none
+fn caller_start_data() -> (Vec<String>, BTreeMap<String, String>)
+{
+    crate::proc_watch::start_data(proc_pid()).unwrap_or_default()
+}
+
+fn ffi_check_out<T>(ptr: *mut T) -> Result<(), ProcessError>
+{
+    if check_user_range(ptr as u64, size_of::<T>() as u64, true) {Ok(())}
+    else {Err(ProcessError::InvalidInput)}
+}
+
+fn ffi_read_value<T: Copy>(ptr: *const T) -> Result<T, ProcessError>
+{
+    let bytes = copy_from_user(ptr as u64, size_of::<T>() as u64).map_err(|_| ProcessError::InvalidInput)?;
+    Ok(unsafe{(bytes.as_ptr() as *const T).read_unaligned()})
+}
+
+fn ffi_write_value<T: UserValue>(ptr: *mut T, value: &T) -> Result<(), ProcessError>
+{
+    copy_value_to_user(ptr as u64, value).map_err(|_| ProcessError::InvalidInput)
 }
 
 pub fn file_read_perms(path: &str) -> Result<FilePermissions, ErrorKind>
@@ -71,27 +198,37 @@ pub fn file_read_perms(path: &str) -> Result<FilePermissions, ErrorKind>
 
 pub fn file_write_perms(path: &str, perms: FilePermissions) -> Result<(), ErrorKind>
 {
-    FILESYSTEM.lock().write_perms_checked(path, perms, check_privilege())
+    FILESYSTEM.lock().write_perms_checked(path, perms, &caller_credentials())
+}
+
+/// Changes the permissions of a file as the layout of `FilePermissions::to_legacy_arr` says.
+pub fn file_write_legacy_perms(path: &str, legacy: &[u8]) -> Result<(), ErrorKind>
+{
+    let mut fs = FILESYSTEM.lock();
+    let perms = fs.get_perms(path)?.with_legacy(legacy);
+    fs.write_perms_checked(path, perms, &caller_credentials())
 }
 
 pub fn file_read(path: &str) -> Result<Vec<u8>, ErrorKind>
 {
-    FILESYSTEM.lock().read_checked(path, check_privilege())
+    FILESYSTEM.lock().read_checked(path, &caller_credentials())
 }
 
 pub fn file_write(path: &str, data: &[u8]) -> Result<(), ErrorKind>
 {
-    FILESYSTEM.lock().write_checked(path, data, check_privilege())
+    FILESYSTEM.lock().write_checked(path, data, &caller_credentials())?;
+    crate::linker::forget(path);
+    Ok(())
 }
 
 pub fn file_delete(path: &str) -> Result<(), ErrorKind>
 {
-    FILESYSTEM.lock().delete_checked(path, check_privilege())
+    FILESYSTEM.lock().delete_checked(path, &caller_credentials())
 }
 
 pub fn file_create(path: &str) -> Result<(), ErrorKind>
 {
-    FILESYSTEM.lock().create(path)
+    FILESYSTEM.lock().create_checked(path, &caller_credentials())
 }
 
 pub fn time_now() -> i64
@@ -106,9 +243,84 @@ pub fn rand_buffer(buf: &mut [u8])
     if c.is_err() {crate::rand::rand_lq(buf);}
 }
 
-pub fn proc_spawn(buf: &[u8])
+/// Starts a process from an ELF image and returns its pid, it runs with the identities of the caller.
+///
+/// The arguments and environment have to fit in `MAX_START_DATA`.
+pub fn proc_spawn(mut buf: &[u8], args: Vec<String>, env: BTreeMap<String, String>) -> Result<u64, ProcessError>
 {
-    crate::proc_watch::spawn(crate::proc::Process::spawn(buf, true));
+    spawn_as(&mut buf, args, env, caller_credentials())
+}
+
+fn spawn_as(source: &mut impl ElfSource, args: Vec<String>, env: BTreeMap<String, String>, cred: Credentials) -> Result<u64, ProcessError>
+{
+    if start_data_size(&args, &env) > MAX_START_DATA {return Err(ProcessError::InvalidInput);}
+    let mut proc = match Process::spawn(source, args, env, cfg!(debug_assertions))
+    {
+        Ok(proc) => proc,
+        Err(LoadError::OutOfMemory) => return Err(ProcessError::OutOfMemory),
+        Err(LoadError::LibraryNotFound | LoadError::ReadFailed) => return Err(ProcessError::ReadFailed),
+        Err(_) => return Err(ProcessError::InvalidExecutable)
+    };
+    proc.cred = cred;
+    proc.handles = crate::proc_watch::with_handles(proc_pid(), |handles| handles.inherited()).unwrap_or_default();
+    let limits = crate::proc_watch::limits(proc_pid()).map_or(ResourceLimits::UNLIMITED, |(_, child)| child);
+    proc.limits = limits;
+    proc.child_limits = limits;
+    proc.handles.set_limit(limits.handles);
+    let pid = proc.pid;
+    match crate::proc_watch::spawn_child(proc)
+    {
+        None => Ok(pid),
+        //freed here, the caller can't have another child
+        Some(_) => Err(ProcessError::LimitExceeded)
+    }
+}
+
+/// Starts the program at `path` with its path as the only argument and the caller's environment.
+pub fn proc_spawn_path(path: &str) -> Result<u64, ProcessError>
+{
+    proc_spawn_args(path, vec![path.into()], None)
+}
+
+/// Starts the program at `path` with the given arguments, and the caller's environment unless one is given.
+///
+/// The caller needs read and execute permission. This is the loader that honors the setuid and
+/// setgid bits, the program runs as the owner or group of its file then, while images passed to
+/// `proc_spawn` always run with the caller's identities.
+pub fn proc_spawn_args(path: &str, args: Vec<String>, env: Option<BTreeMap<String, String>>) -> Result<u64, ProcessError>
+{
+    let env = match env
+    {
+        Some(env) => env,
+        None => caller_start_data().1
+    };
+    let cred = caller_credentials();
+    let res = FILESYSTEM.lock().load_checked(path, &cred);
+    match res
+    {
+        Ok((mut file, perms)) => spawn_as(&mut file, args, env, cred.exec(&perms)),
+        Err(ErrorKind::PermissionDenied) => Err(ProcessError::Unprivileged),
+        Err(ErrorKind::NotFound) => Err(ProcessError::NotFound),
+        Err(_) => Err(ProcessError::ReadFailed)
+    }
+}
+
+/// Returns the arguments of the calling process, each followed by a nul.
+pub fn proc_args() -> Vec<u8>
+{
+    nul_separated(caller_start_data().0.into_iter())
+}
+
+/// Returns the environment of the calling process as `KEY=VALUE` strings, each followed by a nul.
+pub fn proc_env() -> Vec<u8>
+{
+    nul_separated(caller_start_data().1.iter().map(|(key, value)| format!("{}={}", key, value)))
+}
+
+/// Returns the value of a variable in the environment of the calling process.
+pub fn proc_env_var(name: &str) -> Result<Vec<u8>, ErrorKind>
+{
+    caller_start_data().1.remove(name).map(String::into_bytes).ok_or(ErrorKind::NotFound)
 }
 
 pub fn proc_kill(pid: u64) -> Result<(), ProcessError>
@@ -121,49 +333,572 @@ pub fn proc_kill(pid: u64) -> Result<(), ProcessError>
     }
 }
 
-pub fn proc_kill_self() -> Result<(), ProcessError>
+/// Sends signal `sig` to a process.
+///
+/// Unprivileged processes can only signal themselves and their children. A signal the caller
+/// sends itself is handled before this returns, when called through the syscall.
+pub fn proc_signal(pid: u64, sig: u8) -> Result<(), ProcessError>
+{
+    if !crate::signal::valid(sig) {return Err(ProcessError::InvalidInput);}
+    let from = if check_privilege() {None} else {Some(proc_pid())};
+    crate::proc_watch::signal(pid, sig, from)
+}
+
+/// Sets the handler the calling process runs for signal `sig` and returns the previous one.
+///
+/// `handler` is `SIG_DFL`, `SIG_IGN` or the address of a function taking the signal number,
+/// `SIGKILL` and `SIGSTOP` can't be handled.
+pub fn signal_action(sig: u8, handler: u64) -> Result<u64, ProcessError>
+{
+    if handler != SIG_DFL && handler != SIG_IGN && !(USER_REGION_START..USER_REGION_END).contains(&handler) {return Err(ProcessError::InvalidInput);}
+    crate::proc_watch::set_signal_handler(proc_pid(), sig, handler)
+}
+
+/// Returns the resource limits of a process.
+pub fn proc_limits(pid: u64) -> Result<ResourceLimits, ProcessError>
+{
+    crate::proc_watch::limits(pid).map(|(limits, _)| limits).ok_or(ProcessError::NotFound)
+}
+
+/// Sets the resource limits children the calling process spawns from now on start with, they
+/// start with its own limits otherwise.
+///
+/// Unprivileged processes can't give children more than they have themselves, larger limits
+/// are lowered to their own.
+pub fn proc_set_child_limits(limits: ResourceLimits) -> Result<(), ProcessError>
+{
+    let pid = proc_pid();
+    let (own, _) = crate::proc_watch::limits(pid).ok_or(ProcessError::NotFound)?;
+    let limits = if check_privilege() {limits} else {limits.min(own)};
+    if crate::proc_watch::set_child_limits(pid, limits) {Ok(())}
+    else {Err(ProcessError::NotFound)}
+}
+
+/// Returns the user and group identities of a process.
+pub fn proc_ids(pid: u64) -> Result<Credentials, ProcessError>
+{
+    crate::proc_watch::credentials(pid).ok_or(ProcessError::NotFound)
+}
+
+/// Sets the real and effective user and group of the calling process.
+///
+/// Root can switch to any ids, everyone else only to their current real or effective ones, so a
+/// setuid program can drop back to the user that started it.
+pub fn proc_set_ids(uid: u32, gid: u32) -> Result<(), ProcessError>
+{
+    if !caller_credentials().may_become(uid, gid) {return Err(ProcessError::Unprivileged);}
+    if crate::proc_watch::set_credentials(proc_pid(), Credentials::new(uid, gid)) {Ok(())}
+    else {Err(ProcessError::NotFound)}
+}
+
+/// Ends the calling process with the given exit code.
+pub(crate) fn proc_exit(code: i32) -> !
+{
+    not_kernel("proc_exit");
+    unsafe{core::arch::asm!("int {vector}", vector = const crate::usermode::EXIT_VECTOR, in("rdi") code as i64, options(noreturn))}
+}
+
+/// Blocks until a child of the calling process has exited and returns its pid and exit code.
+///
+/// Fails with `ProcessError::Interrupted` if a signal arrives first.
+pub fn proc_wait() -> Result<(u64, i32), ProcessError>
+{
+    let parent = RUNNING_PROCESS.load(Ordering::Relaxed);
+    crate::proc_watch::wait_child(parent, None)
+}
+
+/// Blocks until the given child of the calling process has exited and returns its exit code.
+///
+/// Fails with `ProcessError::Interrupted` if a signal arrives first.
+pub fn proc_waitpid(pid: u64) -> Result<i32, ProcessError>
+{
+    let parent = RUNNING_PROCESS.load(Ordering::Relaxed);
+    crate::proc_watch::wait_child(parent, Some(pid)).map(|(_, code)| code)
+}
+
+pub(crate) fn proc_kill_self() -> Result<(), ProcessError>
+{
+    //exiting right away instead of marking the process keeps it from running out its time slice
+    proc_exit(EXIT_KILLED)
+}
+
+pub fn proc_pid() -> u64
+{
+    RUNNING_PROCESS.load(Ordering::Relaxed)
+}
+
+pub fn proc_list() -> Vec<ProcessInfo>
+{
+    crate::proc_watch::list()
+}
+
+pub fn proc_set_priority(pid: u64, priority: u8) -> Result<(), ProcessError>
+{
+    if !check_privilege() {Err(ProcessError::Unprivileged)}
+    else if priority >= PRIORITY_LEVELS {Err(ProcessError::InvalidInput)}
+    else
+    {
+        if crate::proc_watch::set_priority(pid, priority) {Ok(())}
+        else {Err(ProcessError::NotFound)}
+    }
+}
+
+pub fn proc_set_time_slice(ticks: u64) -> Result<(), ProcessError>
+{
+    if !check_privilege() {Err(ProcessError::Unprivileged)}
+    else if ticks == 0 {Err(ProcessError::InvalidInput)}
+    else
+    {
+        crate::proc_watch::set_time_slice(ticks);
+        Ok(())
+    }
+}
+
+pub fn proc_stats(pid: u64) -> Result<ProcessStats, ProcessError>
+{
+    crate::proc_watch::stats(pid).ok_or(ProcessError::NotFound)
+}
+
+/// Starts a thread in the calling process that calls `entry(arg)` and returns its id.
+///
+/// The thread shares the address space of the process but gets its own stack and TLS area,
+/// returning from `entry` ends it like `thread_exit` does.
+pub fn thread_create(entry: u64, arg: u64) -> Result<u64, ProcessError>
+{
+    if !(USER_REGION_START..USER_REGION_END).contains(&entry) {return Err(ProcessError::InvalidInput);}
+    let pid = proc_pid();
+    let tls = crate::proc_watch::tls_layout(pid).ok_or(ProcessError::NotFound)?;
+    let size = thread_region_size(&tls);
+    let (space, start) = crate::proc_watch::reserve_anon(pid, None, size).map_err(|_| ProcessError::OutOfMemory)?;
+    let thread = match Thread::new(&space, &tls, start, entry, arg)
+    {
+        Ok(thread) => thread,
+        Err(_) =>
+        {
+            space.unmap(VirtAddr::new(start), size);
+            crate::proc_watch::release_anon(pid, size);
+            return Err(ProcessError::OutOfMemory);
+        }
+    };
+    crate::proc_watch::add_thread(pid, thread).map_err(|thread|
+    {
+        thread.release(&space);
+        ProcessError::NotFound
+    })
+}
+
+/// Blocks until the given thread of the calling process has ended and returns the value it ended with.
+///
+/// Each thread can only be joined once, and not by itself. Fails with `ProcessError::Interrupted`
+/// if a signal arrives first.
+pub fn thread_join(tid: u64) -> Result<u64, ProcessError>
 {
-    let pid = RUNNING_PROCESS.load(Ordering::Relaxed);
-    if crate::proc_watch::remove(pid) {Ok(())}
+    if tid == thread_id() {return Err(ProcessError::InvalidInput);}
+    crate::proc_watch::join_thread(proc_pid(), tid)
+}
+
+/// Ends the calling thread with a value for the thread joining it, the process exits with
+/// code 0 if it was the last one.
+pub(crate) fn thread_exit(value: u64) -> !
+{
+    not_kernel("thread_exit");
+    unsafe{core::arch::asm!("int {vector}", vector = const SYSCALL_VECTOR, in("rax") THREAD_EXIT, in("rdi") value, options(noreturn))}
+}
+
+//the exit syscalls switch away for good, but the kernel has no queue entry to end, so it would be
+//resumed after the `int` and return from a function that never returns
+fn not_kernel(name: &str)
+{
+    if RUNNING_PROCESS.load(Ordering::Relaxed) == KERNEL_PID {panic!("{} called by the kernel, it can only end processes", name);}
+}
+
+/// Lets other threads run before the calling thread continues.
+pub fn thread_yield()
+{
+    unsafe{core::arch::asm!("int {vector}", vector = const SYSCALL_VECTOR, inlateout("rax") THREAD_YIELD => _)}
+}
+
+/// Returns the id of the calling thread, the first thread of a process is 0.
+pub fn thread_id() -> u64
+{
+    crate::proc_watch::current_thread(proc_pid()).unwrap_or(0)
+}
+
+/// Blocks the calling thread for at least `ms` milliseconds.
+///
+/// Sleeps end on timer ticks, which come about every 55 milliseconds.
+pub fn sleep(ms: u64)
+{
+    unsafe{core::arch::asm!("int {vector}", vector = const SYSCALL_VECTOR, inlateout("rax") SLEEP => _, in("rdi") ms)}
+}
+
+/// Blocks the calling thread until another thread wakes the futex at `addr`, unless it no
+/// longer holds `expected`.
+///
+/// This can also return without a wake, so callers have to check the value again.
+pub fn futex_wait(addr: u64, expected: u32) -> Result<(), ErrorKind>
+{
+    let res: u64;
+    unsafe{core::arch::asm!("int {vector}", vector = const SYSCALL_VECTOR, inlateout("rax") FUTEX_WAIT => res, in("rdi") addr, in("rsi") expected as u64)};
+    if res as i8 == -1 {Ok(())}
+    else {Err(ErrorKind::InvalidInput)}
+}
+
+/// Wakes up to `count` threads of the calling process waiting on the futex at `addr` and
+/// returns how many were woken.
+pub fn futex_wake(addr: u64, count: u64) -> u64
+{
+    crate::proc_watch::futex_wake(proc_pid(), addr, count)
+}
+
+//adds objects with the given rights to the handle table of the calling process, none of them if
+//that would exceed its handle limit, which fails with `ErrorKind::OutOfMemory`
+fn insert_handles<const N: usize>(objects: [(Object, u32); N]) -> Result<[u64; N], ErrorKind>
+{
+    let mut objects = Some(objects);
+    let res = crate::proc_watch::with_handles(proc_pid(), |handles|
+    {
+        if !handles.has_room(N as u64) {return None;}
+        objects.take().map(|objects| objects.map(|(object, rights)| handles.insert(object, rights)))
+    });
+    //objects that were not added are dropped here, with the process queue unlocked
+    drop(objects);
+    res.ok_or(ErrorKind::InvalidInput)?.ok_or(ErrorKind::OutOfMemory)
+}
+
+//the object behind a handle of the calling process and the rights the handle has
+fn handle_entry(handle: u64) -> Option<(Object, u32)>
+{
+    crate::proc_watch::with_handles(proc_pid(), |handles| handles.get(handle).map(|(object, rights)| (object.clone(), rights))).flatten()
+}
+
+//the object behind a handle of the calling process, which needs all of `rights`
+fn handle_object(handle: u64, rights: u32) -> Result<Object, ErrorKind>
+{
+    let (object, have) = handle_entry(handle).ok_or(ErrorKind::InvalidInput)?;
+    if rights & !have != 0 {return Err(ErrorKind::PermissionDenied);}
+    Ok(object)
+}
+
+/// Returns the pid behind a process handle of the calling process, which needs all of `rights`.
+pub fn handle_pid(handle: u64, rights: u32) -> Result<u64, ProcessError>
+{
+    match handle_object(handle, rights)
+    {
+        Ok(Object::Process(pid)) => Ok(pid),
+        Err(ErrorKind::PermissionDenied) => Err(ProcessError::Unprivileged),
+        _ => Err(ProcessError::InvalidInput)
+    }
+}
+
+/// Creates a pipe and returns the handles of its read and write end.
+///
+/// Fails with `ErrorKind::OutOfMemory` if the process can't hold two more handles.
+pub fn pipe_create() -> Result<(u64, u64), ErrorKind>
+{
+    let (reader, writer) = crate::pipe::pipe();
+    let [read, write] = insert_handles([(Object::PipeReader(Arc::new(reader)), RIGHT_READ | RIGHT_TRANSFER), (Object::PipeWriter(Arc::new(writer)), RIGHT_WRITE | RIGHT_TRANSFER)])?;
+    Ok((read, write))
+}
+
+/// Blocks until the pipe has data and returns up to `len` bytes of it, nothing once the write
+/// end is closed and everything was read.
+pub fn pipe_read(handle: u64, len: usize) -> Result<Vec<u8>, ErrorKind>
+{
+    let Object::PipeReader(reader) = handle_object(handle, RIGHT_READ)? else {return Err(ErrorKind::InvalidInput)};
+    let mut buf = vec![0; len.min(PIPE_CAPACITY)];
+    let n = reader.read(&mut buf)?;
+    buf.truncate(n);
+    Ok(buf)
+}
+
+/// Writes all of `data` to the pipe, blocking while its buffer is full.
+pub fn pipe_write(handle: u64, data: &[u8]) -> Result<(), ErrorKind>
+{
+    let Object::PipeWriter(writer) = handle_object(handle, RIGHT_WRITE)? else {return Err(ErrorKind::InvalidInput)};
+    writer.write(data)
+}
+
+/// Closes a handle of the calling process, the object stays alive while other handles refer to it.
+pub fn handle_close(handle: u64) -> Result<(), ErrorKind>
+{
+    //dropped here, with the process queue unlocked
+    let object = crate::proc_watch::with_handles(proc_pid(), |handles| handles.remove(handle)).flatten();
+    object.map(|_| ()).ok_or(ErrorKind::InvalidInput)
+}
+
+/// Sets whether children the calling process spawns afterwards get a copy of the handle, under the same number.
+///
+/// Only handles with `RIGHT_TRANSFER` can be inherited.
+pub fn handle_inherit(handle: u64, inherit: bool) -> Result<(), ErrorKind>
+{
+    if inherit {handle_object(handle, RIGHT_TRANSFER)?;}
+    let found = crate::proc_watch::with_handles(proc_pid(), |handles| handles.set_inherit(handle, inherit));
+    if found == Some(true) {Ok(())}
+    else {Err(ErrorKind::InvalidInput)}
+}
+
+/// Returns a new handle to the object of `handle` with `rights`, which can't include any the
+/// original lacks.
+pub fn handle_duplicate(handle: u64, rights: u32) -> Result<u64, ErrorKind>
+{
+    let (object, have) = handle_entry(handle).ok_or(ErrorKind::InvalidInput)?;
+    if rights & !have != 0 {return Err(ErrorKind::PermissionDenied);}
+    insert_handles([(object, rights)]).map(|[handle]| handle)
+}
+
+/// Returns the rights of a handle of the calling process.
+pub fn handle_rights(handle: u64) -> Result<u32, ErrorKind>
+{
+    crate::proc_watch::with_handles(proc_pid(), |handles| handles.get(handle).map(|(_, rights)| rights)).flatten().ok_or(ErrorKind::InvalidInput)
+}
+
+/// Opens a handle to the file at `path` with `rights`, made of `RIGHT_READ`, `RIGHT_WRITE` and
+/// `RIGHT_TRANSFER`.
+///
+/// The identities of the caller are checked against the permissions of the file here, whoever
+/// holds the handle later can use it without them.
+pub fn file_open(path: &str, rights: u32) -> Result<u64, ErrorKind>
+{
+    if rights & !(RIGHT_READ | RIGHT_WRITE | RIGHT_TRANSFER) != 0 {return Err(ErrorKind::InvalidInput);}
+    let mut access = 0;
+    if rights & RIGHT_READ != 0 {access |= MODE_READ;}
+    if rights & RIGHT_WRITE != 0 {access |= MODE_WRITE;}
+    let file = FILESYSTEM.lock().open_checked(path, access, &caller_credentials())?;
+    insert_handles([(Object::File(Arc::new(file)), rights)]).map(|[handle]| handle)
+}
+
+/// Reads the file behind a handle with `RIGHT_READ`, fails with `ErrorKind::NotFound` once it was deleted.
+pub fn file_handle_read(handle: u64) -> Result<Vec<u8>, ErrorKind>
+{
+    let Object::File(file) = handle_object(handle, RIGHT_READ)? else {return Err(ErrorKind::InvalidInput)};
+    FILESYSTEM.lock().read_open(&file)
+}
+
+/// Replaces the contents of the file behind a handle with `RIGHT_WRITE`, fails with
+/// `ErrorKind::NotFound` once it was deleted.
+pub fn file_handle_write(handle: u64, data: &[u8]) -> Result<(), ErrorKind>
+{
+    let Object::File(file) = handle_object(handle, RIGHT_WRITE)? else {return Err(ErrorKind::InvalidInput)};
+    let cred = caller_credentials();
+    FILESYSTEM.lock().write_open(&file, data, &cred)?;
+    crate::linker::forget(&file.path);
+    Ok(())
+}
+
+/// Opens a handle to a process with `rights`, made of `RIGHT_SIGNAL`, `RIGHT_KILL` and
+/// `RIGHT_TRANSFER`.
+///
+/// Unless the caller runs as root it can only open itself and its children, but it can pass the
+/// handle on to processes that couldn't.
+pub fn proc_open(pid: u64, rights: u32) -> Result<u64, ProcessError>
+{
+    if rights & !(RIGHT_SIGNAL | RIGHT_KILL | RIGHT_TRANSFER) != 0 {return Err(ProcessError::InvalidInput);}
+    let info = crate::proc_watch::info(pid).ok_or(ProcessError::NotFound)?;
+    if !check_privilege() && pid != proc_pid() && info.parent != proc_pid() {return Err(ProcessError::Unprivileged);}
+    match insert_handles([(Object::Process(pid), rights)])
+    {
+        Ok([handle]) => Ok(handle),
+        Err(ErrorKind::OutOfMemory) => Err(ProcessError::LimitExceeded),
+        Err(_) => Err(ProcessError::InvalidInput)
+    }
+}
+
+/// Sends signal `sig` to the process behind a handle with `RIGHT_SIGNAL`, whatever the
+/// identities of the caller.
+pub fn proc_handle_signal(handle: u64, sig: u8) -> Result<(), ProcessError>
+{
+    if !crate::signal::valid(sig) {return Err(ProcessError::InvalidInput);}
+    crate::proc_watch::signal(handle_pid(handle, RIGHT_SIGNAL)?, sig, None)
+}
+
+/// Kills the process behind a handle with `RIGHT_KILL`.
+pub fn proc_handle_kill(handle: u64) -> Result<(), ProcessError>
+{
+    if crate::proc_watch::remove(handle_pid(handle, RIGHT_KILL)?) {Ok(())}
     else {Err(ProcessError::NotFound)}
 }
 
-pub extern "C" fn c_file_read_perms(path: *const c_char) -> CVecShort
+/// Registers an endpoint for the calling process under `name` and returns the handle it receives through.
+pub fn endpoint_register(name: &str) -> Result<u64, ErrorKind>
+{
+    let receiver = crate::endpoint::register(name, proc_pid())?;
+    insert_handles([(Object::Endpoint(Arc::new(receiver)), RIGHT_READ | RIGHT_TRANSFER)]).map(|[handle]| handle)
+}
+
+/// Sends a message to the endpoint registered under `name`, blocking while its queue is full.
+pub fn endpoint_send(name: &str, data: &[u8]) -> Result<(), ErrorKind>
+{
+    crate::endpoint::send(name, Message{sender: proc_pid(), data: data.to_vec(), handle: None})
+}
+
+/// Sends a message to the endpoint registered under `name` along with a copy of a handle that
+/// has `RIGHT_TRANSFER`, the receiver's copy gets the same rights.
+pub fn endpoint_send_handle(name: &str, data: &[u8], handle: u64) -> Result<(), ErrorKind>
+{
+    let (object, rights) = handle_entry(handle).ok_or(ErrorKind::InvalidInput)?;
+    if rights & RIGHT_TRANSFER == 0 {return Err(ErrorKind::PermissionDenied);}
+    crate::endpoint::send(name, Message{sender: proc_pid(), data: data.to_vec(), handle: Some((object, rights))})
+}
+
+/// Sends a message to the oldest endpoint the process `pid` still has registered.
+pub fn endpoint_send_pid(pid: u64, data: &[u8]) -> Result<(), ErrorKind>
 {
-    let path = &ffi_str_from_ptr(path);
-    let res = file_read_perms(path).map(|p| p.to_byte_arr().to_vec());
-    CVecShort::from(res)
+    crate::endpoint::send_pid(pid, Message{sender: proc_pid(), data: data.to_vec(), handle: None})
 }
 
+/// Returns the next message of the endpoint, waiting for one if `block` is set.
+pub fn endpoint_receive(handle: u64, block: bool) -> Result<Message, ErrorKind>
+{
+    let Object::Endpoint(receiver) = handle_object(handle, RIGHT_READ)? else {return Err(ErrorKind::InvalidInput)};
+    receiver.receive(block)
+}
+
+/// Like `endpoint_receive`, but adds the handle the message carries to the calling process and
+/// returns its number as well, `NO_HANDLE` if there is none.
+///
+/// Fails with `ErrorKind::OutOfMemory` without taking a message if the process can't hold another
+/// handle. Should another thread fill the table in the meantime, the carried handle is closed.
+pub fn endpoint_receive_handle(handle: u64, block: bool) -> Result<(Message, u64), ErrorKind>
+{
+    let room = crate::proc_watch::with_handles(proc_pid(), |handles| handles.has_room(1)).ok_or(ErrorKind::InvalidInput)?;
+    if !room {return Err(ErrorKind::OutOfMemory);}
+    let mut message = endpoint_receive(handle, block)?;
+    let received = match message.handle.take()
+    {
+        Some(entry) => insert_handles([entry]).map_or(NO_HANDLE, |[handle]| handle),
+        None => NO_HANDLE
+    };
+    Ok((message, received))
+}
+
+/// Creates a shared memory object of `pages` zeroed pages under `name`, owned by the calling
+/// process with the permission bits `mode`, maps it into the process and returns its address.
+pub fn shm_create(name: &str, pages: u64, mode: u16, writable: bool, executable: bool) -> Result<u64, ErrorKind>
+{
+    crate::shm::access(writable, executable)?;
+    shm_map_object(crate::shm::create(name, pages, mode, &caller_credentials())?, writable, executable)
+}
+
+/// Maps the shared memory object named `name` into the calling process and returns its address and size in pages.
+///
+/// Fails with `PermissionDenied` if the object's mode doesn't allow the process such a mapping.
+pub fn shm_map(name: &str, writable: bool, executable: bool) -> Result<(u64, u64), ErrorKind>
+{
+    let memory = crate::shm::open(name, crate::shm::access(writable, executable)?, &caller_credentials())?;
+    let pages = memory.pages();
+    shm_map_object(memory, writable, executable).map(|addr| (addr, pages))
+}
+
+fn shm_map_object(memory: Arc<SharedMemory>, writable: bool, executable: bool) -> Result<u64, ErrorKind>
+{
+    let size = memory.pages() * 4096;
+    let (space, start) = crate::proc_watch::reserve_anon(proc_pid(), None, size)?;
+    if let Err(e) = memory.map_into(&space, start, writable, executable)
+    {
+        crate::proc_watch::release_anon(proc_pid(), size);
+        return Err(e);
+    }
+    //the object is dropped here if the process is gone, its address space with it
+    crate::proc_watch::add_shared(proc_pid(), start, memory).map_err(|_| ErrorKind::InvalidInput)?;
+    Ok(start)
+}
+
+/// Unmaps the shared memory object mapped at `addr` from the calling process, it is freed if
+/// no process has it mapped anymore.
+pub fn shm_unmap(addr: u64) -> Result<(), ErrorKind>
+{
+    let memory = crate::proc_watch::remove_shared(proc_pid(), addr).ok_or(ErrorKind::InvalidInput)?;
+    let space = crate::proc_watch::space(proc_pid()).ok_or(ErrorKind::InvalidInput)?;
+    space.unmap(VirtAddr::new(addr), memory.pages() * 4096);
+    crate::proc_watch::release_anon(proc_pid(), memory.pages() * 4096);
+    Ok(())
+}
+
+/// Maps `pages` zeroed pages into the calling process, at `addr` unless it is 0, and returns their address.
+///
+/// Fails with `ErrorKind::OutOfMemory` if that would exceed the page limit of the process.
+pub fn mem_map(addr: u64, pages: u64) -> Result<u64, ErrorKind>
+{
+    let size = pages.checked_mul(4096).filter(|&size| size > 0).ok_or(ErrorKind::InvalidInput)?;
+    if addr % 4096 != 0 {return Err(ErrorKind::InvalidInput);}
+    let hint = if addr == 0 {None} else {Some(addr)};
+    let (space, start) = crate::proc_watch::reserve_anon(proc_pid(), hint, size)?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+    let res = match space.map_unused(VirtAddr::new(start), size, flags)
+    {
+        Ok(()) =>
+        {
+            crate::proc_watch::add_mapping(proc_pid(), start, size);
+            return Ok(start);
+        },
+        Err(MapToError::PageAlreadyMapped(_)) => Err(ErrorKind::InvalidInput),
+        Err(_) =>
+        {
+            space.unmap(VirtAddr::new(start), size);
+            Err(ErrorKind::OutOfMemory)
+        }
+    };
+    crate::proc_watch::release_anon(proc_pid(), size);
+    res
+}
+
+/// Unmaps pages of the calling process that were mapped by `mem_map`.
+///
+/// Fails with `ErrorKind::InvalidInput` if any of them was not mapped by `mem_map` or is unmapped already.
+pub fn mem_unmap(addr: u64, pages: u64) -> Result<(), ErrorKind>
+{
+    let size = pages.checked_mul(4096).ok_or(ErrorKind::InvalidInput)?;
+    if addr % 4096 != 0 {return Err(ErrorKind::InvalidInput);}
+    if size == 0 {return Ok(());}
+    let space = crate::proc_watch::space(proc_pid()).ok_or(ErrorKind::InvalidInput)?;
+    if !crate::proc_watch::remove_mapping(proc_pid(), addr, size) {return Err(ErrorKind::InvalidInput);}
+    space.unmap(VirtAddr::new(addr), size);
+    Ok(())
+}
+
+/// Returns the permissions in the layout from before files had owners, `c_file_read_mode`
+/// returns all of them.
+pub extern "C" fn c_file_read_perms(path: *const c_char, buf_ptr: *mut c_uchar, buf_len: c_ulonglong) -> CVecShort
+{
+    let res = ffi_str_from_ptr(path).and_then(|path| file_read_perms(&path)).map(|p| p.to_legacy_arr().to_vec());
+    CVecShort::copy_out(res, buf_ptr, buf_len)
+}
+
+/// Takes the permissions in the layout from before files had owners, `c_file_write_mode` takes
+/// all of them.
 pub extern "C" fn c_file_write_perms(path: *const c_char, perms_buf_ptr: *const c_uchar, perms_buf_len: c_ulonglong) -> c_schar
 {
-    let path = &ffi_str_from_ptr(path);
-    ffi_errorkind_res(file_write_perms(path, FilePermissions::from_byte_slice(&ffi_byte_slice_from_parts(perms_buf_ptr, perms_buf_len))).map(|_| ()))
+    if perms_buf_len != LEGACY_PERMS_SIZE as u64 {return ErrorKind::InvalidInput as c_schar;}
+    let res = ffi_str_from_ptr(path).and_then(|path|
+    {
+        let perms = ffi_byte_slice_from_parts(perms_buf_ptr, perms_buf_len)?;
+        file_write_legacy_perms(&path, &perms)
+    });
+    ffi_errorkind_res(res)
 }
 
-pub extern "C" fn c_file_read(path: *const c_char) -> CVecShort
+pub extern "C" fn c_file_read(path: *const c_char, buf_ptr: *mut c_uchar, buf_len: c_ulonglong) -> CVecShort
 {
-    let path = &ffi_str_from_ptr(path);
-    let res = file_read(path);
-    CVecShort::from(res)
+    let res = ffi_str_from_ptr(path).and_then(|path| file_read(&path));
+    CVecShort::copy_out(res, buf_ptr, buf_len)
 }
 
 pub extern "C" fn c_file_write(path: *const c_char, data_buf_ptr: *const c_uchar, data_buf_len: c_ulonglong) -> c_schar
 {
-    let path = &ffi_str_from_ptr(path);
-    ffi_errorkind_res(file_write(path, &ffi_byte_slice_from_parts(data_buf_ptr, data_buf_len)).map(|_| ()))
+    if data_buf_len > MAX_FILE_WRITE {return ErrorKind::InvalidInput as c_schar;}
+    let res = ffi_str_from_ptr(path).and_then(|path| file_write(&path, &ffi_byte_slice_from_parts(data_buf_ptr, data_buf_len)?));
+    ffi_errorkind_res(res)
 }
 
 pub extern "C" fn c_file_delete(path: *const c_char) -> c_schar
 {
-    let path = &ffi_str_from_ptr(path);
-    ffi_errorkind_res(file_delete(path).map(|_| ()))
+    ffi_errorkind_res(ffi_str_from_ptr(path).and_then(|path| file_delete(&path)))
 }
 
 pub extern "C" fn c_file_create(path: *const c_char) -> c_schar
 {
-    let path = &ffi_str_from_ptr(path);
-    ffi_errorkind_res(file_create(path).map(|_| ()))
+    ffi_errorkind_res(ffi_str_from_ptr(path).and_then(|path| file_create(&path)))
 }
 
 pub extern "C" fn c_time_now() -> c_longlong
@@ -171,7 +906,399 @@ pub extern "C" fn c_time_now() -> c_longlong
     time_now()
 }
 
-pub extern "C" fn c_rand_buffer(buf_ptr: *mut c_uchar, buf_len: c_ulonglong)
+pub extern "C" fn c_rand_buffer(buf_ptr: *mut c_uchar, buf_len: c_ulonglong) -> c_schar
+{
+    if !check_user_range(buf_ptr as u64, buf_len, true) {return ErrorKind::InvalidInput as i8;}
+    //filling in chunks keeps a huge request from allocating a kernel buffer of the same size
+    let mut chunk = [0u8; 256];
+    let mut done = 0;
+    while done < buf_len
+    {
+        let len = (buf_len - done).min(chunk.len() as u64) as usize;
+        rand_buffer(&mut chunk[..len]);
+        if copy_to_user(buf_ptr as u64 + done, &chunk[..len]).is_err() {return ErrorKind::InvalidInput as i8;}
+        done += len as u64;
+    }
+    -1
+}
+
+pub extern "C" fn c_proc_set_priority(pid: c_ulonglong, priority: c_uchar) -> c_schar
+{
+    ffi_process_res(proc_set_priority(pid, priority))
+}
+
+pub extern "C" fn c_proc_set_time_slice(ticks: c_ulonglong) -> c_schar
+{
+    ffi_process_res(proc_set_time_slice(ticks))
+}
+
+pub extern "C" fn c_proc_stats(pid: c_ulonglong, stats_ptr: *mut ProcessStats) -> c_schar
+{
+    let res = proc_stats(pid).and_then(|s| ffi_write_value(stats_ptr, &s));
+    ffi_process_res(res)
+}
+
+pub extern "C" fn c_proc_exit(code: c_int) -> !
+{
+    proc_exit(code)
+}
+
+pub extern "C" fn c_proc_wait(pid_ptr: *mut c_ulonglong, code_ptr: *mut c_int) -> c_schar
+{
+    //the outputs are checked first, collecting the child can not be undone
+    let res = ffi_check_out(pid_ptr).and(ffi_check_out(code_ptr)).and_then(|_| proc_wait()).and_then(|(pid, code)|
+    {
+        ffi_write_value(pid_ptr, &pid)?;
+        ffi_write_value(code_ptr, &code)
+    });
+    ffi_process_res(res)
+}
+
+pub extern "C" fn c_proc_waitpid(pid: c_ulonglong, code_ptr: *mut c_int) -> c_schar
+{
+    let res = ffi_check_out(code_ptr).and_then(|_| proc_waitpid(pid)).and_then(|code| ffi_write_value(code_ptr, &code));
+    ffi_process_res(res)
+}
+
+pub extern "C" fn c_proc_spawn_path(path: *const c_char, pid_ptr: *mut c_ulonglong) -> c_schar
+{
+    let res = ffi_check_out(pid_ptr).and_then(|_| ffi_str_from_ptr(path).map_err(|_| ProcessError::InvalidInput))
+        .and_then(|path| proc_spawn_path(&path)).and_then(|pid| ffi_write_value(pid_ptr, &pid));
+    ffi_process_res(res)
+}
+
+pub extern "C" fn c_proc_spawn_args(path: *const c_char, args_ptr: *const c_char, args_len: c_ulonglong, env_ptr: *const c_char, env_len: c_ulonglong, pid_ptr: *mut c_ulonglong) -> c_schar
+{
+    let res = ffi_check_out(pid_ptr).and_then(|_| ffi_str_from_ptr(path).map_err(|_| ProcessError::InvalidInput))
+        .and_then(|path| proc_spawn_args(&path, ffi_strings_from_parts(args_ptr, args_len)?, ffi_env_from_parts(env_ptr, env_len)?))
+        .and_then(|pid| ffi_write_value(pid_ptr, &pid));
+    ffi_process_res(res)
+}
+
+pub extern "C" fn c_proc_args(buf_ptr: *mut c_uchar, buf_len: c_ulonglong) -> CVecShort
+{
+    CVecShort::copy_out(Ok(proc_args()), buf_ptr, buf_len)
+}
+
+pub extern "C" fn c_proc_env(buf_ptr: *mut c_uchar, buf_len: c_ulonglong) -> CVecShort
+{
+    CVecShort::copy_out(Ok(proc_env()), buf_ptr, buf_len)
+}
+
+pub extern "C" fn c_proc_env_var(name: *const c_char, buf_ptr: *mut c_uchar, buf_len: c_ulonglong) -> CVecShort
+{
+    let res = ffi_str_from_ptr(name).and_then(|name| proc_env_var(&name));
+    CVecShort::copy_out(res, buf_ptr, buf_len)
+}
+
+pub extern "C" fn c_proc_kill(pid: c_ulonglong) -> c_schar
+{
+    ffi_process_res(proc_kill(pid))
+}
+
+pub extern "C" fn c_proc_kill_self() -> c_schar
+{
+    ffi_process_res(proc_kill_self())
+}
+
+pub extern "C" fn c_proc_signal(pid: c_ulonglong, sig: c_uchar) -> c_schar
+{
+    ffi_process_res(proc_signal(pid, sig))
+}
+
+pub extern "C" fn c_signal_action(sig: c_uchar, handler: c_ulonglong, old_ptr: *mut c_ulonglong) -> c_schar
+{
+    let res = ffi_check_out(old_ptr).and_then(|_| signal_action(sig, handler)).and_then(|old| ffi_write_value(old_ptr, &old));
+    ffi_process_res(res)
+}
+
+pub extern "C" fn c_proc_limits(pid: c_ulonglong, limits_ptr: *mut ResourceLimits) -> c_schar
+{
+    let res = proc_limits(pid).and_then(|limits| ffi_write_value(limits_ptr, &limits));
+    ffi_process_res(res)
+}
+
+pub extern "C" fn c_proc_set_child_limits(limits_ptr: *const ResourceLimits) -> c_schar
+{
+    let res = ffi_read_value(limits_ptr).and_then(proc_set_child_limits);
+    ffi_process_res(res)
+}
+
+pub extern "C" fn c_proc_ids(pid: c_ulonglong, cred_ptr: *mut Credentials) -> c_schar
+{
+    let res = proc_ids(pid).and_then(|cred| ffi_write_value(cred_ptr, &cred));
+    ffi_process_res(res)
+}
+
+pub extern "C" fn c_proc_set_ids(uid: c_uint, gid: c_uint) -> c_schar
+{
+    ffi_process_res(proc_set_ids(uid, gid))
+}
+
+pub extern "C" fn c_proc_pid() -> c_ulonglong
+{
+    proc_pid()
+}
+
+/// Copies up to `buf_len` entries into the buffer and returns the total number of processes,
+/// or `c_ulonglong::MAX` if the buffer is not writable.
+///
+/// The entries have the layout from before processes had user and group ids, `c_proc_list_ids`
+/// returns them with their ids.
+pub extern "C" fn c_proc_list(buf_ptr: *mut LegacyProcessInfo, buf_len: c_ulonglong) -> c_ulonglong
+{
+    let list: Vec<LegacyProcessInfo> = proc_list().into_iter().map(LegacyProcessInfo::from).collect();
+    ffi_copy_list(&list, buf_ptr, buf_len)
+}
+
+/// Returns the address of the mapped pages, or 0 if they could not be mapped.
+pub extern "C" fn c_mem_map(addr: c_ulonglong, pages: c_ulonglong) -> c_ulonglong
+{
+    mem_map(addr, pages).unwrap_or(0)
+}
+
+pub extern "C" fn c_mem_unmap(addr: c_ulonglong, pages: c_ulonglong) -> c_schar
+{
+    ffi_errorkind_res(mem_unmap(addr, pages))
+}
+
+pub extern "C" fn c_thread_create(entry: c_ulonglong, arg: c_ulonglong, tid_ptr: *mut c_ulonglong) -> c_schar
+{
+    let res = ffi_check_out(tid_ptr).and_then(|_| thread_create(entry, arg)).and_then(|tid| ffi_write_value(tid_ptr, &tid));
+    ffi_process_res(res)
+}
+
+pub extern "C" fn c_thread_join(tid: c_ulonglong, value_ptr: *mut c_ulonglong) -> c_schar
+{
+    let res = ffi_check_out(value_ptr).and_then(|_| thread_join(tid)).and_then(|value| ffi_write_value(value_ptr, &value));
+    ffi_process_res(res)
+}
+
+pub extern "C" fn c_thread_exit(value: c_ulonglong) -> !
+{
+    thread_exit(value)
+}
+
+pub extern "C" fn c_thread_yield()
+{
+    thread_yield()
+}
+
+pub extern "C" fn c_thread_id() -> c_ulonglong
+{
+    thread_id()
+}
+
+pub extern "C" fn c_sleep(ms: c_ulonglong)
+{
+    sleep(ms)
+}
+
+pub extern "C" fn c_futex_wait(addr: *const c_uint, expected: c_uint) -> c_schar
+{
+    ffi_errorkind_res(futex_wait(addr as u64, expected))
+}
+
+pub extern "C" fn c_futex_wake(addr: *const c_uint, count: c_ulonglong) -> c_ulonglong
+{
+    futex_wake(addr as u64, count)
+}
+
+pub extern "C" fn c_pipe_create(read_ptr: *mut c_ulonglong, write_ptr: *mut c_ulonglong) -> c_schar
+{
+    let res = ffi_check_out(read_ptr).and_then(|_| ffi_check_out(write_ptr)).map_err(|_| ErrorKind::InvalidInput).and_then(|_| pipe_create()).and_then(|(read, write)|
+    {
+        copy_value_to_user(read_ptr as u64, &read).and_then(|_| copy_value_to_user(write_ptr as u64, &write)).map_err(|_| ErrorKind::InvalidInput)
+    });
+    ffi_errorkind_res(res)
+}
+
+pub extern "C" fn c_pipe_read(handle: c_ulonglong, buf_ptr: *mut c_uchar, buf_len: c_ulonglong, read_ptr: *mut c_ulonglong) -> c_schar
+{
+    //checked before blocking, so the data isn't lost to a bad buffer afterwards
+    if !check_user_range(buf_ptr as u64, buf_len, true) || ffi_check_out(read_ptr).is_err() {return ErrorKind::InvalidInput as c_schar;}
+    let res = pipe_read(handle, buf_len as usize).and_then(|data|
+    {
+        copy_to_user(buf_ptr as u64, &data).and_then(|_| copy_value_to_user(read_ptr as u64, &(data.len() as u64))).map_err(|_| ErrorKind::InvalidInput)
+    });
+    ffi_errorkind_res(res)
+}
+
+pub extern "C" fn c_pipe_write(handle: c_ulonglong, data_ptr: *const c_uchar, data_len: c_ulonglong) -> c_schar
+{
+    //copied a buffer at a time, so large writes don't need as much kernel heap, empty writes still check the handle
+    let mut res = Ok(());
+    for offset in (0..data_len.max(1)).step_by(PIPE_CAPACITY)
+    {
+        let len = (data_len - offset).min(PIPE_CAPACITY as u64);
+        res = ffi_byte_slice_from_parts(data_ptr.wrapping_add(offset as usize), len).and_then(|data| pipe_write(handle, &data));
+        if res.is_err() {break;}
+    }
+    ffi_errorkind_res(res)
+}
+
+pub extern "C" fn c_handle_close(handle: c_ulonglong) -> c_schar
+{
+    ffi_errorkind_res(handle_close(handle))
+}
+
+pub extern "C" fn c_handle_inherit(handle: c_ulonglong, inherit: bool) -> c_schar
+{
+    ffi_errorkind_res(handle_inherit(handle, inherit))
+}
+
+pub extern "C" fn c_handle_duplicate(handle: c_ulonglong, rights: c_uint, new_ptr: *mut c_ulonglong) -> c_schar
+{
+    let res = ffi_check_out(new_ptr).map_err(|_| ErrorKind::InvalidInput).and_then(|_| handle_duplicate(handle, rights))
+        .and_then(|new| copy_value_to_user(new_ptr as u64, &new).map_err(|_| ErrorKind::InvalidInput));
+    ffi_errorkind_res(res)
+}
+
+pub extern "C" fn c_handle_rights(handle: c_ulonglong, rights_ptr: *mut c_uint) -> c_schar
+{
+    let res = handle_rights(handle).and_then(|rights| copy_value_to_user(rights_ptr as u64, &rights).map_err(|_| ErrorKind::InvalidInput));
+    ffi_errorkind_res(res)
+}
+
+pub extern "C" fn c_file_open(path: *const c_char, rights: c_uint, handle_ptr: *mut c_ulonglong) -> c_schar
+{
+    let res = ffi_check_out(handle_ptr).map_err(|_| ErrorKind::InvalidInput).and_then(|_| ffi_str_from_ptr(path)).and_then(|path| file_open(&path, rights))
+        .and_then(|handle| copy_value_to_user(handle_ptr as u64, &handle).map_err(|_| ErrorKind::InvalidInput));
+    ffi_errorkind_res(res)
+}
+
+pub extern "C" fn c_file_handle_read(handle: c_ulonglong, buf_ptr: *mut c_uchar, buf_len: c_ulonglong) -> CVecShort
+{
+    CVecShort::copy_out(file_handle_read(handle), buf_ptr, buf_len)
+}
+
+pub extern "C" fn c_file_handle_write(handle: c_ulonglong, data_ptr: *const c_uchar, data_len: c_ulonglong) -> c_schar
+{
+    if data_len > MAX_FILE_WRITE {return ErrorKind::InvalidInput as c_schar;}
+    let res = ffi_byte_slice_from_parts(data_ptr, data_len).and_then(|data| file_handle_write(handle, &data));
+    ffi_errorkind_res(res)
+}
+
+pub extern "C" fn c_proc_open(pid: c_ulonglong, rights: c_uint, handle_ptr: *mut c_ulonglong) -> c_schar
+{
+    let res = ffi_check_out(handle_ptr).and_then(|_| proc_open(pid, rights)).and_then(|handle| ffi_write_value(handle_ptr, &handle));
+    ffi_process_res(res)
+}
+
+pub extern "C" fn c_proc_handle_signal(handle: c_ulonglong, sig: c_uchar) -> c_schar
+{
+    ffi_process_res(proc_handle_signal(handle, sig))
+}
+
+pub extern "C" fn c_proc_handle_kill(handle: c_ulonglong) -> c_schar
+{
+    ffi_process_res(proc_handle_kill(handle))
+}
+
+pub extern "C" fn c_endpoint_register(name: *const c_char, handle_ptr: *mut c_ulonglong) -> c_schar
+{
+    let res = ffi_check_out(handle_ptr).map_err(|_| ErrorKind::InvalidInput).and_then(|_| ffi_str_from_ptr(name)).and_then(|name| endpoint_register(&name))
+        .and_then(|handle| copy_value_to_user(handle_ptr as u64, &handle).map_err(|_| ErrorKind::InvalidInput));
+    ffi_errorkind_res(res)
+}
+
+pub extern "C" fn c_endpoint_send(name: *const c_char, data_ptr: *const c_uchar, data_len: c_ulonglong) -> c_schar
+{
+    if data_len > MAX_MESSAGE_SIZE as u64 {return ErrorKind::InvalidInput as c_schar;}
+    let res = ffi_str_from_ptr(name).and_then(|name| endpoint_send(&name, &ffi_byte_slice_from_parts(data_ptr, data_len)?));
+    ffi_errorkind_res(res)
+}
+
+pub extern "C" fn c_endpoint_send_pid(pid: c_ulonglong, data_ptr: *const c_uchar, data_len: c_ulonglong) -> c_schar
+{
+    if data_len > MAX_MESSAGE_SIZE as u64 {return ErrorKind::InvalidInput as c_schar;}
+    let res = ffi_byte_slice_from_parts(data_ptr, data_len).and_then(|data| endpoint_send_pid(pid, &data));
+    ffi_errorkind_res(res)
+}
+
+pub extern "C" fn c_endpoint_send_handle(name: *const c_char, data_ptr: *const c_uchar, data_len: c_ulonglong, handle: c_ulonglong) -> c_schar
+{
+    if data_len > MAX_MESSAGE_SIZE as u64 {return ErrorKind::InvalidInput as c_schar;}
+    let res = ffi_str_from_ptr(name).and_then(|name| endpoint_send_handle(&name, &ffi_byte_slice_from_parts(data_ptr, data_len)?, handle));
+    ffi_errorkind_res(res)
+}
+
+/// Copies as much of the message as fits into the buffer, the rest of it is lost. A handle the
+/// message carries is closed, `c_endpoint_receive_handle` keeps it.
+pub extern "C" fn c_endpoint_receive(handle: c_ulonglong, buf_ptr: *mut c_uchar, buf_len: c_ulonglong, sender_ptr: *mut c_ulonglong, block: bool) -> CVecShort
+{
+    //checked before blocking, so the message isn't lost to a bad buffer afterwards
+    let checked = check_user_range(buf_ptr as u64, buf_len, true) && ffi_check_out(sender_ptr).is_ok();
+    let res = if checked {endpoint_receive(handle, block)} else {Err(ErrorKind::InvalidInput)};
+    let res = res.and_then(|message| copy_value_to_user(sender_ptr as u64, &message.sender).map(|_| message.data).map_err(|_| ErrorKind::InvalidInput));
+    CVecShort::copy_out(res, buf_ptr, buf_len)
+}
+
+/// Copies as much of the message as fits into the buffer, the rest of it is lost.
+pub extern "C" fn c_endpoint_receive_handle(handle: c_ulonglong, buf_ptr: *mut c_uchar, buf_len: c_ulonglong, info_ptr: *mut MessageInfo, block: bool) -> CVecShort
+{
+    //checked before blocking, so the message isn't lost to a bad buffer afterwards
+    let checked = check_user_range(buf_ptr as u64, buf_len, true) && ffi_check_out(info_ptr).is_ok();
+    let res = if checked {endpoint_receive_handle(handle, block)} else {Err(ErrorKind::InvalidInput)};
+    let res = res.and_then(|(message, received)|
+    {
+        let info = MessageInfo{sender: message.sender, handle: received};
+        copy_value_to_user(info_ptr as u64, &info).map(|_| message.data).map_err(|_| ErrorKind::InvalidInput)
+    });
+    CVecShort::copy_out(res, buf_ptr, buf_len)
+}
+
+/// The object gets `DEFAULT_SHARED_MODE`, `c_shm_create_mode` takes the mode.
+pub extern "C" fn c_shm_create(name: *const c_char, pages: c_ulonglong, writable: bool, executable: bool, addr_ptr: *mut c_ulonglong) -> c_schar
+{
+    c_shm_create_mode(name, pages, DEFAULT_SHARED_MODE as c_uint, writable, executable, addr_ptr)
+}
+
+pub extern "C" fn c_shm_create_mode(name: *const c_char, pages: c_ulonglong, mode: c_uint, writable: bool, executable: bool, addr_ptr: *mut c_ulonglong) -> c_schar
+{
+    let res = ffi_check_out(addr_ptr).map_err(|_| ErrorKind::InvalidInput).and_then(|_| u16::try_from(mode).map_err(|_| ErrorKind::InvalidInput))
+        .and_then(|mode| ffi_str_from_ptr(name).and_then(|name| shm_create(&name, pages, mode, writable, executable)))
+        .and_then(|addr| copy_value_to_user(addr_ptr as u64, &addr).map_err(|_| ErrorKind::InvalidInput));
+    ffi_errorkind_res(res)
+}
+
+pub extern "C" fn c_shm_map(name: *const c_char, writable: bool, executable: bool, addr_ptr: *mut c_ulonglong, pages_ptr: *mut c_ulonglong) -> c_schar
+{
+    let res = ffi_check_out(addr_ptr).and(ffi_check_out(pages_ptr)).map_err(|_| ErrorKind::InvalidInput).and_then(|_| ffi_str_from_ptr(name)).and_then(|name| shm_map(&name, writable, executable))
+        .and_then(|(addr, pages)| copy_value_to_user(addr_ptr as u64, &addr).and_then(|_| copy_value_to_user(pages_ptr as u64, &pages)).map_err(|_| ErrorKind::InvalidInput));
+    ffi_errorkind_res(res)
+}
+
+pub extern "C" fn c_shm_unmap(addr: c_ulonglong) -> c_schar
+{
+    ffi_errorkind_res(shm_unmap(addr))
+}
+
+/// Returns true if the kernel implements syscall `nr`.
+pub extern "C" fn c_abi_supported(nr: c_ulonglong) -> bool
+{
+    nr < crate::syscall_abi::SYSCALL_COUNT
+}
+
+pub extern "C" fn c_file_read_mode(path: *const c_char, buf_ptr: *mut c_uchar, buf_len: c_ulonglong) -> CVecShort
+{
+    let res = ffi_str_from_ptr(path).and_then(|path| file_read_perms(&path)).map(|p| p.to_byte_arr().to_vec());
+    CVecShort::copy_out(res, buf_ptr, buf_len)
+}
+
+pub extern "C" fn c_file_write_mode(path: *const c_char, perms_buf_ptr: *const c_uchar, perms_buf_len: c_ulonglong) -> c_schar
+{
+    if perms_buf_len != PERMS_SIZE as u64 {return ErrorKind::InvalidInput as c_schar;}
+    let res = ffi_str_from_ptr(path).and_then(|path|
+    {
+        let perms = ffi_byte_slice_from_parts(perms_buf_ptr, perms_buf_len)?;
+        file_write_perms(&path, FilePermissions::from_byte_slice(&perms))
+    });
+    ffi_errorkind_res(res)
+}
+
+/// Like `c_proc_list`, with the effective user and group id of each process.
+pub extern "C" fn c_proc_list_ids(buf_ptr: *mut ProcessInfo, buf_len: c_ulonglong) -> c_ulonglong
 {
-    rand_buffer(&mut ffi_byte_slice_from_parts(buf_ptr, buf_len));
+    ffi_copy_list(&proc_list(), buf_ptr, buf_len)
 }
\ No newline at end of file
@@ -0,0 +1,245 @@
+//! Numbered syscall interface programs enter through `int 0x80`.
+//!
+//! The syscall number goes in rax and up to six arguments in rdi, rsi, rdx, rcx, r8 and r9,
+//! the order of the System V calling convention. Every syscall behaves like the `c_*`
+//! function of the same name, its result comes back in rax and bad pointers make it fail
+//! with an error code. Functions returning a `CVecShort` take the address to store it at
+//! as their first argument and return it.
+//!
+//! Numbers are never reused or reordered, new syscalls are appended and `ABI_VERSION` is
+//! raised whenever existing ones change. Unknown numbers fail with `ErrorKind::Unsupported`.
+
+use core::ffi::c_char;
+use core::mem::size_of;
+use embedded_io::ErrorKind;
+use x86_64::instructions::interrupts;
+use crate::abi_defs::{SYSCALLS, ERROR_KINDS, PROCESS_ERRORS, RIGHT_SIGNAL};
+use crate::endpoint::MessageInfo;
+use crate::proc::{Credentials, LegacyProcessInfo, ProcessInfo, ProcessStats, ResourceLimits, ThreadStatus, EXIT_KILLED};
+use crate::syscall::*;
+use crate::trap::TrapFrame;
+use crate::usermode::{check_user_range, copy_value_to_user};
+
+pub const FILE_READ_PERMS: u64 = 0;
+pub const FILE_WRITE_PERMS: u64 = 1;
+pub const FILE_READ: u64 = 2;
+pub const FILE_WRITE: u64 = 3;
+pub const FILE_DELETE: u64 = 4;
+pub const FILE_CREATE: u64 = 5;
+pub const TIME_NOW: u64 = 6;
+pub const RAND_BUFFER: u64 = 7;
+pub const PROC_SET_PRIORITY: u64 = 8;
+pub const PROC_SET_TIME_SLICE: u64 = 9;
+pub const PROC_STATS: u64 = 10;
+pub const PROC_EXIT: u64 = 11;
+pub const PROC_WAIT: u64 = 12;
+pub const PROC_WAITPID: u64 = 13;
+pub const PROC_SPAWN_PATH: u64 = 14;
+pub const PROC_KILL: u64 = 15;
+pub const PROC_KILL_SELF: u64 = 16;
+pub const PROC_PID: u64 = 17;
+pub const PROC_LIST: u64 = 18;
+pub const MEM_MAP: u64 = 19;
+pub const MEM_UNMAP: u64 = 20;
+pub const ABI_SUPPORTED: u64 = 21;
+pub const PROC_ARGS: u64 = 22;
+pub const PROC_ENV: u64 = 23;
+pub const PROC_ENV_VAR: u64 = 24;
+pub const PROC_SPAWN_ARGS: u64 = 25;
+pub const THREAD_CREATE: u64 = 26;
+pub const THREAD_JOIN: u64 = 27;
+pub const THREAD_EXIT: u64 = 28;
+pub const THREAD_YIELD: u64 = 29;
+pub const THREAD_ID: u64 = 30;
+pub const SLEEP: u64 = 31;
+pub const FUTEX_WAIT: u64 = 32;
+pub const FUTEX_WAKE: u64 = 33;
+pub const PIPE_CREATE: u64 = 34;
+pub const PIPE_READ: u64 = 35;
+pub const PIPE_WRITE: u64 = 36;
+pub const HANDLE_CLOSE: u64 = 37;
+pub const HANDLE_INHERIT: u64 = 38;
+pub const ENDPOINT_REGISTER: u64 = 39;
+pub const ENDPOINT_SEND: u64 = 40;
+pub const ENDPOINT_SEND_PID: u64 = 41;
+pub const ENDPOINT_RECEIVE: u64 = 42;
+pub const SHM_CREATE: u64 = 43;
+pub const SHM_MAP: u64 = 44;
+pub const SHM_UNMAP: u64 = 45;
+pub const PROC_SIGNAL: u64 = 46;
+pub const SIGNAL_ACTION: u64 = 47;
+pub const SIGNAL_RETURN: u64 = 48;
+pub const PROC_LIMITS: u64 = 49;
+pub const PROC_SET_CHILD_LIMITS: u64 = 50;
+pub const PROC_IDS: u64 = 51;
+pub const PROC_SET_IDS: u64 = 52;
+pub const HANDLE_DUPLICATE: u64 = 53;
+pub const HANDLE_RIGHTS: u64 = 54;
+pub const FILE_OPEN: u64 = 55;
+pub const FILE_HANDLE_READ: u64 = 56;
+pub const FILE_HANDLE_WRITE: u64 = 57;
+pub const PROC_OPEN: u64 = 58;
+pub const PROC_HANDLE_SIGNAL: u64 = 59;
+pub const PROC_HANDLE_KILL: u64 = 60;
+pub const ENDPOINT_SEND_HANDLE: u64 = 61;
+pub const ENDPOINT_RECEIVE_HANDLE: u64 = 62;
+pub const FILE_READ_MODE: u64 = 63;
+pub const FILE_WRITE_MODE: u64 = 64;
+pub const PROC_LIST_IDS: u64 = 65;
+pub const SHM_CREATE_MODE: u64 = 66;
+
+/// Number of syscalls, valid numbers are below this.
+pub const SYSCALL_COUNT: u64 = 67;
+
+/// Longest path accepted by syscalls, including the nul terminator.
+pub const MAX_PATH_LEN: u64 = 4096;
+
+//the generated C header is built from abi_defs.rs, so it has to agree with the kernel
+const _: () = assert!(SYSCALLS.len() == SYSCALL_COUNT as usize);
+const _: () = assert!(ERROR_KINDS.len() == ErrorKind::OutOfMemory as usize + 1);
+const _: () = assert!(PROCESS_ERRORS.len() == ProcessError::Interrupted as usize + 1);
+const _: () = assert!(size_of::<CVecShort>() == 24 && size_of::<ProcessStats>() == 24 && size_of::<ProcessInfo>() == 24 && size_of::<LegacyProcessInfo>() == 24 && size_of::<ResourceLimits>() == 32 && size_of::<Credentials>() == 16 && size_of::<MessageInfo>() == 16);
+
+//returned for unknown numbers
+const UNSUPPORTED: u64 = ErrorKind::Unsupported as i8 as u64;
+//returned by syscalls reporting an `i8` status that succeeded
+const OK: u64 = -1i8 as u64;
+
+/// Entered through `SYSCALL_VECTOR`.
+///
+/// # Safety
+///
+/// `frame` has to point to the frame the stub in trap.rs pushed for the calling context.
+pub unsafe extern "C" fn syscall_handler(frame: *mut TrapFrame) -> *mut TrapFrame
+{
+    let f = unsafe{&mut *frame};
+    //these switch to another context instead of returning to the caller right away, so they are handled right here
+    match f.rax
+    {
+        PROC_EXIT => return crate::proc_watch::exit_current(f.rdi as i32, frame),
+        PROC_KILL_SELF => return crate::proc_watch::exit_current(EXIT_KILLED, frame),
+        THREAD_EXIT => return crate::proc_watch::exit_thread(f.rdi, frame),
+        THREAD_YIELD => return crate::proc_watch::yield_current(frame),
+        SLEEP => return crate::proc_watch::sleep_current(f.rdi, frame),
+        FUTEX_WAIT => return futex_wait(f.rdi, f.rsi as u32, frame),
+        PROC_SIGNAL => return proc_signal(f.rdi, f.rsi as u8, frame),
+        PROC_HANDLE_SIGNAL => return proc_handle_signal(f.rdi, f.rsi as u8, frame),
+        SIGNAL_RETURN => return unsafe{crate::proc_watch::signal_return(frame)},
+        _ => {}
+    }
+    //syscalls may block or take long, so the timer has to be able to preempt them
+    interrupts::enable();
+    let res = dispatch(f.rax, [f.rdi, f.rsi, f.rdx, f.rcx, f.r8, f.r9]);
+    interrupts::disable();
+    f.rax = res;
+    frame
+}
+
+//the `c_*` functions copy everything they get pointers to through the checked copy functions in usermode.rs
+fn dispatch(nr: u64, a: [u64; 6]) -> u64
+{
+    match nr
+    {
+        FILE_READ_PERMS => return_vec(a[0], c_file_read_perms(a[1] as *const c_char, a[2] as *mut u8, a[3])),
+        FILE_READ => return_vec(a[0], c_file_read(a[1] as *const c_char, a[2] as *mut u8, a[3])),
+        FILE_WRITE_PERMS => c_file_write_perms(a[0] as *const c_char, a[1] as *const u8, a[2]) as u64,
+        FILE_WRITE => c_file_write(a[0] as *const c_char, a[1] as *const u8, a[2]) as u64,
+        FILE_DELETE => c_file_delete(a[0] as *const c_char) as u64,
+        FILE_CREATE => c_file_create(a[0] as *const c_char) as u64,
+        TIME_NOW => c_time_now() as u64,
+        RAND_BUFFER => c_rand_buffer(a[0] as *mut u8, a[1]) as u64,
+        PROC_SET_PRIORITY => c_proc_set_priority(a[0], a[1] as u8) as u64,
+        PROC_SET_TIME_SLICE => c_proc_set_time_slice(a[0]) as u64,
+        PROC_STATS => c_proc_stats(a[0], a[1] as *mut ProcessStats) as u64,
+        PROC_WAIT => c_proc_wait(a[0] as *mut u64, a[1] as *mut i32) as u64,
+        PROC_WAITPID => c_proc_waitpid(a[0], a[1] as *mut i32) as u64,
+        PROC_SPAWN_PATH => c_proc_spawn_path(a[0] as *const c_char, a[1] as *mut u64) as u64,
+        PROC_KILL => c_proc_kill(a[0]) as u64,
+        PROC_PID => c_proc_pid(),
+        PROC_LIST => c_proc_list(a[0] as *mut LegacyProcessInfo, a[1]),
+        MEM_MAP => c_mem_map(a[0], a[1]),
+        MEM_UNMAP => c_mem_unmap(a[0], a[1]) as u64,
+        ABI_SUPPORTED => c_abi_supported(a[0]) as u64,
+        PROC_ARGS => return_vec(a[0], c_proc_args(a[1] as *mut u8, a[2])),
+        PROC_ENV => return_vec(a[0], c_proc_env(a[1] as *mut u8, a[2])),
+        PROC_ENV_VAR => return_vec(a[0], c_proc_env_var(a[1] as *const c_char, a[2] as *mut u8, a[3])),
+        PROC_SPAWN_ARGS => c_proc_spawn_args(a[0] as *const c_char, a[1] as *const c_char, a[2], a[3] as *const c_char, a[4], a[5] as *mut u64) as u64,
+        THREAD_CREATE => c_thread_create(a[0], a[1], a[2] as *mut u64) as u64,
+        THREAD_JOIN => c_thread_join(a[0], a[1] as *mut u64) as u64,
+        THREAD_ID => c_thread_id(),
+        FUTEX_WAKE => c_futex_wake(a[0] as *const u32, a[1]),
+        PIPE_CREATE => c_pipe_create(a[0] as *mut u64, a[1] as *mut u64) as u64,
+        PIPE_READ => c_pipe_read(a[0], a[1] as *mut u8, a[2], a[3] as *mut u64) as u64,
+        PIPE_WRITE => c_pipe_write(a[0], a[1] as *const u8, a[2]) as u64,
+        HANDLE_CLOSE => c_handle_close(a[0]) as u64,
+        HANDLE_INHERIT => c_handle_inherit(a[0], a[1] as u8 != 0) as u64,
+        ENDPOINT_REGISTER => c_endpoint_register(a[0] as *const c_char, a[1] as *mut u64) as u64,
+        ENDPOINT_SEND => c_endpoint_send(a[0] as *const c_char, a[1] as *const u8, a[2]) as u64,
+        ENDPOINT_SEND_PID => c_endpoint_send_pid(a[0], a[1] as *const u8, a[2]) as u64,
+        ENDPOINT_RECEIVE => return_vec(a[0], c_endpoint_receive(a[1], a[2] as *mut u8, a[3], a[4] as *mut u64, a[5] as u8 != 0)),
+        SHM_CREATE => c_shm_create(a[0] as *const c_char, a[1], a[2] as u8 != 0, a[3] as u8 != 0, a[4] as *mut u64) as u64,
+        SHM_MAP => c_shm_map(a[0] as *const c_char, a[1] as u8 != 0, a[2] as u8 != 0, a[3] as *mut u64, a[4] as *mut u64) as u64,
+        SHM_UNMAP => c_shm_unmap(a[0]) as u64,
+        SIGNAL_ACTION => c_signal_action(a[0] as u8, a[1], a[2] as *mut u64) as u64,
+        PROC_LIMITS => c_proc_limits(a[0], a[1] as *mut ResourceLimits) as u64,
+        PROC_SET_CHILD_LIMITS => c_proc_set_child_limits(a[0] as *const ResourceLimits) as u64,
+        PROC_IDS => c_proc_ids(a[0], a[1] as *mut Credentials) as u64,
+        PROC_SET_IDS => c_proc_set_ids(a[0] as u32, a[1] as u32) as u64,
+        HANDLE_DUPLICATE => c_handle_duplicate(a[0], a[1] as u32, a[2] as *mut u64) as u64,
+        HANDLE_RIGHTS => c_handle_rights(a[0], a[1] as *mut u32) as u64,
+        FILE_OPEN => c_file_open(a[0] as *const c_char, a[1] as u32, a[2] as *mut u64) as u64,
+        FILE_HANDLE_READ => return_vec(a[0], c_file_handle_read(a[1], a[2] as *mut u8, a[3])),
+        FILE_HANDLE_WRITE => c_file_handle_write(a[0], a[1] as *const u8, a[2]) as u64,
+        PROC_OPEN => c_proc_open(a[0], a[1] as u32, a[2] as *mut u64) as u64,
+        PROC_HANDLE_KILL => c_proc_handle_kill(a[0]) as u64,
+        ENDPOINT_SEND_HANDLE => c_endpoint_send_handle(a[0] as *const c_char, a[1] as *const u8, a[2], a[3]) as u64,
+        ENDPOINT_RECEIVE_HANDLE => return_vec(a[0], c_endpoint_receive_handle(a[1], a[2] as *mut u8, a[3], a[4] as *mut MessageInfo, a[5] as u8 != 0)),
+        FILE_READ_MODE => return_vec(a[0], c_file_read_mode(a[1] as *const c_char, a[2] as *mut u8, a[3])),
+        FILE_WRITE_MODE => c_file_write_mode(a[0] as *const c_char, a[1] as *const u8, a[2]) as u64,
+        PROC_LIST_IDS => c_proc_list_ids(a[0] as *mut ProcessInfo, a[1]),
+        SHM_CREATE_MODE => c_shm_create_mode(a[0] as *const c_char, a[1], a[2] as u32, a[3] as u8 != 0, a[4] as u8 != 0, a[5] as *mut u64) as u64,
+        _ => UNSUPPORTED
+    }
+}
+
+//blocks the caller unless the futex no longer holds `expected`, nothing can wake it in between
+//since interrupts stay disabled until the switch
+fn futex_wait(addr: u64, expected: u32, frame: *mut TrapFrame) -> *mut TrapFrame
+{
+    let f = unsafe{&mut *frame};
+    if addr % 4 != 0 || !check_user_range(addr, 4, false)
+    {
+        f.rax = ErrorKind::InvalidInput as i8 as u64;
+        return frame;
+    }
+    f.rax = OK;
+    let value = unsafe{(addr as *const u32).read_volatile()};
+    if value != expected {return frame;}
+    crate::proc_watch::block_current(ThreadStatus::Waiting(addr), frame)
+}
+
+//a signal the caller sends itself takes effect before it continues, switching away runs the
+//handler on the way back or leaves the process stopped or ended
+fn proc_signal(pid: u64, sig: u8, frame: *mut TrapFrame) -> *mut TrapFrame
+{
+    let f = unsafe{&mut *frame};
+    f.rax = c_proc_signal(pid, sig) as u64;
+    if f.rax == OK && pid == proc_pid() {return crate::proc_watch::yield_current(frame);}
+    frame
+}
+
+//like `proc_signal`, for a process handle
+fn proc_handle_signal(handle: u64, sig: u8, frame: *mut TrapFrame) -> *mut TrapFrame
+{
+    let f = unsafe{&mut *frame};
+    f.rax = c_proc_handle_signal(handle, sig) as u64;
+    if f.rax == OK && handle_pid(handle, RIGHT_SIGNAL).is_ok_and(|pid| pid == proc_pid()) {return crate::proc_watch::yield_current(frame);}
+    frame
+}
+
+//stores a `CVecShort` at the address the caller passed and returns that address, or 0 if it is bad
+fn return_vec(addr: u64, res: CVecShort) -> u64
+{
+    if copy_value_to_user(addr, &res).is_err() {return 0;}
+    addr
+}
\ No newline at end of file
@@ -0,0 +1,201 @@
+use core::arch::{asm, global_asm};
+use x86_64::VirtAddr;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+/// Register state saved by the entry stubs, laid out the way they push it.
+///
+/// Handlers get a pointer to the frame of the interrupted context and return a pointer
+/// to the frame that should be resumed, which is how contexts get switched.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct TrapFrame
+{
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64
+}
+
+impl TrapFrame
+{
+    /// Returns true if the frame was pushed while ring 3 code was running.
+    pub fn from_user(&self) -> bool
+    {
+        self.cs & 0x3 == 0x3
+    }
+}
+
+/// x87, MMX and SSE registers of a thread, in the layout `fxsave` stores them.
+///
+/// The kernel is built without SSE, so the registers only ever hold user state and are swapped
+/// whenever another thread gets to run.
+#[repr(C, align(16))]
+pub struct FpuState([u8; 512]);
+
+impl FpuState
+{
+    /// Returns the state programs start with, every exception masked and the registers cleared.
+    pub fn new() -> Self
+    {
+        let mut state = FpuState([0; 512]);
+        state.0[..2].copy_from_slice(&0x037Fu16.to_le_bytes());
+        state.0[24..28].copy_from_slice(&0x1F80u32.to_le_bytes());
+        state
+    }
+
+    /// Stores the registers of the running thread.
+    pub fn save(&mut self)
+    {
+        unsafe{asm!("fxsave64 [{}]", in(reg) self.0.as_mut_ptr(), options(nostack, preserves_flags))};
+    }
+
+    /// Loads the registers of the thread about to run.
+    pub fn restore(&self)
+    {
+        unsafe{asm!("fxrstor64 [{}]", in(reg) self.0.as_ptr(), options(nostack, preserves_flags))};
+    }
+}
+
+impl Default for FpuState
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+/// Lets programs use SSE, which `FpuState` needs to save and restore the registers.
+pub fn init_fpu()
+{
+    unsafe
+    {
+        Cr0::update(|flags|
+        {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+}
+
+global_asm!(r#"
+.global trap_common
+trap_common:
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+    mov rdi, rsp
+    sub rsp, 8
+    //user code can leave the direction flag set, string instructions in the kernel expect it clear
+    cld
+    call rax
+    mov rsp, rax
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+    add rsp, 8
+    iretq
+
+.macro trap_stub name, handler
+.global \name
+\name:
+    push 0
+    push rax
+    lea rax, [rip + \handler]
+    jmp trap_common
+.endm
+
+.macro trap_stub_error name, handler
+.global \name
+\name:
+    push rax
+    lea rax, [rip + \handler]
+    jmp trap_common
+.endm
+
+trap_stub trap_timer, {timer}
+trap_stub trap_exit, {exit}
+trap_stub trap_syscall, {syscall}
+trap_stub_error trap_page_fault, {page_fault}
+trap_stub_error trap_general_protection_fault, {general_protection_fault}
+"#,
+    timer = sym crate::interrupts::timer_handler,
+    exit = sym crate::usermode::exit_handler,
+    syscall = sym crate::syscall_abi::syscall_handler,
+    page_fault = sym crate::interrupts::page_fault_handler,
+    general_protection_fault = sym crate::interrupts::general_protection_fault_handler
+);
+
+extern "C"
+{
+    fn trap_timer();
+    fn trap_exit();
+    fn trap_syscall();
+    fn trap_page_fault();
+    fn trap_general_protection_fault();
+}
+
+pub fn timer_stub() -> VirtAddr
+{
+    VirtAddr::from_ptr(trap_timer as *const ())
+}
+
+pub fn exit_stub() -> VirtAddr
+{
+    VirtAddr::from_ptr(trap_exit as *const ())
+}
+
+pub fn syscall_stub() -> VirtAddr
+{
+    VirtAddr::from_ptr(trap_syscall as *const ())
+}
+
+pub fn page_fault_stub() -> VirtAddr
+{
+    VirtAddr::from_ptr(trap_page_fault as *const ())
+}
+
+pub fn general_protection_fault_stub() -> VirtAddr
+{
+    VirtAddr::from_ptr(trap_general_protection_fault as *const ())
+}
\ No newline at end of file
@@ -0,0 +1,252 @@
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::structures::paging::mapper::MapToError;
+use conquer_once::spin::OnceCell;
+use alloc::vec::Vec;
+use alloc::vec;
+use x86_64::instructions::interrupts;
+use crate::memory::{self, AddressSpace, BadAddress};
+use crate::trap::TrapFrame;
+
+/// Start of the virtual range reserved for process images and stacks.
+pub const USER_REGION_START: u64 = 0x0000_2000_0000_0000;
+/// End (exclusive) of the virtual range reserved for process images and stacks.
+pub const USER_REGION_END: u64 = 0x0000_4000_0000_0000;
+/// Start of the range memory requested by processes at runtime is mapped in.
+pub const ANON_REGION_START: u64 = 0x0000_3000_0000_0000;
+/// End (exclusive) of the range memory requested by processes at runtime is mapped in.
+pub const ANON_REGION_END: u64 = 0x0000_3F00_0000_0000;
+/// Page holding the code that process entry points return into.
+pub const TRAMPOLINE_ADDR: u64 = USER_REGION_END - 4096;
+/// Address threads started at runtime return into, which ends the thread with the returned value.
+pub const THREAD_RETURN_ADDR: u64 = TRAMPOLINE_ADDR + THREAD_RETURN_OFFSET;
+/// Address signal handlers return into, which restores the context the signal interrupted.
+pub const SIGNAL_RETURN_ADDR: u64 = TRAMPOLINE_ADDR + SIGNAL_RETURN_OFFSET;
+/// Address that exits the process with the code in rdi when jumped to.
+pub const EXIT_STUB_ADDR: u64 = TRAMPOLINE_ADDR + EXIT_STUB_OFFSET;
+/// Interrupt vector the trampoline uses to hand control back to the kernel.
+pub const EXIT_VECTOR: u8 = 0x81;
+/// Interrupt vector programs enter syscalls through.
+pub const SYSCALL_VECTOR: u8 = 0x80;
+//the code threads and signal handlers return into follows the exit code in the trampoline page
+const THREAD_RETURN_OFFSET: u64 = 16;
+const SIGNAL_RETURN_OFFSET: u64 = 32;
+const EXIT_STUB_OFFSET: u64 = 48;
+//the syscall stubs follow that, one 8 byte stub per syscall number
+const SYSCALL_STUBS_OFFSET: u64 = 64;
+//processes start with only the reserved bit and the interrupt flag set
+const USER_RFLAGS: u64 = 0x202;
+
+//movzx edi, al; xor edi, 1; int 0x81; jmp $
+//entry points return true on success, which becomes exit code 0
+const TRAMPOLINE_CODE: [u8; 10] = [0x0F, 0xB6, 0xF8, 0x83, 0xF7, 0x01, 0xCD, EXIT_VECTOR, 0xEB, 0xFE];
+
+static TRAMPOLINE_FRAME: OnceCell<PhysFrame> = OnceCell::uninit();
+
+/// Sets up the return trampoline shared by every process.
+///
+/// Must be called after `memory::init_global`.
+pub fn init()
+{
+    {
+        let mut mapper = memory::MAPPER.try_get().expect("memory not initialized").lock();
+        let table = mapper.level_4_table();
+        let start = usize::from(VirtAddr::new(USER_REGION_START).p4_index());
+        let end = usize::from(VirtAddr::new(USER_REGION_END).p4_index());
+        for i in start..end
+        {
+            assert!(table[i].is_unused(), "user region overlaps kernel mappings");
+        }
+    }
+    let frame = memory::FRAME_ALLOCATOR.try_get().expect("memory not initialized").lock().allocate_frame().expect("failed to allocate user trampoline");
+    let ptr: *mut u8 = memory::phys_to_virt(frame.start_address()).as_mut_ptr();
+    unsafe{ptr.copy_from_nonoverlapping(TRAMPOLINE_CODE.as_ptr(), TRAMPOLINE_CODE.len())};
+    //mov rdi, rax; mov eax, THREAD_EXIT; int 0x80; jmp $
+    let n = (crate::syscall_abi::THREAD_EXIT as u32).to_le_bytes();
+    let thread_return = [0x48, 0x89, 0xC7, 0xB8, n[0], n[1], n[2], n[3], 0xCD, SYSCALL_VECTOR, 0xEB, 0xFE];
+    unsafe{ptr.add(THREAD_RETURN_OFFSET as usize).copy_from_nonoverlapping(thread_return.as_ptr(), thread_return.len())};
+    //mov eax, SIGNAL_RETURN; int 0x80; jmp $
+    let n = (crate::syscall_abi::SIGNAL_RETURN as u32).to_le_bytes();
+    let signal_return = [0xB8, n[0], n[1], n[2], n[3], 0xCD, SYSCALL_VECTOR, 0xEB, 0xFE];
+    unsafe{ptr.add(SIGNAL_RETURN_OFFSET as usize).copy_from_nonoverlapping(signal_return.as_ptr(), signal_return.len())};
+    //int 0x81; jmp $
+    let exit_stub = [0xCD, EXIT_VECTOR, 0xEB, 0xFE];
+    unsafe{ptr.add(EXIT_STUB_OFFSET as usize).copy_from_nonoverlapping(exit_stub.as_ptr(), exit_stub.len())};
+    for nr in 0..crate::syscall_abi::SYSCALL_COUNT
+    {
+        //mov eax, nr; int 0x80; ret
+        let n = (nr as u32).to_le_bytes();
+        let stub = [0xB8, n[0], n[1], n[2], n[3], 0xCD, SYSCALL_VECTOR, 0xC3];
+        unsafe{ptr.add((SYSCALL_STUBS_OFFSET + nr * 8) as usize).copy_from_nonoverlapping(stub.as_ptr(), stub.len())};
+    }
+    TRAMPOLINE_FRAME.try_init_once(|| frame).expect("usermode::init should only be called once");
+}
+
+/// Returns the user address of the stub that enters syscall `nr` with the C calling convention.
+pub fn syscall_stub_addr(nr: u64) -> u64
+{
+    TRAMPOLINE_ADDR + SYSCALL_STUBS_OFFSET + nr * 8
+}
+
+/// Returns true if `len` bytes at `addr` are mapped user accessible in the active address
+/// space, and writable if `write` is set.
+pub fn check_user_range(addr: u64, len: u64, write: bool) -> bool
+{
+    if len == 0 {return true;}
+    let end = match addr.checked_add(len)
+    {
+        Some(end) if addr >= USER_REGION_START && end <= USER_REGION_END => end,
+        _ => return false
+    };
+    let mut page = addr & !0xFFF;
+    while page < end
+    {
+        let flags = match memory::active_page_flags(VirtAddr::new(page))
+        {
+            Some(flags) => flags,
+            None => return false
+        };
+        if !flags.contains(PageTableFlags::USER_ACCESSIBLE) || (write && !flags.contains(PageTableFlags::WRITABLE)) {return false;}
+        page += 4096;
+    }
+    true
+}
+
+//calls `f` with the start and length of each part of the range within a single page, right after
+//checking that page with interrupts disabled, so another thread can't unmap it before `f` is done.
+//`f` must not allocate, a preempted thread might hold the heap lock
+fn for_user_pages(addr: u64, len: u64, write: bool, mut f: impl FnMut(u64, usize)) -> Result<(), BadAddress>
+{
+    let mut done = 0;
+    while done < len
+    {
+        let cur = addr.checked_add(done).ok_or(BadAddress)?;
+        let n = (4096 - (cur & 0xFFF)).min(len - done);
+        interrupts::without_interrupts(||
+        {
+            if !check_user_range(cur, n, write) {return Err(BadAddress);}
+            f(cur, n as usize);
+            Ok(())
+        })?;
+        done += n;
+    }
+    Ok(())
+}
+
+/// Copies `len` bytes at `addr` out of the active address space, failing if the range is not
+/// readable user memory.
+pub fn copy_from_user(addr: u64, len: u64) -> Result<Vec<u8>, BadAddress>
+{
+    //checked once up front too, so a bad length can't make the buffer huge
+    if !check_user_range(addr, len, false) {return Err(BadAddress);}
+    let mut res = vec![0; len as usize];
+    for_user_pages(addr, len, false, |cur, n|
+    {
+        let offset = (cur - addr) as usize;
+        unsafe{(cur as *const u8).copy_to_nonoverlapping(res[offset..].as_mut_ptr(), n)};
+    })?;
+    Ok(res)
+}
+
+/// Copies `data` to `addr` in the active address space, failing if the range is not writable
+/// user memory.
+pub fn copy_to_user(addr: u64, data: &[u8]) -> Result<(), BadAddress>
+{
+    for_user_pages(addr, data.len() as u64, true, |cur, n|
+    {
+        let offset = (cur - addr) as usize;
+        unsafe{(cur as *mut u8).copy_from_nonoverlapping(data[offset..].as_ptr(), n)};
+    })
+}
+
+/// Values that can be copied to user memory byte for byte.
+///
+/// # Safety
+///
+/// Every byte of the type has to belong to a field. Padding holds whatever was in kernel memory
+/// before, which copying it would hand to the process.
+pub unsafe trait UserValue: Copy {}
+
+unsafe impl UserValue for u32 {}
+unsafe impl UserValue for i32 {}
+unsafe impl UserValue for u64 {}
+
+/// Copies a plain value to `addr` in the active address space.
+pub fn copy_value_to_user<T: UserValue>(addr: u64, value: &T) -> Result<(), BadAddress>
+{
+    let bytes = unsafe{core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())};
+    copy_to_user(addr, bytes)
+}
+
+/// Copies the nul terminated string at `addr` out of the active address space, without the
+/// terminator. Fails if it is not in readable user memory or not shorter than `max` bytes.
+pub fn copy_str_from_user(addr: u64, max: u64) -> Result<Vec<u8>, BadAddress>
+{
+    let mut res = Vec::new();
+    let mut len = 0;
+    while len < max
+    {
+        let cur = addr.checked_add(len).ok_or(BadAddress)?;
+        let n = (4096 - (cur & 0xFFF)).min(max - len);
+        //like `for_user_pages`, the room is reserved first so nothing is allocated with interrupts disabled
+        res.reserve(n as usize);
+        let terminated = interrupts::without_interrupts(||
+        {
+            if !check_user_range(cur, n, false) {return Err(BadAddress);}
+            let chunk = unsafe{core::slice::from_raw_parts(cur as *const u8, n as usize)};
+            let end = chunk.iter().position(|&byte| byte == 0);
+            res.extend_from_slice(&chunk[..end.unwrap_or(chunk.len())]);
+            Ok(end.is_some())
+        })?;
+        if terminated {return Ok(res);}
+        len += n;
+    }
+    Err(BadAddress)
+}
+
+/// Creates an empty process address space with the return trampoline mapped.
+pub fn new_address_space() -> Result<AddressSpace, MapToError<Size4KiB>>
+{
+    let space = AddressSpace::new(VirtAddr::new(USER_REGION_START), VirtAddr::new(USER_REGION_END))?;
+    let frame = *TRAMPOLINE_FRAME.try_get().expect("usermode not initialized");
+    space.map_shared(Page::containing_address(VirtAddr::new(TRAMPOLINE_ADDR)), frame, PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE)?;
+    Ok(space)
+}
+
+/// Copies data for the entry point to the top of a stack and returns the initial stack pointer.
+///
+/// The data is preceded by the address the entry point returns to, so it starts 8 bytes above
+/// the returned stack pointer.
+pub fn prepare_stack(space: &AddressSpace, stack_top: VirtAddr, args: &[u8], return_addr: u64) -> Result<VirtAddr, BadAddress>
+{
+    let args_start = (stack_top - args.len() as u64).align_down(16u64);
+    let ret_addr = args_start - 8u64;
+    space.write(args_start, args)?;
+    space.write(ret_addr, &return_addr.to_le_bytes())?;
+    Ok(ret_addr)
+}
+
+/// Builds the frame that starts executing `entry` in ring 3 with the given stack and up to six arguments.
+pub fn initial_frame(entry: VirtAddr, user_rsp: VirtAddr, args: &[u64]) -> TrapFrame
+{
+    let (code_selector, data_selector) = crate::gdt::user_selectors();
+    let mut frame = TrapFrame{rip: entry.as_u64(), cs: code_selector.0.into(), rflags: USER_RFLAGS, rsp: user_rsp.as_u64(), ss: data_selector.0.into(), ..TrapFrame::default()};
+    let registers = [&mut frame.rdi, &mut frame.rsi, &mut frame.rdx, &mut frame.rcx, &mut frame.r8, &mut frame.r9];
+    for (register, &arg) in registers.into_iter().zip(args)
+    {
+        *register = arg;
+    }
+    frame
+}
+
+/// Entered through `EXIT_VECTOR` once a process entry point returns or it exits explicitly,
+/// with the exit code in rdi.
+///
+/// # Safety
+///
+/// `frame` has to point to the frame the stub in trap.rs pushed for the exiting context.
+pub unsafe extern "C" fn exit_handler(frame: *mut TrapFrame) -> *mut TrapFrame
+{
+    let code = unsafe{(*frame).rdi} as i32;
+    crate::proc_watch::exit_current(code, frame)
+}
\ No newline at end of file
@@ -0,0 +1,23 @@
+//! Writes a greeting to a file and checks that it reads back the same.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use sdk::{fs, process, time};
+
+sdk::entry!(main);
+
+fn main() -> i32
+{
+    let greeting = format!("hello from process {} at {}", process::pid(), time::now());
+    if fs::read_perms("hello").is_err() && fs::create("hello").is_err() {return 1;}
+    if fs::write("hello", greeting.as_bytes()).is_err() {return 2;}
+    match fs::read("hello")
+    {
+        Ok(data) if data == greeting.as_bytes() => 0,
+        _ => 3
+    }
+}
\ No newline at end of file
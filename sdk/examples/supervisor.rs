@@ -0,0 +1,19 @@
+//! Starts the `hello` example, waits for it and exits with its exit code.
+
+#![no_std]
+#![no_main]
+
+use sdk::process;
+
+sdk::entry!(main);
+
+fn main() -> i32
+{
+    let child = match process::spawn("hello")
+    {
+        Ok(pid) => pid,
+        Err(_) => return 1
+    };
+    if !process::list().iter().any(|p| p.pid == child && p.parent == process::pid()) {return 2;}
+    process::wait_pid(child).unwrap_or(process::EXIT_KILLED)
+}
\ No newline at end of file
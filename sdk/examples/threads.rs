@@ -0,0 +1,25 @@
+//! Reads a file on a second thread while the first one computes, then checks both results.
+
+#![no_std]
+#![no_main]
+
+use sdk::{fs, thread};
+
+sdk::entry!(main);
+
+fn main() -> i32
+{
+    if fs::read_perms("threads").is_err() && fs::create("threads").is_err() {return 1;}
+    if fs::write("threads", b"overlapped").is_err() {return 2;}
+    let reader = match thread::spawn(|| fs::read("threads").map_or(0, |data| data.len() as u64))
+    {
+        Ok(handle) => handle,
+        Err(_) => return 3
+    };
+    let sum: u64 = (1..=10_000u64).sum();
+    match reader.join()
+    {
+        Ok(10) if sum == 50_005_000 => 0,
+        _ => 4
+    }
+}
\ No newline at end of file
@@ -0,0 +1,47 @@
+//! Which version of the syscall ABI the kernel speaks.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::syscall::*;
+
+/// First ABI version with `ABI_SUPPORTED`.
+const QUERY_VERSION: u32 = 2;
+
+/// Start of the table the kernel passes to entry points, followed by the syscall entries.
+#[repr(C)]
+pub struct SysCallTableHeader
+{
+    pub abi_version: u32,
+    pub entry_count: u32
+}
+
+static VERSION: AtomicU32 = AtomicU32::new(0);
+static ENTRY_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Records the table passed to the entry point, called by the code `entry!` generates.
+///
+/// # Safety
+///
+/// `table` must be null or point to the table the kernel passed.
+#[doc(hidden)]
+pub unsafe fn init(table: *const SysCallTableHeader)
+{
+    if let Some(table) = unsafe{table.as_ref()}
+    {
+        VERSION.store(table.abi_version, Ordering::Relaxed);
+        ENTRY_COUNT.store(table.entry_count, Ordering::Relaxed);
+    }
+}
+
+/// Returns the ABI version of the kernel, 0 if it didn't pass a syscall table.
+pub fn version() -> u32
+{
+    VERSION.load(Ordering::Relaxed)
+}
+
+/// Returns true if the kernel implements syscall `nr`.
+pub fn supported(nr: u64) -> bool
+{
+    if version() < QUERY_VERSION {return nr < ENTRY_COUNT.load(Ordering::Relaxed).into();}
+    let ret = unsafe{syscall1(ABI_SUPPORTED, nr)};
+    ret as u8 != 0
+}
\ No newline at end of file
@@ -0,0 +1,121 @@
+//! Named endpoints, message queues other processes send to by name or by pid.
+//!
+//! Messages keep their boundaries and carry the pid of their sender, so a server can register an
+//! endpoint, receive requests and reply to the endpoint of whoever sent them. A message can also
+//! carry a handle, which the receiver gets a copy of.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use embedded_io::ErrorKind;
+use crate::fs::{CVecShort, c_path};
+use crate::handle;
+use crate::syscall::*;
+
+/// Longest message that can be sent.
+pub const MAX_MESSAGE_SIZE: usize = 1024;
+
+/// An endpoint registered by this process, unregistered when dropped.
+pub struct Endpoint
+{
+    handle: u64
+}
+
+/// A received message.
+pub struct Message
+{
+    /// Pid of the process that sent it.
+    pub sender: u64,
+    pub data: Vec<u8>,
+    /// Handle sent along, now belonging to this process.
+    pub handle: Option<u64>
+}
+
+//where the kernel stores the sender and the handle of a message
+#[repr(C)]
+struct MessageInfo
+{
+    sender: u64,
+    handle: u64
+}
+
+//handle number of messages without one
+const NO_HANDLE: u64 = u64::MAX;
+
+/// Registers an endpoint under `name`, fails with `AlreadyExists` if it is taken.
+pub fn register(name: &str) -> Result<Endpoint, ErrorKind>
+{
+    let name = c_path(name);
+    let mut handle = 0u64;
+    errorkind_res(unsafe{syscall2(ENDPOINT_REGISTER, name.as_ptr() as u64, &mut handle as *mut u64 as u64)})?;
+    Ok(Endpoint{handle})
+}
+
+/// Sends a message to the endpoint registered under `name`, blocking while its queue is full.
+///
+/// Fails with `NotFound` if there is no such endpoint, with `BrokenPipe` if it goes away
+/// before the message was queued and with `Interrupted` if a signal arrives while it blocks.
+pub fn send(name: &str, data: &[u8]) -> Result<(), ErrorKind>
+{
+    let name = c_path(name);
+    errorkind_res(unsafe{syscall3(ENDPOINT_SEND, name.as_ptr() as u64, data.as_ptr() as u64, data.len() as u64)})
+}
+
+/// Sends a message to the endpoint registered under `name` along with a copy of `handle`, which
+/// needs `handle::RIGHT_TRANSFER`. The receiver's copy has the same rights.
+pub fn send_handle(name: &str, data: &[u8], handle: u64) -> Result<(), ErrorKind>
+{
+    let name = c_path(name);
+    errorkind_res(unsafe{syscall4(ENDPOINT_SEND_HANDLE, name.as_ptr() as u64, data.as_ptr() as u64, data.len() as u64, handle)})
+}
+
+/// Sends a message to the endpoint the process `pid` registered first, of the ones it still has.
+pub fn send_pid(pid: u64, data: &[u8]) -> Result<(), ErrorKind>
+{
+    errorkind_res(unsafe{syscall3(ENDPOINT_SEND_PID, pid, data.as_ptr() as u64, data.len() as u64)})
+}
+
+impl Endpoint
+{
+    pub fn handle(&self) -> u64
+    {
+        self.handle
+    }
+
+    /// Blocks until a message arrives and returns it, fails with `Interrupted` if a signal
+    /// arrives first.
+    pub fn receive(&self) -> Result<Message, ErrorKind>
+    {
+        self.receive_message(true)
+    }
+
+    /// Returns the next message, or `None` if there is none right now.
+    pub fn try_receive(&self) -> Result<Option<Message>, ErrorKind>
+    {
+        match self.receive_message(false)
+        {
+            Ok(message) => Ok(Some(message)),
+            Err(ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    fn receive_message(&self, block: bool) -> Result<Message, ErrorKind>
+    {
+        let mut data = vec![0u8; MAX_MESSAGE_SIZE];
+        let mut info = MessageInfo{sender: 0, handle: NO_HANDLE};
+        let mut out = CVecShort{res: 0, ptr: core::ptr::null(), len: 0};
+        unsafe{syscall6(ENDPOINT_RECEIVE_HANDLE, &mut out as *mut CVecShort as u64, self.handle, data.as_mut_ptr() as u64, data.len() as u64, &mut info as *mut MessageInfo as u64, block as u64)};
+        errorkind_res(out.res as u64)?;
+        data.truncate(out.len as usize);
+        let handle = if info.handle == NO_HANDLE {None} else {Some(info.handle)};
+        Ok(Message{sender: info.sender, data, handle})
+    }
+}
+
+impl Drop for Endpoint
+{
+    fn drop(&mut self)
+    {
+        let _ = handle::close(self.handle);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,32 @@
+//! Arguments and environment the process was started with.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::fs::{c_path, read_all};
+use crate::syscall::*;
+
+/// Returns the arguments of the process, programs started by path get the path as the first one.
+pub fn args() -> Vec<String>
+{
+    split(&read_all(PROC_ARGS, None).unwrap_or_default()).map(String::from).collect()
+}
+
+/// Returns every environment variable as a key and a value.
+pub fn vars() -> Vec<(String, String)>
+{
+    split(&read_all(PROC_ENV, None).unwrap_or_default()).filter_map(|var| var.split_once('=')).map(|(key, value)| (key.into(), value.into())).collect()
+}
+
+/// Returns the value of an environment variable, or `None` if it is not set.
+pub fn var(name: &str) -> Option<String>
+{
+    let name = c_path(name);
+    let value = read_all(PROC_ENV_VAR, Some(name.as_ptr() as u64)).ok()?;
+    String::from_utf8(value).ok()
+}
+
+//the kernel returns strings each followed by a nul
+fn split(data: &[u8]) -> impl Iterator<Item = &str>
+{
+    core::str::from_utf8(data).unwrap_or_default().split_terminator('\0')
+}
\ No newline at end of file
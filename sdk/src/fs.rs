@@ -0,0 +1,183 @@
+//! Files on the kernel's filesystem.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use embedded_io::ErrorKind;
+use crate::handle;
+use crate::syscall::*;
+
+pub use crate::abi_defs::{MODE_SETUID, MODE_SETGID, MODE_READ, MODE_WRITE, MODE_EXEC};
+
+/// Most bytes a single write can carry, longer ones fail with `ErrorKind::InvalidInput`.
+pub const MAX_WRITE: usize = 16 * 1024;
+
+/// Owner, group and mode bits of a file.
+///
+/// Only root and the owner can change them, and only root can give a file to another owner.
+/// Writes by anyone but root clear the setuid and setgid bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilePermissions
+{
+    pub owner: u32,
+    pub group: u32,
+    pub mode: u16
+}
+
+//how the kernel reads and writes permissions
+const PERMS_SIZE: usize = 10;
+
+//what the kernel returns for syscalls that copy data into a buffer of the caller
+#[repr(C)]
+pub(crate) struct CVecShort
+{
+    pub(crate) res: i8,
+    pub(crate) ptr: *const u8,
+    pub(crate) len: u64
+}
+
+//the kernel expects nul terminated paths
+pub(crate) fn c_path(path: &str) -> Vec<u8>
+{
+    let mut res = Vec::with_capacity(path.len() + 1);
+    res.extend_from_slice(path.as_bytes());
+    res.push(0);
+    res
+}
+
+//reads into `buf` and returns the full size of the data, `arg` is passed before the buffer if given
+fn read_into(nr: u64, arg: Option<u64>, buf: &mut [u8]) -> Result<usize, ErrorKind>
+{
+    let mut out = CVecShort{res: 0, ptr: core::ptr::null(), len: 0};
+    let out_ptr = &mut out as *mut CVecShort as u64;
+    match arg
+    {
+        Some(arg) => unsafe{syscall4(nr, out_ptr, arg, buf.as_mut_ptr() as u64, buf.len() as u64)},
+        None => unsafe{syscall3(nr, out_ptr, buf.as_mut_ptr() as u64, buf.len() as u64)}
+    };
+    errorkind_res(out.res as u64)?;
+    Ok(out.len as usize)
+}
+
+//reads all of the data a syscall returns into a buffer of the caller
+pub(crate) fn read_all(nr: u64, arg: Option<u64>) -> Result<Vec<u8>, ErrorKind>
+{
+    let mut buf = Vec::new();
+    loop
+    {
+        let len = read_into(nr, arg, &mut buf)?;
+        //the data can change size between the calls
+        if len <= buf.len()
+        {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+        buf = vec![0; len];
+    }
+}
+
+pub fn read(path: &str) -> Result<Vec<u8>, ErrorKind>
+{
+    let path = c_path(path);
+    read_all(FILE_READ, Some(path.as_ptr() as u64))
+}
+
+pub fn read_perms(path: &str) -> Result<FilePermissions, ErrorKind>
+{
+    let path = c_path(path);
+    let mut buf = [0u8; PERMS_SIZE];
+    read_into(FILE_READ_MODE, Some(path.as_ptr() as u64), &mut buf)?;
+    let owner = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let group = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+    Ok(FilePermissions{owner, group, mode: u16::from_le_bytes([buf[8], buf[9]])})
+}
+
+pub fn write_perms(path: &str, perms: FilePermissions) -> Result<(), ErrorKind>
+{
+    let path = c_path(path);
+    let mut buf = [0u8; PERMS_SIZE];
+    buf[..4].copy_from_slice(&perms.owner.to_le_bytes());
+    buf[4..8].copy_from_slice(&perms.group.to_le_bytes());
+    buf[8..].copy_from_slice(&perms.mode.to_le_bytes());
+    errorkind_res(unsafe{syscall3(FILE_WRITE_MODE, path.as_ptr() as u64, buf.as_ptr() as u64, buf.len() as u64)})
+}
+
+pub fn write(path: &str, data: &[u8]) -> Result<(), ErrorKind>
+{
+    let path = c_path(path);
+    errorkind_res(unsafe{syscall3(FILE_WRITE, path.as_ptr() as u64, data.as_ptr() as u64, data.len() as u64)})
+}
+
+pub fn delete(path: &str) -> Result<(), ErrorKind>
+{
+    let path = c_path(path);
+    errorkind_res(unsafe{syscall1(FILE_DELETE, path.as_ptr() as u64)})
+}
+
+/// Creates an empty file owned by the effective user and group of the process.
+///
+/// Fails with `AlreadyExists` if there is a file at `path`.
+pub fn create(path: &str) -> Result<(), ErrorKind>
+{
+    let path = c_path(path);
+    errorkind_res(unsafe{syscall1(FILE_CREATE, path.as_ptr() as u64)})
+}
+
+/// A handle to a file, closed when dropped.
+///
+/// Access is checked once, when the handle is opened. It can be passed on to processes that
+/// couldn't open the file themselves, a copy with fewer rights limits what they can do.
+///
+/// The handle refers to the file itself, not its path. Once the file is deleted reads and writes
+/// fail with `NotFound`, even if another file is created at the path.
+pub struct FileHandle
+{
+    handle: u64
+}
+
+/// Opens a handle to the file at `path` with `rights`, made of `handle::RIGHT_READ`,
+/// `handle::RIGHT_WRITE` and `handle::RIGHT_TRANSFER`.
+pub fn open(path: &str, rights: u32) -> Result<FileHandle, ErrorKind>
+{
+    let path = c_path(path);
+    let mut handle = 0u64;
+    errorkind_res(unsafe{syscall3(FILE_OPEN, path.as_ptr() as u64, rights as u64, &mut handle as *mut u64 as u64)})?;
+    Ok(FileHandle{handle})
+}
+
+impl FileHandle
+{
+    /// Takes over a handle the process got some other way, like from its parent or over an endpoint.
+    pub fn from_raw(handle: u64) -> Self
+    {
+        FileHandle{handle}
+    }
+
+    pub fn handle(&self) -> u64
+    {
+        self.handle
+    }
+
+    /// Returns a copy of the handle with `rights`, which can't include any this one lacks.
+    pub fn duplicate(&self, rights: u32) -> Result<FileHandle, ErrorKind>
+    {
+        handle::duplicate(self.handle, rights).map(FileHandle::from_raw)
+    }
+
+    pub fn read(&self) -> Result<Vec<u8>, ErrorKind>
+    {
+        read_all(FILE_HANDLE_READ, Some(self.handle))
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<(), ErrorKind>
+    {
+        errorkind_res(unsafe{syscall3(FILE_HANDLE_WRITE, self.handle, data.as_ptr() as u64, data.len() as u64)})
+    }
+}
+
+impl Drop for FileHandle
+{
+    fn drop(&mut self)
+    {
+        let _ = handle::close(self.handle);
+    }
+}
\ No newline at end of file
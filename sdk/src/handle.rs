@@ -0,0 +1,41 @@
+//! Handles, the numbers the kernel gives out for objects like pipe ends.
+//!
+//! Each handle carries rights that say what it can be used for. Copies made with `duplicate`
+//! can only have fewer, so a process can hand out e.g. a read-only copy of a file handle.
+
+use embedded_io::ErrorKind;
+use crate::syscall::*;
+
+pub use crate::abi_defs::{RIGHT_READ, RIGHT_WRITE, RIGHT_SIGNAL, RIGHT_KILL, RIGHT_TRANSFER, RIGHTS_ALL};
+
+/// Returns a new handle to the same object with `rights`, which fails with `PermissionDenied` if
+/// they include any the original lacks.
+pub fn duplicate(handle: u64, rights: u32) -> Result<u64, ErrorKind>
+{
+    let mut new = 0u64;
+    errorkind_res(unsafe{syscall3(HANDLE_DUPLICATE, handle, rights as u64, &mut new as *mut u64 as u64)})?;
+    Ok(new)
+}
+
+/// Returns the rights of a handle.
+pub fn rights(handle: u64) -> Result<u32, ErrorKind>
+{
+    let mut rights = 0u32;
+    errorkind_res(unsafe{syscall2(HANDLE_RIGHTS, handle, &mut rights as *mut u32 as u64)})?;
+    Ok(rights)
+}
+
+/// Closes a handle, the object lives on while other handles, e.g. of children, refer to it.
+pub fn close(handle: u64) -> Result<(), ErrorKind>
+{
+    errorkind_res(unsafe{syscall1(HANDLE_CLOSE, handle)})
+}
+
+/// Sets whether children spawned afterwards get a copy of the handle, which needs `RIGHT_TRANSFER`.
+///
+/// Children get inherited handles under the same numbers, so they can be passed to them as
+/// arguments or environment variables.
+pub fn set_inherit(handle: u64, inherit: bool) -> Result<(), ErrorKind>
+{
+    errorkind_res(unsafe{syscall2(HANDLE_INHERIT, handle, inherit.into())})
+}
\ No newline at end of file
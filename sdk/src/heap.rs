@@ -0,0 +1,92 @@
+//! Memory from the kernel and the global allocator built on it.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use embedded_io::ErrorKind;
+use linked_list_allocator::Heap;
+use spin::Mutex;
+use crate::syscall::*;
+
+pub const PAGE_SIZE: usize = 4096;
+//the heap grows by at least this many pages, so small allocations don't each cost a syscall
+const GROW_PAGES: usize = 16;
+
+/// Maps `pages` zeroed pages, at `addr` unless it is null, and returns their address.
+pub fn map_pages(addr: *mut u8, pages: usize) -> Result<*mut u8, ErrorKind>
+{
+    let res = unsafe{syscall2(MEM_MAP, addr as u64, pages as u64)};
+    if res == 0 {Err(ErrorKind::OutOfMemory)}
+    else {Ok(res as *mut u8)}
+}
+
+/// Unmaps pages returned by `map_pages`, fails with `InvalidInput` for any others.
+///
+/// # Safety
+///
+/// Nothing may use the pages anymore.
+pub unsafe fn unmap_pages(addr: *mut u8, pages: usize) -> Result<(), ErrorKind>
+{
+    errorkind_res(unsafe{syscall2(MEM_UNMAP, addr as u64, pages as u64)})
+}
+
+/// Allocator that grows its heap with pages mapped right after it.
+pub struct ProcessHeap
+{
+    heap: Mutex<Heap>
+}
+
+impl ProcessHeap
+{
+    pub const fn new() -> Self
+    {
+        ProcessHeap{heap: Mutex::new(Heap::empty())}
+    }
+}
+
+impl Default for ProcessHeap
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+//maps enough pages for an allocation of `layout` to fit, returns false if that failed
+fn grow(heap: &mut Heap, layout: Layout) -> bool
+{
+    let pages = (layout.size() + layout.align() + PAGE_SIZE).div_ceil(PAGE_SIZE).max(GROW_PAGES);
+    if heap.size() == 0
+    {
+        match map_pages(ptr::null_mut(), pages)
+        {
+            Ok(addr) => unsafe{heap.init(addr, pages * PAGE_SIZE)},
+            Err(_) => return false
+        }
+    }
+    else
+    {
+        if map_pages(heap.top(), pages).is_err() {return false;}
+        unsafe{heap.extend(pages * PAGE_SIZE)};
+    }
+    true
+}
+
+unsafe impl GlobalAlloc for ProcessHeap
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8
+    {
+        let mut heap = self.heap.lock();
+        if let Ok(ptr) = heap.allocate_first_fit(layout) {return ptr.as_ptr();}
+        if !grow(&mut heap, layout) {return ptr::null_mut();}
+        heap.allocate_first_fit(layout).map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout)
+    {
+        unsafe{self.heap.lock().deallocate(NonNull::new_unchecked(ptr), layout)};
+    }
+}
+
+#[cfg(target_os = "none")]
+#[global_allocator]
+static ALLOCATOR: ProcessHeap = ProcessHeap::new();
\ No newline at end of file
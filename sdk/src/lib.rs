@@ -0,0 +1,70 @@
+//! Library for writing programs that run as processes on the kernel.
+//!
+//! A program is a `no_std`, `no_main` binary that names its main function with [`entry!`]:
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! sdk::entry!(main);
+//!
+//! fn main() -> i32
+//! {
+//!     0
+//! }
+//! ```
+//!
+//! Build it for `x86_64-unknown-none`, the resulting position independent ELF can be
+//! loaded with `Process::spawn`, e.g. `cargo build -p sdk --examples --target x86_64-unknown-none`.
+
+#![no_std]
+
+extern crate alloc;
+
+//shared with the kernel, so syscall numbers and error codes can't drift from what it implements
+#[allow(dead_code)]
+#[path = "../../kernel/src/abi_defs.rs"]
+mod abi_defs;
+
+pub mod abi;
+pub mod syscall;
+pub mod endpoint;
+pub mod env;
+pub mod fs;
+pub mod handle;
+pub mod heap;
+pub mod pipe;
+pub mod process;
+pub mod rand;
+pub mod shm;
+pub mod signal;
+pub mod sync;
+pub mod thread;
+pub mod time;
+
+pub use embedded_io::ErrorKind;
+
+/// Defines the entry point of the program.
+///
+/// The given function takes no arguments and returns the exit code of the process.
+#[macro_export]
+macro_rules! entry
+{
+    ($main:path) =>
+    {
+        #[no_mangle]
+        extern "C" fn _start(table: *const $crate::abi::SysCallTableHeader) -> bool
+        {
+            unsafe{$crate::abi::init(table)};
+            let main: fn() -> i32 = $main;
+            $crate::process::exit(main())
+        }
+    };
+}
+
+#[cfg(target_os = "none")]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> !
+{
+    process::exit(process::EXIT_PANIC)
+}
\ No newline at end of file
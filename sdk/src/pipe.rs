@@ -0,0 +1,107 @@
+//! Pipes, one way byte streams that can connect processes.
+//!
+//! To hand an end to a child, mark it inherited before spawning the child and tell the child
+//! its handle, then drop the parent's copy so the pipe closes once the child is done with it.
+
+use alloc::vec::Vec;
+use embedded_io::ErrorKind;
+use crate::handle;
+use crate::syscall::*;
+
+/// Read end of a pipe, closed when dropped.
+pub struct PipeReader
+{
+    handle: u64
+}
+
+/// Write end of a pipe, closed when dropped.
+pub struct PipeWriter
+{
+    handle: u64
+}
+
+/// Creates a pipe and returns its read and write end.
+pub fn pipe() -> Result<(PipeReader, PipeWriter), ErrorKind>
+{
+    let mut read = 0u64;
+    let mut write = 0u64;
+    errorkind_res(unsafe{syscall2(PIPE_CREATE, &mut read as *mut u64 as u64, &mut write as *mut u64 as u64)})?;
+    Ok((PipeReader{handle: read}, PipeWriter{handle: write}))
+}
+
+impl PipeReader
+{
+    /// Takes over a handle, e.g. one inherited from the parent.
+    pub fn from_handle(handle: u64) -> Self
+    {
+        PipeReader{handle}
+    }
+
+    pub fn handle(&self) -> u64
+    {
+        self.handle
+    }
+
+    /// Blocks until there is data and reads as much of it as fits into `buf`, returns 0 once
+    /// the write end is closed and everything was read. Fails with `Interrupted` if a signal
+    /// arrives while it blocks.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, ErrorKind>
+    {
+        let mut read = 0u64;
+        errorkind_res(unsafe{syscall4(PIPE_READ, self.handle, buf.as_mut_ptr() as u64, buf.len() as u64, &mut read as *mut u64 as u64)})?;
+        Ok(read as usize)
+    }
+
+    /// Reads until the write end is closed.
+    pub fn read_to_end(&self) -> Result<Vec<u8>, ErrorKind>
+    {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 512];
+        loop
+        {
+            match self.read(&mut buf)?
+            {
+                0 => return Ok(data),
+                n => data.extend_from_slice(&buf[..n])
+            }
+        }
+    }
+}
+
+impl PipeWriter
+{
+    /// Takes over a handle, e.g. one inherited from the parent.
+    pub fn from_handle(handle: u64) -> Self
+    {
+        PipeWriter{handle}
+    }
+
+    pub fn handle(&self) -> u64
+    {
+        self.handle
+    }
+
+    /// Writes all of `data`, blocking while the pipe is full. Fails with `BrokenPipe` once the
+    /// read end is closed, and with `Interrupted` if a signal arrives while it blocks, part of
+    /// `data` might have been written then.
+    pub fn write_all(&self, data: &[u8]) -> Result<(), ErrorKind>
+    {
+        errorkind_res(unsafe{syscall3(PIPE_WRITE, self.handle, data.as_ptr() as u64, data.len() as u64)})
+    }
+}
+
+impl Drop for PipeReader
+{
+    fn drop(&mut self)
+    {
+        let _ = handle::close(self.handle);
+    }
+}
+
+impl Drop for PipeWriter
+{
+    fn drop(&mut self)
+    {
+        let _ = handle::close(self.handle);
+    }
+}
\ No newline at end of file
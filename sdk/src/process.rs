@@ -0,0 +1,366 @@
+//! Starting, stopping and inspecting processes.
+
+use alloc::vec::Vec;
+use embedded_io::ErrorKind;
+use crate::abi_defs::PROCESS_ERRORS;
+use crate::fs::c_path;
+use crate::handle;
+use crate::syscall::*;
+
+/// Exit code of a process whose program panicked.
+pub const EXIT_PANIC: i32 = 101;
+/// Exit code the kernel reports for killed processes.
+pub const EXIT_KILLED: i32 = -1;
+/// Number of priority levels, higher levels are scheduled first.
+pub const PRIORITY_LEVELS: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i8)]
+pub enum ProcessError
+{
+    Unprivileged,
+    NotFound,
+    InvalidInput,
+    /// The executable or a library it needs could not be read from the filesystem.
+    ReadFailed,
+    /// The executable is not a valid ELF file for this machine.
+    InvalidExecutable,
+    /// There was not enough memory to load the executable.
+    OutOfMemory,
+    /// The caller has as many uncollected children as its limits allow.
+    LimitExceeded,
+    /// A signal arrived while the call was blocked.
+    Interrupted,
+    /// A code this version of the library doesn't know about.
+    Unknown
+}
+
+//`Unknown` has no code
+const _: () = assert!(PROCESS_ERRORS.len() == ProcessError::Interrupted as usize + 1);
+
+//in code order, built from the names so an error that moves or is added fails to compile
+const PROCESS_ERROR_CODES: [ProcessError; PROCESS_ERRORS.len()] =
+{
+    let mut errors = [ProcessError::Unknown; PROCESS_ERRORS.len()];
+    let mut i = 0;
+    while i < errors.len()
+    {
+        errors[i] = ProcessError::from_name(PROCESS_ERRORS[i]);
+        i += 1;
+    }
+    errors
+};
+
+impl ProcessError
+{
+    const fn from_name(name: &str) -> Self
+    {
+        let errors = [("UNPRIVILEGED", ProcessError::Unprivileged), ("NOT_FOUND", ProcessError::NotFound), ("INVALID_INPUT", ProcessError::InvalidInput), ("READ_FAILED", ProcessError::ReadFailed), ("INVALID_EXECUTABLE", ProcessError::InvalidExecutable), ("OUT_OF_MEMORY", ProcessError::OutOfMemory), ("LIMIT_EXCEEDED", ProcessError::LimitExceeded), ("INTERRUPTED", ProcessError::Interrupted)];
+        let mut i = 0;
+        while i < errors.len()
+        {
+            if str_eq(errors[i].0, name) {return errors[i].1;}
+            i += 1;
+        }
+        panic!("process error in abi_defs.rs unknown to the SDK")
+    }
+
+    fn from_code(code: i8) -> Self
+    {
+        *PROCESS_ERROR_CODES.get(code as usize).unwrap_or(&ProcessError::Unknown)
+    }
+}
+
+pub(crate) fn process_res(ret: u64) -> Result<(), ProcessError>
+{
+    if succeeded(ret) {Ok(())}
+    else {Err(ProcessError::from_code(ret as i8))}
+}
+
+/// Scheduling accounting of a process.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct ProcessStats
+{
+    /// Timer ticks spent running.
+    pub ticks: u64,
+    /// Times the scheduler switched to the process.
+    pub scheduled: u64,
+    pub priority: u8
+}
+
+/// Limits on what a process may use, `u64::MAX` means unlimited.
+///
+/// Exceeding the page or handle limit makes the call fail with `ErrorKind::OutOfMemory`,
+/// exceeding the CPU budget ends the process with `signal::SIGXCPU`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct ResourceLimits
+{
+    /// Pages of memory mapped at runtime, including thread stacks and shared memory.
+    pub pages: u64,
+    /// Handles held at the same time.
+    pub handles: u64,
+    /// Timer ticks the process may spend running.
+    pub cpu_ticks: u64,
+    /// Children that were not collected with `wait` or `wait_pid` yet.
+    pub children: u64
+}
+
+impl ResourceLimits
+{
+    pub const UNLIMITED: ResourceLimits = ResourceLimits{pages: u64::MAX, handles: u64::MAX, cpu_ticks: u64::MAX, children: u64::MAX};
+}
+
+impl Default for ResourceLimits
+{
+    fn default() -> Self
+    {
+        ResourceLimits::UNLIMITED
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct ProcessInfo
+{
+    pub pid: u64,
+    pub parent: u64,
+    /// Effective user id.
+    pub uid: u32,
+    /// Effective group id.
+    pub gid: u32
+}
+
+pub use crate::abi_defs::ROOT_ID;
+
+/// User and group identities of a process.
+///
+/// Permission checks use the effective ids. The real ids belong to whoever started the process,
+/// they only differ from the effective ones while it runs a setuid or setgid program.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct Credentials
+{
+    pub uid: u32,
+    pub gid: u32,
+    pub euid: u32,
+    pub egid: u32
+}
+
+/// Ends the process with the given exit code.
+pub fn exit(code: i32) -> !
+{
+    unsafe{syscall1(PROC_EXIT, code as u64)};
+    unreachable!()
+}
+
+/// Ends the process as if it had been killed.
+pub fn kill_self() -> !
+{
+    unsafe{syscall0(PROC_KILL_SELF)};
+    unreachable!()
+}
+
+pub fn pid() -> u64
+{
+    unsafe{syscall0(PROC_PID)}
+}
+
+/// Starts the program stored at `path` as a child and returns its pid.
+///
+/// The child gets the path as its only argument and the environment of the caller.
+pub fn spawn(path: &str) -> Result<u64, ProcessError>
+{
+    let path = c_path(path);
+    let mut pid = 0u64;
+    process_res(unsafe{syscall2(PROC_SPAWN_PATH, path.as_ptr() as u64, &mut pid as *mut u64 as u64)})?;
+    Ok(pid)
+}
+
+/// Starts the program stored at `path` as a child with the given arguments and returns its pid.
+///
+/// Without `env` the child gets the environment of the caller.
+pub fn spawn_with(path: &str, args: &[&str], env: Option<&[(&str, &str)]>) -> Result<u64, ProcessError>
+{
+    let path = c_path(path);
+    //the kernel takes both as strings each followed by a nul
+    let mut arg_block = Vec::new();
+    for arg in args
+    {
+        arg_block.extend_from_slice(arg.as_bytes());
+        arg_block.push(0);
+    }
+    let env_block = env.map(|env|
+    {
+        let mut block = Vec::new();
+        for (key, value) in env
+        {
+            block.extend_from_slice(key.as_bytes());
+            block.push(b'=');
+            block.extend_from_slice(value.as_bytes());
+            block.push(0);
+        }
+        block
+    });
+    let (env_ptr, env_len) = env_block.as_ref().map_or((0, 0), |block| (block.as_ptr() as u64, block.len() as u64));
+    let mut pid = 0u64;
+    process_res(unsafe{syscall6(PROC_SPAWN_ARGS, path.as_ptr() as u64, arg_block.as_ptr() as u64, arg_block.len() as u64, env_ptr, env_len, &mut pid as *mut u64 as u64)})?;
+    Ok(pid)
+}
+
+/// Returns the resource limits of a process.
+pub fn limits(pid: u64) -> Result<ResourceLimits, ProcessError>
+{
+    let mut limits = ResourceLimits::UNLIMITED;
+    process_res(unsafe{syscall2(PROC_LIMITS, pid, &mut limits as *mut ResourceLimits as u64)})?;
+    Ok(limits)
+}
+
+/// Sets the limits children spawned from now on start with, by default they get the caller's own.
+///
+/// Unless the caller runs as root, limits above its own are lowered to those.
+pub fn set_child_limits(limits: &ResourceLimits) -> Result<(), ProcessError>
+{
+    process_res(unsafe{syscall1(PROC_SET_CHILD_LIMITS, limits as *const ResourceLimits as u64)})
+}
+
+/// Returns the user and group identities of a process.
+pub fn ids(pid: u64) -> Result<Credentials, ProcessError>
+{
+    let mut cred = Credentials::default();
+    process_res(unsafe{syscall2(PROC_IDS, pid, &mut cred as *mut Credentials as u64)})?;
+    Ok(cred)
+}
+
+/// Sets the real and effective user and group of the process.
+///
+/// Root can switch to any ids, everyone else only to their current real or effective ones, so a
+/// setuid program can drop back to the user that started it.
+pub fn set_ids(uid: u32, gid: u32) -> Result<(), ProcessError>
+{
+    process_res(unsafe{syscall2(PROC_SET_IDS, uid as u64, gid as u64)})
+}
+
+/// Kills another process, the caller has to run as root.
+pub fn kill(pid: u64) -> Result<(), ProcessError>
+{
+    process_res(unsafe{syscall1(PROC_KILL, pid)})
+}
+
+/// Blocks until a child has exited and returns its pid and exit code.
+///
+/// Fails with `Interrupted` if a signal arrives first.
+pub fn wait() -> Result<(u64, i32), ProcessError>
+{
+    let mut pid = 0u64;
+    let mut code = 0i32;
+    process_res(unsafe{syscall2(PROC_WAIT, &mut pid as *mut u64 as u64, &mut code as *mut i32 as u64)})?;
+    Ok((pid, code))
+}
+
+/// Blocks until the given child has exited and returns its exit code.
+///
+/// Fails with `Interrupted` if a signal arrives first.
+pub fn wait_pid(pid: u64) -> Result<i32, ProcessError>
+{
+    let mut code = 0i32;
+    process_res(unsafe{syscall2(PROC_WAITPID, pid, &mut code as *mut i32 as u64)})?;
+    Ok(code)
+}
+
+/// Changes the priority of a process, the caller has to run as root.
+pub fn set_priority(pid: u64, priority: u8) -> Result<(), ProcessError>
+{
+    process_res(unsafe{syscall2(PROC_SET_PRIORITY, pid, priority.into())})
+}
+
+/// Changes how many timer ticks processes run before being preempted, the caller has to run as root.
+pub fn set_time_slice(ticks: u64) -> Result<(), ProcessError>
+{
+    process_res(unsafe{syscall1(PROC_SET_TIME_SLICE, ticks)})
+}
+
+pub fn stats(pid: u64) -> Result<ProcessStats, ProcessError>
+{
+    let mut stats = ProcessStats::default();
+    process_res(unsafe{syscall2(PROC_STATS, pid, &mut stats as *mut ProcessStats as u64)})?;
+    Ok(stats)
+}
+
+/// Returns every process that has not exited.
+pub fn list() -> Vec<ProcessInfo>
+{
+    let mut buf: Vec<ProcessInfo> = Vec::new();
+    loop
+    {
+        let total = unsafe{syscall2(PROC_LIST_IDS, buf.as_mut_ptr() as u64, buf.len() as u64)} as usize;
+        //processes can start between the calls
+        if total <= buf.len()
+        {
+            buf.truncate(total);
+            return buf;
+        }
+        buf.resize(total, ProcessInfo::default());
+    }
+}
+
+/// A handle to a process, closed when dropped.
+///
+/// Its holder can signal or kill the process as far as the rights of the handle allow, whatever
+/// its own identities are.
+pub struct ProcessHandle
+{
+    handle: u64
+}
+
+/// Opens a handle to a process with `rights`, made of `handle::RIGHT_SIGNAL`,
+/// `handle::RIGHT_KILL` and `handle::RIGHT_TRANSFER`.
+///
+/// Unless the caller runs as root it can only open itself and its children.
+pub fn open(pid: u64, rights: u32) -> Result<ProcessHandle, ProcessError>
+{
+    let mut handle = 0u64;
+    process_res(unsafe{syscall3(PROC_OPEN, pid, rights as u64, &mut handle as *mut u64 as u64)})?;
+    Ok(ProcessHandle{handle})
+}
+
+impl ProcessHandle
+{
+    /// Takes over a handle the process got some other way, like from its parent or over an endpoint.
+    pub fn from_raw(handle: u64) -> Self
+    {
+        ProcessHandle{handle}
+    }
+
+    pub fn handle(&self) -> u64
+    {
+        self.handle
+    }
+
+    /// Returns a copy of the handle with `rights`, which can't include any this one lacks.
+    pub fn duplicate(&self, rights: u32) -> Result<ProcessHandle, ErrorKind>
+    {
+        handle::duplicate(self.handle, rights).map(ProcessHandle::from_raw)
+    }
+
+    /// Sends `sig` to the process, the handle needs `RIGHT_SIGNAL`.
+    pub fn signal(&self, sig: u8) -> Result<(), ProcessError>
+    {
+        process_res(unsafe{syscall2(PROC_HANDLE_SIGNAL, self.handle, sig.into())})
+    }
+
+    /// Kills the process, the handle needs `RIGHT_KILL`.
+    pub fn kill(&self) -> Result<(), ProcessError>
+    {
+        process_res(unsafe{syscall1(PROC_HANDLE_KILL, self.handle)})
+    }
+}
+
+impl Drop for ProcessHandle
+{
+    fn drop(&mut self)
+    {
+        let _ = handle::close(self.handle);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,18 @@
+//! Random numbers from the kernel.
+
+use embedded_io::ErrorKind;
+use crate::syscall::*;
+
+/// Fills `buf` with random bytes.
+pub fn fill(buf: &mut [u8]) -> Result<(), ErrorKind>
+{
+    errorkind_res(unsafe{syscall2(RAND_BUFFER, buf.as_mut_ptr() as u64, buf.len() as u64)})
+}
+
+pub fn u64() -> u64
+{
+    let mut buf = [0u8; 8];
+    //the buffer is always valid, so this can't fail
+    let _ = fill(&mut buf);
+    u64::from_le_bytes(buf)
+}
\ No newline at end of file
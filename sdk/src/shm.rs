@@ -0,0 +1,98 @@
+//! Named shared memory, pages that several processes map to exchange data without copying.
+//!
+//! One process creates an object under a name, others map it by that name. The object is freed
+//! once no process has it mapped anymore. Accesses are not synchronized, use the futexes in
+//! [`crate::sync`] on words inside the memory for that.
+//!
+//! Objects have an owner, a group and mode bits like files, which decide who may map them and how.
+//! No mapping can be writable and executable at once.
+
+use embedded_io::ErrorKind;
+use crate::fs::c_path;
+use crate::syscall::*;
+
+/// Most pages an object can have.
+pub const MAX_PAGES: usize = 1024;
+/// Mode of objects made by [`create`], only the owner can map them.
+pub const DEFAULT_MODE: u16 = 0o600;
+
+/// What a mapping allows besides reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Protection
+{
+    pub writable: bool,
+    pub executable: bool
+}
+
+impl Protection
+{
+    pub const READ_ONLY: Protection = Protection{writable: false, executable: false};
+    pub const READ_WRITE: Protection = Protection{writable: true, executable: false};
+}
+
+/// A shared memory object mapped into this process, unmapped when dropped.
+pub struct SharedMemory
+{
+    addr: *mut u8,
+    pages: usize
+}
+
+/// Creates a zeroed object of `pages` pages under `name` with [`DEFAULT_MODE`] and maps it.
+///
+/// Fails with `AlreadyExists` if the name is taken.
+pub fn create(name: &str, pages: usize, prot: Protection) -> Result<SharedMemory, ErrorKind>
+{
+    let name = c_path(name);
+    let mut addr = 0u64;
+    errorkind_res(unsafe{syscall5(SHM_CREATE, name.as_ptr() as u64, pages as u64, prot.writable.into(), prot.executable.into(), &mut addr as *mut u64 as u64)})?;
+    Ok(SharedMemory{addr: addr as *mut u8, pages})
+}
+
+/// Creates a zeroed object like [`create`], owned by this process' effective ids with the
+/// permission bits `mode`.
+pub fn create_mode(name: &str, pages: usize, mode: u16, prot: Protection) -> Result<SharedMemory, ErrorKind>
+{
+    let name = c_path(name);
+    let mut addr = 0u64;
+    errorkind_res(unsafe{syscall6(SHM_CREATE_MODE, name.as_ptr() as u64, pages as u64, mode.into(), prot.writable.into(), prot.executable.into(), &mut addr as *mut u64 as u64)})?;
+    Ok(SharedMemory{addr: addr as *mut u8, pages})
+}
+
+/// Maps the object named `name`, fails with `NotFound` if there is none and `PermissionDenied`
+/// if its mode doesn't allow this process such a mapping.
+pub fn open(name: &str, prot: Protection) -> Result<SharedMemory, ErrorKind>
+{
+    let name = c_path(name);
+    let mut addr = 0u64;
+    let mut pages = 0u64;
+    errorkind_res(unsafe{syscall5(SHM_MAP, name.as_ptr() as u64, prot.writable.into(), prot.executable.into(), &mut addr as *mut u64 as u64, &mut pages as *mut u64 as u64)})?;
+    Ok(SharedMemory{addr: addr as *mut u8, pages: pages as usize})
+}
+
+impl SharedMemory
+{
+    /// Start of the mapping, writes through it fault unless it was mapped writable.
+    pub fn as_ptr(&self) -> *mut u8
+    {
+        self.addr
+    }
+
+    /// Size of the mapping in bytes.
+    pub fn len(&self) -> usize
+    {
+        self.pages * 4096
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.pages == 0
+    }
+}
+
+impl Drop for SharedMemory
+{
+    fn drop(&mut self)
+    {
+        let _ = errorkind_res(unsafe{syscall1(SHM_UNMAP, self.addr as u64)});
+    }
+}
\ No newline at end of file
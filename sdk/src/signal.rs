@@ -0,0 +1,55 @@
+//! Signals, asynchronous notifications processes send each other.
+//!
+//! Without a handler most signals end the process, handlers let it shut down cleanly instead.
+//! A handler runs on the stack of whichever thread resumes next, like code interrupted at an
+//! arbitrary point, so it should only touch atomics and other state that is safe to use there.
+
+use crate::process::{ProcessError, process_res};
+use crate::syscall::*;
+
+pub use crate::abi_defs::{SIGHUP, SIGINT, SIGQUIT, SIGABRT, SIGKILL, SIGUSR1, SIGSEGV, SIGUSR2, SIGPIPE, SIGALRM, SIGTERM, SIGCHLD, SIGCONT, SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU, SIGURG, SIGXCPU, SIGWINCH};
+
+/// A process ended by signal `sig` exits with code `EXIT_SIGNALED + sig`.
+pub const EXIT_SIGNALED: i32 = 128;
+
+/// What a process does when it receives a signal.
+#[derive(Clone, Copy)]
+pub enum Handler
+{
+    /// Terminates the process, except for `SIGCHLD`, `SIGURG` and `SIGWINCH`, which are
+    /// ignored, the stop signals, which stop it, and `SIGCONT`, which continues it.
+    Default,
+    Ignore,
+    /// Called with the signal number, which stays blocked until the function returns.
+    Function(extern "C" fn(i32))
+}
+
+/// Sets what the calling process does when it receives `sig`.
+///
+/// `SIGKILL` and `SIGSTOP` always have their default action, setting theirs fails with `InvalidInput`.
+pub fn set_handler(sig: u8, handler: Handler) -> Result<(), ProcessError>
+{
+    let handler = match handler
+    {
+        Handler::Default => 0,
+        Handler::Ignore => 1,
+        Handler::Function(f) => f as usize as u64
+    };
+    let mut old = 0u64;
+    process_res(unsafe{syscall3(SIGNAL_ACTION, sig.into(), handler, &mut old as *mut u64 as u64)})
+}
+
+/// Sends `sig` to the process `pid`.
+///
+/// Unprivileged processes can only signal themselves and their children.
+pub fn send(pid: u64, sig: u8) -> Result<(), ProcessError>
+{
+    process_res(unsafe{syscall2(PROC_SIGNAL, pid, sig.into())})
+}
+
+/// Sends `sig` to the calling process, its handler has run by the time this returns unless the
+/// signal is blocked because its handler is running already.
+pub fn raise(sig: u8) -> Result<(), ProcessError>
+{
+    send(crate::process::pid(), sig)
+}
\ No newline at end of file
@@ -0,0 +1,29 @@
+//! Futexes, the kernel primitive for blocking until another thread changes a value.
+//!
+//! A thread calls [`wait`] with the value it saw and the thread that changes it calls [`wake`]
+//! afterwards. Since the kernel checks the value before blocking, a wake between the check in
+//! user code and the wait is never lost.
+
+use core::sync::atomic::AtomicU32;
+use embedded_io::ErrorKind;
+use crate::syscall::*;
+
+/// Blocks the calling thread until `futex` is woken, unless it no longer holds `expected`.
+///
+/// This can also return without a wake, so callers have to check the value again.
+pub fn wait(futex: &AtomicU32, expected: u32) -> Result<(), ErrorKind>
+{
+    errorkind_res(unsafe{syscall2(FUTEX_WAIT, futex.as_ptr() as u64, expected.into())})
+}
+
+/// Wakes up to `count` threads waiting on `futex` and returns how many were woken.
+pub fn wake(futex: &AtomicU32, count: u64) -> u64
+{
+    unsafe{syscall2(FUTEX_WAKE, futex.as_ptr() as u64, count)}
+}
+
+/// Wakes every thread waiting on `futex`.
+pub fn wake_all(futex: &AtomicU32) -> u64
+{
+    wake(futex, u64::MAX)
+}
\ No newline at end of file
@@ -0,0 +1,222 @@
+//! Raw syscall numbers and the `int 0x80` instruction sequences.
+//!
+//! The numbers come from `kernel/src/abi_defs.rs`, the description the kernel and its C header
+//! are built from.
+
+use core::arch::asm;
+use embedded_io::ErrorKind;
+use crate::abi_defs::SYSCALLS;
+
+pub const FILE_READ_PERMS: u64 = nr("file_read_perms");
+pub const FILE_WRITE_PERMS: u64 = nr("file_write_perms");
+pub const FILE_READ: u64 = nr("file_read");
+pub const FILE_WRITE: u64 = nr("file_write");
+pub const FILE_DELETE: u64 = nr("file_delete");
+pub const FILE_CREATE: u64 = nr("file_create");
+pub const TIME_NOW: u64 = nr("time_now");
+pub const RAND_BUFFER: u64 = nr("rand_buffer");
+pub const PROC_SET_PRIORITY: u64 = nr("proc_set_priority");
+pub const PROC_SET_TIME_SLICE: u64 = nr("proc_set_time_slice");
+pub const PROC_STATS: u64 = nr("proc_stats");
+pub const PROC_EXIT: u64 = nr("proc_exit");
+pub const PROC_WAIT: u64 = nr("proc_wait");
+pub const PROC_WAITPID: u64 = nr("proc_waitpid");
+pub const PROC_SPAWN_PATH: u64 = nr("proc_spawn_path");
+pub const PROC_KILL: u64 = nr("proc_kill");
+pub const PROC_KILL_SELF: u64 = nr("proc_kill_self");
+pub const PROC_PID: u64 = nr("proc_pid");
+pub const PROC_LIST: u64 = nr("proc_list");
+pub const MEM_MAP: u64 = nr("mem_map");
+pub const MEM_UNMAP: u64 = nr("mem_unmap");
+pub const ABI_SUPPORTED: u64 = nr("abi_supported");
+pub const PROC_ARGS: u64 = nr("proc_args");
+pub const PROC_ENV: u64 = nr("proc_env");
+pub const PROC_ENV_VAR: u64 = nr("proc_env_var");
+pub const PROC_SPAWN_ARGS: u64 = nr("proc_spawn_args");
+pub const THREAD_CREATE: u64 = nr("thread_create");
+pub const THREAD_JOIN: u64 = nr("thread_join");
+pub const THREAD_EXIT: u64 = nr("thread_exit");
+pub const THREAD_YIELD: u64 = nr("thread_yield");
+pub const THREAD_ID: u64 = nr("thread_id");
+pub const SLEEP: u64 = nr("sleep");
+pub const FUTEX_WAIT: u64 = nr("futex_wait");
+pub const FUTEX_WAKE: u64 = nr("futex_wake");
+pub const PIPE_CREATE: u64 = nr("pipe_create");
+pub const PIPE_READ: u64 = nr("pipe_read");
+pub const PIPE_WRITE: u64 = nr("pipe_write");
+pub const HANDLE_CLOSE: u64 = nr("handle_close");
+pub const HANDLE_INHERIT: u64 = nr("handle_inherit");
+pub const ENDPOINT_REGISTER: u64 = nr("endpoint_register");
+pub const ENDPOINT_SEND: u64 = nr("endpoint_send");
+pub const ENDPOINT_SEND_PID: u64 = nr("endpoint_send_pid");
+pub const ENDPOINT_RECEIVE: u64 = nr("endpoint_receive");
+pub const SHM_CREATE: u64 = nr("shm_create");
+pub const SHM_MAP: u64 = nr("shm_map");
+pub const SHM_UNMAP: u64 = nr("shm_unmap");
+pub const PROC_SIGNAL: u64 = nr("proc_signal");
+pub const SIGNAL_ACTION: u64 = nr("signal_action");
+pub const SIGNAL_RETURN: u64 = nr("signal_return");
+pub const PROC_LIMITS: u64 = nr("proc_limits");
+pub const PROC_SET_CHILD_LIMITS: u64 = nr("proc_set_child_limits");
+pub const PROC_IDS: u64 = nr("proc_ids");
+pub const PROC_SET_IDS: u64 = nr("proc_set_ids");
+pub const HANDLE_DUPLICATE: u64 = nr("handle_duplicate");
+pub const HANDLE_RIGHTS: u64 = nr("handle_rights");
+pub const FILE_OPEN: u64 = nr("file_open");
+pub const FILE_HANDLE_READ: u64 = nr("file_handle_read");
+pub const FILE_HANDLE_WRITE: u64 = nr("file_handle_write");
+pub const PROC_OPEN: u64 = nr("proc_open");
+pub const PROC_HANDLE_SIGNAL: u64 = nr("proc_handle_signal");
+pub const PROC_HANDLE_KILL: u64 = nr("proc_handle_kill");
+pub const ENDPOINT_SEND_HANDLE: u64 = nr("endpoint_send_handle");
+pub const ENDPOINT_RECEIVE_HANDLE: u64 = nr("endpoint_receive_handle");
+pub const FILE_READ_MODE: u64 = nr("file_read_mode");
+pub const FILE_WRITE_MODE: u64 = nr("file_write_mode");
+pub const PROC_LIST_IDS: u64 = nr("proc_list_ids");
+pub const SHM_CREATE_MODE: u64 = nr("shm_create_mode");
+
+//every syscall needs a constant here, so the SDK notices when the kernel gains new ones
+const _: () = assert!(SYSCALLS.len() == SHM_CREATE_MODE as usize + 1);
+const _: () = assert!(crate::abi_defs::ERROR_KINDS.len() == ErrorKind::OutOfMemory as usize + 1);
+
+/// Value the kernel returns in the low byte on success.
+const SUCCESS: i8 = -1;
+
+//in code order, built from the names so a kind that moves or is added fails to compile
+const ERROR_KINDS: [ErrorKind; crate::abi_defs::ERROR_KINDS.len()] =
+{
+    let mut kinds = [ErrorKind::Other; crate::abi_defs::ERROR_KINDS.len()];
+    let mut i = 0;
+    while i < kinds.len()
+    {
+        kinds[i] = error_kind(crate::abi_defs::ERROR_KINDS[i]);
+        i += 1;
+    }
+    kinds
+};
+
+const fn error_kind(name: &str) -> ErrorKind
+{
+    let kinds = [("OTHER", ErrorKind::Other), ("NOT_FOUND", ErrorKind::NotFound), ("PERMISSION_DENIED", ErrorKind::PermissionDenied), ("CONNECTION_REFUSED", ErrorKind::ConnectionRefused), ("CONNECTION_RESET", ErrorKind::ConnectionReset), ("CONNECTION_ABORTED", ErrorKind::ConnectionAborted), ("NOT_CONNECTED", ErrorKind::NotConnected), ("ADDR_IN_USE", ErrorKind::AddrInUse), ("ADDR_NOT_AVAILABLE", ErrorKind::AddrNotAvailable), ("BROKEN_PIPE", ErrorKind::BrokenPipe), ("ALREADY_EXISTS", ErrorKind::AlreadyExists), ("INVALID_INPUT", ErrorKind::InvalidInput), ("INVALID_DATA", ErrorKind::InvalidData), ("TIMED_OUT", ErrorKind::TimedOut), ("INTERRUPTED", ErrorKind::Interrupted), ("UNSUPPORTED", ErrorKind::Unsupported), ("OUT_OF_MEMORY", ErrorKind::OutOfMemory)];
+    let mut i = 0;
+    while i < kinds.len()
+    {
+        if str_eq(kinds[i].0, name) {return kinds[i].1;}
+        i += 1;
+    }
+    panic!("error kind in abi_defs.rs unknown to the SDK")
+}
+
+//the number of the syscall called `name`, which has to exist
+const fn nr(name: &str) -> u64
+{
+    let mut i = 0;
+    while i < SYSCALLS.len()
+    {
+        if str_eq(SYSCALLS[i].name, name) {return i as u64;}
+        i += 1;
+    }
+    panic!("syscall missing from abi_defs.rs")
+}
+
+pub(crate) const fn str_eq(a: &str, b: &str) -> bool
+{
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {return false;}
+    let mut i = 0;
+    while i < a.len()
+    {
+        if a[i] != b[i] {return false;}
+        i += 1;
+    }
+    true
+}
+
+/// Decodes the result of a syscall that reports file style errors.
+pub fn errorkind_res(ret: u64) -> Result<(), ErrorKind>
+{
+    match ret as i8
+    {
+        SUCCESS => Ok(()),
+        code => Err(*ERROR_KINDS.get(code as usize).unwrap_or(&ErrorKind::Other))
+    }
+}
+
+/// Returns true if a syscall reporting an `i8` status succeeded.
+pub fn succeeded(ret: u64) -> bool
+{
+    ret as i8 == SUCCESS
+}
+
+/// Enters syscall `nr`, the number of the function says how many arguments it passes.
+///
+/// # Safety
+///
+/// The arguments must be what syscall `nr` expects, the kernel reads and writes memory
+/// through the pointers among them.
+pub unsafe fn syscall0(nr: u64) -> u64
+{
+    let ret;
+    unsafe{asm!("int 0x80", inlateout("rax") nr => ret)};
+    ret
+}
+
+/// # Safety
+///
+/// See `syscall0`.
+pub unsafe fn syscall1(nr: u64, a0: u64) -> u64
+{
+    let ret;
+    unsafe{asm!("int 0x80", inlateout("rax") nr => ret, in("rdi") a0)};
+    ret
+}
+
+/// # Safety
+///
+/// See `syscall0`.
+pub unsafe fn syscall2(nr: u64, a0: u64, a1: u64) -> u64
+{
+    let ret;
+    unsafe{asm!("int 0x80", inlateout("rax") nr => ret, in("rdi") a0, in("rsi") a1)};
+    ret
+}
+
+/// # Safety
+///
+/// See `syscall0`.
+pub unsafe fn syscall3(nr: u64, a0: u64, a1: u64, a2: u64) -> u64
+{
+    let ret;
+    unsafe{asm!("int 0x80", inlateout("rax") nr => ret, in("rdi") a0, in("rsi") a1, in("rdx") a2)};
+    ret
+}
+
+/// # Safety
+///
+/// See `syscall0`.
+pub unsafe fn syscall4(nr: u64, a0: u64, a1: u64, a2: u64, a3: u64) -> u64
+{
+    let ret;
+    unsafe{asm!("int 0x80", inlateout("rax") nr => ret, in("rdi") a0, in("rsi") a1, in("rdx") a2, in("rcx") a3)};
+    ret
+}
+
+/// # Safety
+///
+/// See `syscall0`.
+pub unsafe fn syscall5(nr: u64, a0: u64, a1: u64, a2: u64, a3: u64, a4: u64) -> u64
+{
+    let ret;
+    unsafe{asm!("int 0x80", inlateout("rax") nr => ret, in("rdi") a0, in("rsi") a1, in("rdx") a2, in("rcx") a3, in("r8") a4)};
+    ret
+}
+
+/// # Safety
+///
+/// See `syscall0`.
+pub unsafe fn syscall6(nr: u64, a0: u64, a1: u64, a2: u64, a3: u64, a4: u64, a5: u64) -> u64
+{
+    let ret;
+    unsafe{asm!("int 0x80", inlateout("rax") nr => ret, in("rdi") a0, in("rsi") a1, in("rdx") a2, in("rcx") a3, in("r8") a4, in("r9") a5)};
+    ret
+}
\ No newline at end of file
@@ -0,0 +1,81 @@
+//! Threads of the calling process.
+//!
+//! Threads share the memory of the process and run until their function returns or they call
+//! [`exit`]. The whole process ends once its main function returns, threads included.
+
+use alloc::boxed::Box;
+use crate::process::{ProcessError, process_res};
+use crate::syscall::*;
+
+type Start = Box<dyn FnOnce() -> u64 + Send>;
+
+/// A thread started with [`spawn`].
+pub struct JoinHandle
+{
+    tid: u64
+}
+
+impl JoinHandle
+{
+    pub fn id(&self) -> u64
+    {
+        self.tid
+    }
+
+    /// Blocks until the thread has ended and returns the value it ended with.
+    ///
+    /// Fails with `Interrupted` if a signal arrives first, `join(tid)` can wait for it again then.
+    pub fn join(self) -> Result<u64, ProcessError>
+    {
+        join(self.tid)
+    }
+}
+
+/// Starts a thread running `f`.
+pub fn spawn<F: FnOnce() -> u64 + Send + 'static>(f: F) -> Result<JoinHandle, ProcessError>
+{
+    //the closure is a fat pointer, so it gets boxed once more to pass it in one register
+    let start: Box<Start> = Box::new(Box::new(f));
+    let arg = Box::into_raw(start);
+    let mut tid = 0u64;
+    let res = process_res(unsafe{syscall3(THREAD_CREATE, run as extern "C" fn(u64) -> u64 as usize as u64, arg as u64, &mut tid as *mut u64 as u64)});
+    if res.is_err() {drop(unsafe{Box::from_raw(arg)});}
+    res.map(|_| JoinHandle{tid})
+}
+
+extern "C" fn run(arg: u64) -> u64
+{
+    let start = unsafe{Box::from_raw(arg as *mut Start)};
+    start()
+}
+
+/// Blocks until thread `tid` has ended and returns the value it ended with, each thread can only be joined once.
+///
+/// Fails with `Interrupted` if a signal arrives first.
+pub fn join(tid: u64) -> Result<u64, ProcessError>
+{
+    let mut value = 0u64;
+    process_res(unsafe{syscall2(THREAD_JOIN, tid, &mut value as *mut u64 as u64)})?;
+    Ok(value)
+}
+
+/// Ends the calling thread with a value for the thread joining it.
+///
+/// The process exits with code 0 if this was its last thread.
+pub fn exit(value: u64) -> !
+{
+    unsafe{syscall1(THREAD_EXIT, value)};
+    unreachable!()
+}
+
+/// Lets other threads run before the calling thread continues.
+pub fn yield_now()
+{
+    unsafe{syscall0(THREAD_YIELD)};
+}
+
+/// Returns the id of the calling thread, the first thread of the process is 0.
+pub fn id() -> u64
+{
+    unsafe{syscall0(THREAD_ID)}
+}
\ No newline at end of file
@@ -0,0 +1,20 @@
+//! Wall clock time and sleeping.
+
+use core::time::Duration;
+use crate::syscall::*;
+
+/// Returns the current time as seconds since the Unix epoch.
+pub fn now() -> i64
+{
+    let ret = unsafe{syscall0(TIME_NOW)};
+    ret as i64
+}
+
+/// Blocks the calling thread for at least `duration`.
+///
+/// The kernel wakes sleepers on timer ticks, which come about every 55 milliseconds.
+pub fn sleep(duration: Duration)
+{
+    let ms = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+    unsafe{syscall1(SLEEP, ms)};
+}
\ No newline at end of file